@@ -182,7 +182,7 @@ impl Checker {
             &mut self.timestamps,
             &mut self.data,
         ) {
-            Ok(()) => (),
+            Ok(_) => (),
             Err(e) => panic!("{e}"),
         };
 
@@ -39,6 +39,17 @@ fn main() -> Result<()> {
 
     assert_eq!(ts1, ts2);
 
+    let (input_series, _) = ByteSeries::builder()
+        .retrieve_payload_size()
+        .with_any_header()
+        .open(&path)
+        .wrap_err("Could not open backup input")?;
+    let ts3 = read_streaming(input_series)?;
+    validate_ts(&ts3);
+    eprintln!("streamed timestamps validated");
+
+    assert_eq!(ts1, ts3);
+
     Ok(())
 }
 
@@ -76,6 +87,15 @@ fn read_all(mut input_series: ByteSeries) -> Result<Vec<u64>> {
     Ok(timestamps)
 }
 
+fn read_streaming(mut input_series: ByteSeries) -> Result<Vec<u64>> {
+    input_series
+        .iter_range(.., &mut EmptyDecoder)
+        .wrap_err("Could not start streaming data")?
+        .map(|res| res.map(|(ts, _)| ts))
+        .collect::<Result<_, _>>()
+        .wrap_err("Could not stream all data")
+}
+
 fn validate_ts(timestamps: &[u64]) {
     let mut prev = 0;
     assert!(
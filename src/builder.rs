@@ -1,28 +1,96 @@
 use std::path::Path;
 use std::str::Utf8Error;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 use crate::downsample::resample::EmptyResampler;
-use crate::{downsample, series, ByteSeries, CorruptionCallback, Resampler};
+use crate::file::HeaderDeserErr;
+use crate::series::compression::Field;
+use crate::series::data::BufferPolicy;
+use crate::{
+    downsample, series, ByteSeries, CorruptionCallback, Resampler, SeriesReader,
+};
+use crate::{
+    Compression, DuplicatePolicy, Durability, RepairPolicy, Retention, Timestamp,
+};
 
 #[derive(Debug)]
-enum HeaderOption {
+pub enum HeaderOption {
     MustMatch(Vec<u8>),
     Ignore,
 }
 
-impl HeaderOption {
-    fn as_bytes(&self) -> &[u8] {
+/// A header that is stored as RON, see
+/// [`with_typed_header`](ByteSeriesBuilder::with_typed_header).
+#[derive(Debug)]
+pub struct TypedHeader<H>(H);
+
+/// How the header is written when creating a series and checked when
+/// opening one.
+pub trait UserHeader {
+    /// What `open` returns as header
+    type Output;
+    /// # Errors
+    /// If the header could not be serialized
+    fn to_bytes(&self) -> Result<Vec<u8>, ron::Error>;
+    fn into_output(self) -> Self::Output;
+    /// # Errors
+    /// If the header in the file does not match this one
+    fn check(self, in_file: Vec<u8>) -> Result<Self::Output, HeaderError>;
+}
+
+impl UserHeader for HeaderOption {
+    type Output = Vec<u8>;
+
+    fn to_bytes(&self) -> Result<Vec<u8>, ron::Error> {
         match self {
-            HeaderOption::MustMatch(vec) => vec,
-            HeaderOption::Ignore => &[],
+            HeaderOption::MustMatch(vec) => Ok(vec.clone()),
+            HeaderOption::Ignore => Ok(Vec::new()),
         }
     }
-    fn into_bytes(self) -> Vec<u8> {
+    fn into_output(self) -> Vec<u8> {
         match self {
             HeaderOption::MustMatch(vec) => vec,
             HeaderOption::Ignore => Vec::new(),
         }
     }
+    fn check(self, in_file: Vec<u8>) -> Result<Vec<u8>, HeaderError> {
+        match self {
+            HeaderOption::MustMatch(expected) if in_file != expected => {
+                Err(HeaderError::mismatch(expected, in_file))
+            }
+            HeaderOption::MustMatch(expected) => Ok(expected),
+            HeaderOption::Ignore => Ok(in_file),
+        }
+    }
+}
+
+impl<H> UserHeader for TypedHeader<H>
+where
+    H: Serialize + DeserializeOwned + PartialEq + core::fmt::Debug,
+{
+    type Output = H;
+
+    fn to_bytes(&self) -> Result<Vec<u8>, ron::Error> {
+        ron::to_string(&self.0).map(String::into_bytes)
+    }
+    fn into_output(self) -> H {
+        self.0
+    }
+    fn check(self, in_file: Vec<u8>) -> Result<H, HeaderError> {
+        let in_opened: H = ron::de::from_bytes(&in_file)
+            .map_err(|error| HeaderDeserErr::new(error, in_file))
+            .map_err(HeaderError::Deserializing)?;
+        if in_opened != self.0 {
+            return Err(HeaderError::TypedMismatch {
+                passed_in: format!("{:?}", self.0),
+                in_opened: format!("{in_opened:?}"),
+            });
+        }
+        Ok(in_opened)
+    }
 }
 
 #[derive(Debug)]
@@ -45,14 +113,31 @@ pub struct ByteSeriesBuilder<
     const CAN_CREATE_NEW: bool,
     const CAN_IGNORE_PAYLOADSIZE: bool,
     R,
+    H = HeaderOption,
 > {
     payload_size: PayloadSizeOption,
     create_new: bool,
-    header: HeaderOption,
+    header: H,
     ignore_header: bool,
     resampler: R,
     resample_configs: Vec<downsample::Config>,
     corruption_callback: Option<CorruptionCallback>,
+    options: Options,
+}
+
+/// Settings that do not influence what the builder can do
+#[derive(Debug, Default)]
+pub(crate) struct Options {
+    pub(crate) mmap_reads: bool,
+    pub(crate) buffer_writes: Option<BufferPolicy>,
+    pub(crate) durability: Durability,
+    pub(crate) reorder_window: Option<Timestamp>,
+    pub(crate) duplicates: DuplicatePolicy,
+    pub(crate) retention: Option<Retention>,
+    pub(crate) compression: Compression,
+    pub(crate) payload_layout: Option<Vec<Field>>,
+    pub(crate) checksums: bool,
+    pub(crate) repair: RepairPolicy,
 }
 
 impl<
@@ -60,7 +145,8 @@ impl<
         const HEADER_SET: bool,
         const CAN_IGNORE_PAYLOADSIZE: bool,
         R,
-    > ByteSeriesBuilder<PAYLOAD_SET, HEADER_SET, true, CAN_IGNORE_PAYLOADSIZE, R>
+        H,
+    > ByteSeriesBuilder<PAYLOAD_SET, HEADER_SET, true, CAN_IGNORE_PAYLOADSIZE, R, H>
 where
     R: Resampler + Clone + Send + 'static,
     R::State: Send + 'static,
@@ -72,7 +158,7 @@ where
     pub fn create_new(
         self,
         create_new: bool,
-    ) -> ByteSeriesBuilder<PAYLOAD_SET, HEADER_SET, true, false, R> {
+    ) -> ByteSeriesBuilder<PAYLOAD_SET, HEADER_SET, true, false, R, H> {
         ByteSeriesBuilder {
             payload_size: self.payload_size,
             header: self.header,
@@ -80,20 +166,26 @@ where
             resampler: self.resampler,
             resample_configs: self.resample_configs,
             corruption_callback: self.corruption_callback,
+            options: self.options,
             create_new,
         }
     }
 }
 
-impl<const PAYLOAD_SET: bool, const HEADER_SET: bool, const CAN_CREATE_NEW: bool, R>
-    ByteSeriesBuilder<PAYLOAD_SET, HEADER_SET, CAN_CREATE_NEW, true, R>
+impl<
+        const PAYLOAD_SET: bool,
+        const HEADER_SET: bool,
+        const CAN_CREATE_NEW: bool,
+        R,
+        H,
+    > ByteSeriesBuilder<PAYLOAD_SET, HEADER_SET, CAN_CREATE_NEW, true, R, H>
 where
     R: Resampler + Clone + Send + 'static,
     R::State: Send + 'static,
 {
     pub fn retrieve_payload_size(
         self,
-    ) -> ByteSeriesBuilder<true, HEADER_SET, false, true, R> {
+    ) -> ByteSeriesBuilder<true, HEADER_SET, false, true, R, H> {
         ByteSeriesBuilder {
             payload_size: PayloadSizeOption::Ignore,
             header: self.header,
@@ -101,6 +193,7 @@ where
             resampler: self.resampler,
             resample_configs: self.resample_configs,
             corruption_callback: self.corruption_callback,
+            options: self.options,
             create_new: self.create_new,
         }
     }
@@ -112,8 +205,16 @@ impl<
         const CAN_CREATE_NEW: bool,
         const CAN_IGNORE_PAYLOADSIZE: bool,
         R,
+        H,
+    >
+    ByteSeriesBuilder<
+        PAYLOAD_SET,
+        HEADER_SET,
+        CAN_CREATE_NEW,
+        CAN_IGNORE_PAYLOADSIZE,
+        R,
+        H,
     >
-    ByteSeriesBuilder<PAYLOAD_SET, HEADER_SET, CAN_CREATE_NEW, CAN_IGNORE_PAYLOADSIZE, R>
 where
     R: Resampler + Clone + Send + 'static,
     R::State: Send + 'static,
@@ -127,12 +228,13 @@ where
             resample_configs: Vec::new(),
             corruption_callback: None,
             create_new: false,
+            options: Options::default(),
         }
     }
     pub fn payload_size(
         self,
         bytes: usize,
-    ) -> ByteSeriesBuilder<true, HEADER_SET, true, true, R> {
+    ) -> ByteSeriesBuilder<true, HEADER_SET, true, true, R, H> {
         ByteSeriesBuilder {
             payload_size: PayloadSizeOption::MustMatch(bytes),
             header: self.header,
@@ -140,6 +242,7 @@ where
             resampler: self.resampler,
             resample_configs: self.resample_configs,
             corruption_callback: self.corruption_callback,
+            options: self.options,
             create_new: self.create_new,
         }
     }
@@ -162,6 +265,36 @@ where
             resampler: self.resampler,
             resample_configs: self.resample_configs,
             corruption_callback: self.corruption_callback,
+            options: self.options,
+            create_new: self.create_new,
+        }
+    }
+    /// Like [`with_header`](Self::with_header) but the header is
+    /// serialized to RON. Opening deserializes the header in the file and
+    /// fails if it is not equal to the one passed in, the header is then
+    /// returned typed.
+    pub fn with_typed_header<T>(
+        self,
+        header: T,
+    ) -> ByteSeriesBuilder<
+        PAYLOAD_SET,
+        true,
+        CAN_CREATE_NEW,
+        CAN_IGNORE_PAYLOADSIZE,
+        R,
+        TypedHeader<T>,
+    >
+    where
+        T: Serialize + DeserializeOwned + PartialEq + core::fmt::Debug,
+    {
+        ByteSeriesBuilder {
+            payload_size: self.payload_size,
+            header: TypedHeader(header),
+            ignore_header: false,
+            resampler: self.resampler,
+            resample_configs: self.resample_configs,
+            corruption_callback: self.corruption_callback,
+            options: self.options,
             create_new: self.create_new,
         }
     }
@@ -178,6 +311,7 @@ where
             resampler: self.resampler,
             resample_configs: self.resample_configs,
             corruption_callback: self.corruption_callback,
+            options: self.options,
             create_new: self.create_new,
         }
     }
@@ -191,6 +325,7 @@ where
         CAN_CREATE_NEW,
         CAN_IGNORE_PAYLOADSIZE,
         NewR,
+        H,
     > {
         ByteSeriesBuilder {
             payload_size: self.payload_size,
@@ -200,6 +335,7 @@ where
             resample_configs: configs,
             create_new: self.create_new,
             corruption_callback: self.corruption_callback,
+            options: self.options,
         }
     }
     /// Normally running into a corrupt metadata section means the operation
@@ -213,6 +349,14 @@ where
     ///
     /// - If instead it returns false then reading is aborted and one of the
     /// errors above is returned.
+    ///
+    /// With [`checksums`](Self::checksums) it is also called for a meta
+    /// section with a wrong checksum. Returning true skips that section.
+    ///
+    /// The callback is passed a [`CorruptionEvent`](crate::CorruptionEvent)
+    /// telling where the corruption is and how many lines are skipped if it
+    /// returns true. The read functions return the
+    /// [`Skipped`](crate::series::corruption::Skipped) lines in total.
     pub fn with_callback_on_recoverable_corruption(
         mut self,
         callback: CorruptionCallback,
@@ -220,6 +364,136 @@ where
         self.corruption_callback = Some(callback);
         self
     }
+    /// Serve reads from a memory map of the data instead of reading it into
+    /// a buffer. This saves a lot of syscalls for random reads on data that
+    /// is not in the OS's page cache.
+    ///
+    /// Default is false.
+    pub fn mmap_reads(mut self, mmap_reads: bool) -> Self {
+        self.options.mmap_reads = mmap_reads;
+        self
+    }
+    /// Keep pushed lines in memory and write them out together. They are
+    /// written once `max_lines` are buffered or when a line is pushed more
+    /// then `max_delay` after the oldest buffered line. You can write them
+    /// out earlier using [`ByteSeries::commit`].
+    ///
+    /// Reads through the [`ByteSeries`] include the buffered lines. A
+    /// [`SeriesReader`](crate::SeriesReader) only sees committed lines.
+    ///
+    /// # Warning
+    /// Buffered lines are lost on a crash. They are committed when the
+    /// [`ByteSeries`] is dropped.
+    pub fn buffer_writes(mut self, max_lines: usize, max_delay: Duration) -> Self {
+        self.options.buffer_writes = Some(BufferPolicy {
+            max_lines,
+            max_delay,
+        });
+        self
+    }
+    /// How often to wait for the OS to write pushed lines to disk, see
+    /// [`Durability`].
+    ///
+    /// Default is [`Durability::None`].
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.options.durability = durability;
+        self
+    }
+    /// Accept lines that arrive out of order as long as they are at most
+    /// `width` older then the newest line pushed. Lines are kept in memory
+    /// till they are `width` older then the newest line, then they are
+    /// sorted and written.
+    ///
+    /// Reads through the [`ByteSeries`] include the lines kept in memory. A
+    /// [`SeriesReader`](crate::SeriesReader) only sees written lines.
+    ///
+    /// # Warning
+    /// Lines in the window are lost on a crash. They are written when the
+    /// [`ByteSeries`] is dropped or using
+    /// [`ByteSeries::flush_reorder_window`].
+    pub fn reorder_window(mut self, width: Timestamp) -> Self {
+        self.options.reorder_window = Some(width);
+        self
+    }
+    /// What to do when a line is pushed with the same timestamp as an
+    /// earlier line, see [`DuplicatePolicy`]. A line readers can already
+    /// see is never replaced, the new line is dropped instead.
+    ///
+    /// Default is [`DuplicatePolicy::Reject`].
+    pub fn on_duplicate(mut self, policy: DuplicatePolicy) -> Self {
+        self.options.duplicates = policy;
+        self
+    }
+    /// Drop the oldest lines once they are too old or the data grows too
+    /// large, see [`Retention`].
+    ///
+    /// Default is to keep everything.
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.options.retention = Some(retention);
+        self
+    }
+    /// Store the lines in compressed blocks, see [`Compression`]. Only
+    /// used when a new series is created, an existing series keeps the
+    /// compression it was created with.
+    ///
+    /// Default is [`Compression::None`].
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.options.compression = compression;
+        self
+    }
+    /// Declare the fields the payload consists of, for example
+    /// `[Field::F32, Field::F32, Field::U16]`. Before a block is compressed
+    /// each field is then encoded relative to the line before it: floats
+    /// are XOR-ed and integers stored as delta-of-delta. For slowly changing
+    /// values that compresses far better. Reading decodes the payload again
+    /// before it is passed to the [`Decoder`](crate::Decoder).
+    ///
+    /// Needs a [`compression`](Self::compression) and the fields must add up
+    /// to the payload size. Like the compression this is only used when a
+    /// new series is created.
+    ///
+    /// Default is to compress the payloads as they are.
+    pub fn payload_layout(mut self, fields: impl IntoIterator<Item = Field>) -> Self {
+        self.options.payload_layout = Some(fields.into_iter().collect());
+        self
+    }
+    /// Store a crc32 checksum for every meta section together with the
+    /// lines up to the next meta section. Reads check the sections they
+    /// touch and return a
+    /// [`ReadError::ChecksumMismatch`](crate::series::data::ReadError) for
+    /// a corrupt one. If the
+    /// [corruption callback](Self::with_callback_on_recoverable_corruption)
+    /// returns true the section is skipped instead. The section lines are
+    /// currently appended to has no checksum yet and is not checked.
+    ///
+    /// A checked section is read into memory as a whole. This includes
+    /// reads through a [`SeriesReader`](crate::SeriesReader) and the
+    /// iterators. Readers from [`ByteSeries::reader`] do not call the
+    /// corruption callback, they return the error. Like the compression
+    /// this is only used when a new series is created.
+    ///
+    /// Default is false.
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.options.checksums = checksums;
+        self
+    }
+    /// What to do when opening finds the end of the data left behind by an
+    /// interrupted write, for example a partially written line. With
+    /// [`RepairPolicy::Report`] opening fails with a
+    /// [`RepairNeeded`](crate::series::data::OpenError::RepairNeeded) error
+    /// listing exactly which bytes a repair would remove. No file is
+    /// changed then.
+    ///
+    /// Bytes a repair removes are first appended to the `.quarantine` file
+    /// of the series, see [`repair`](crate::series::repair) for its format.
+    /// The policy covers the downsampled caches too. Torn compressed blocks
+    /// and the index are always repaired.
+    ///
+    /// Default is [`RepairPolicy::Auto`].
+    pub fn repair_policy(mut self, policy: RepairPolicy) -> Self {
+        self.options.repair = policy;
+        self
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -241,6 +515,16 @@ pub enum HeaderError {
         the correct header"
     )]
     Unexpected,
+    #[error("The header in the file is not of the type passed in")]
+    Deserializing(#[source] HeaderDeserErr),
+    #[error(
+        "The header in the file is not equal to the one passed in. The \
+        provided header was: {passed_in}. The header in the file was: {in_opened}"
+    )]
+    TypedMismatch {
+        passed_in: String,
+        in_opened: String,
+    },
 }
 
 impl HeaderError {
@@ -260,16 +544,17 @@ impl HeaderError {
 }
 
 /// payload is set we can thus both create and open new series
-impl<const CAN_IGNORE_PAYLOADSIZE: bool, R>
-    ByteSeriesBuilder<true, true, true, CAN_IGNORE_PAYLOADSIZE, R>
+impl<const CAN_IGNORE_PAYLOADSIZE: bool, R, H>
+    ByteSeriesBuilder<true, true, true, CAN_IGNORE_PAYLOADSIZE, R, H>
 where
     R: Resampler + Clone + Send + 'static,
     R::State: Send + 'static,
+    H: UserHeader,
 {
     pub fn open(
         self,
         path: impl AsRef<Path>,
-    ) -> Result<(ByteSeries, Vec<u8>), series::Error> {
+    ) -> Result<(ByteSeries, H::Output), series::Error> {
         let path = if path
             .as_ref()
             .extension()
@@ -281,49 +566,120 @@ where
         };
 
         if self.create_new {
+            let header = self.header.to_bytes().map_err(|e| {
+                series::Error::Create(series::data::CreateError::File {
+                    source: crate::file::OpenError::SerializingHeader(e),
+                    path: path.with_extension("byteseries"),
+                })
+            })?;
             let bs = ByteSeries::new_with_resamplers(
                 path,
                 self.payload_size.expect("CAN_CREATE_NEW is true"),
-                self.header.as_bytes(),
+                &header,
                 self.resampler,
                 self.resample_configs,
                 self.corruption_callback,
+                self.options,
             )?;
-            Ok((bs, self.header.into_bytes()))
+            Ok((bs, self.header.into_output()))
         } else {
-            let (bs, in_file) = ByteSeries::open_existing_with_resampler(
+            ByteSeries::open_existing_with_resampler(
                 path,
                 self.payload_size,
+                self.header,
                 self.resampler,
                 self.resample_configs,
                 self.corruption_callback,
-            )?;
+                self.options,
+            )
+        }
+    }
+}
 
-            let header = match self.header {
-                HeaderOption::MustMatch(expected) if in_file != expected => {
-                    return Err(series::Error::Header(HeaderError::mismatch(
-                        expected, in_file,
-                    )))
-                }
-                HeaderOption::MustMatch(_) => self.header.into_bytes(),
-                HeaderOption::Ignore => in_file,
-            };
-            Ok((bs, header))
+/// the header is set so the series can be opened read only
+impl<
+        const PAYLOAD_SET: bool,
+        const CAN_CREATE_NEW: bool,
+        const CAN_IGNORE_PAYLOADSIZE: bool,
+        R,
+        H,
+    > ByteSeriesBuilder<PAYLOAD_SET, true, CAN_CREATE_NEW, CAN_IGNORE_PAYLOADSIZE, R, H>
+where
+    H: UserHeader,
+{
+    /// Open the series without changing any of its files, for example for
+    /// a backup on a read only mount. Instead of a [`ByteSeries`] opening
+    /// returns a [`SeriesReader`], so nothing can be pushed.
+    ///
+    /// Repairs are only done in memory: a torn tail left by a crash is not
+    /// read and a broken or missing index is rebuilt in memory. The
+    /// downsampled caches are not used, `read_n` resamples the data
+    /// itself. Meta sections with a stored checksum are checked while
+    /// reading, a mismatch is reported to the
+    /// [corruption callback](Self::with_callback_on_recoverable_corruption).
+    /// `create_new` is ignored, the series must exist.
+    ///
+    /// The reader only sees the lines written before it was opened.
+    pub fn read_only(self) -> ReadOnlyBuilder<H> {
+        ReadOnlyBuilder {
+            payload_size: self.payload_size,
+            header: self.header,
+            corruption_callback: self.corruption_callback,
+            options: self.options,
         }
     }
 }
 
+/// Opens a series without changing it, see
+/// [`ByteSeriesBuilder::read_only`].
+pub struct ReadOnlyBuilder<H> {
+    payload_size: PayloadSizeOption,
+    header: H,
+    corruption_callback: Option<CorruptionCallback>,
+    options: Options,
+}
+
+impl<H: UserHeader> ReadOnlyBuilder<H> {
+    /// # Errors
+    /// Returns an error if the series does not exist, its header does not
+    /// match or it could not be read. See the [`series::Error`] docs for an
+    /// exhaustive list.
+    pub fn open(
+        self,
+        path: impl AsRef<Path>,
+    ) -> Result<(SeriesReader, H::Output), series::Error> {
+        let path = if path
+            .as_ref()
+            .extension()
+            .is_some_and(|ext| ext == "byteseries")
+        {
+            path.as_ref().with_extension("")
+        } else {
+            path.as_ref().to_owned()
+        };
+
+        ByteSeries::open_read_only(
+            path,
+            self.payload_size,
+            self.header,
+            self.corruption_callback,
+            self.options,
+        )
+    }
+}
+
 /// payload is not set and thus we an only try and open a file
-impl<const CAN_IGNORE_PAYLOADSIZE: bool, R>
-    ByteSeriesBuilder<true, true, false, CAN_IGNORE_PAYLOADSIZE, R>
+impl<const CAN_IGNORE_PAYLOADSIZE: bool, R, H>
+    ByteSeriesBuilder<true, true, false, CAN_IGNORE_PAYLOADSIZE, R, H>
 where
     R: Resampler + Clone + Send + 'static,
     R::State: Send + 'static,
+    H: UserHeader,
 {
     pub fn open(
         self,
         path: impl AsRef<Path>,
-    ) -> Result<(ByteSeries, Vec<u8>), series::Error> {
+    ) -> Result<(ByteSeries, H::Output), series::Error> {
         let path = if path
             .as_ref()
             .extension()
@@ -334,24 +690,14 @@ where
             path.as_ref().to_owned()
         };
 
-        let (bs, in_file) = ByteSeries::open_existing_with_resampler(
+        ByteSeries::open_existing_with_resampler(
             path,
             self.payload_size,
+            self.header,
             self.resampler,
             self.resample_configs,
             self.corruption_callback,
-        )?;
-
-        let header = match self.header {
-            HeaderOption::MustMatch(expected) if in_file != expected => {
-                return Err(series::Error::Header(HeaderError::mismatch(
-                    expected, in_file,
-                )))
-            }
-            HeaderOption::MustMatch(_) => self.header.into_bytes(),
-            HeaderOption::Ignore => in_file,
-        };
-
-        Ok((bs, header))
+            self.options,
+        )
     }
 }
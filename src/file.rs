@@ -3,11 +3,31 @@ use std::convert::TryInto;
 use std::fs::{File, OpenOptions};
 use std::io::{self, SeekFrom};
 use std::io::{Read, Seek, Write};
+use std::ops::{Deref, Range};
+use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard};
+
+use memmap2::Mmap;
 
 use tracing::instrument;
 
-use crate::series::data::inline_meta::SetLen;
+use crate::series::compression::Encoding;
+use crate::series::data::inline_meta::{ReadAt, SetLen};
+
+mod blocks;
+use blocks::Blocks;
+pub(crate) use blocks::{table_path, tail_path};
+
+/// Makes renames in the directory holding `path` survive a crash of the OS
+/// or power failure.
+pub(crate) fn sync_dir_of(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum OpenError {
@@ -28,11 +48,24 @@ pub struct HeaderDeserErr {
     header: Vec<u8>,
 }
 
+impl HeaderDeserErr {
+    pub(crate) fn new(error: ron::error::SpannedError, header: Vec<u8>) -> Self {
+        Self { error, header }
+    }
+
+    /// The header as found in the file
+    #[must_use]
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+}
+
 pub(crate) struct FileWithHeader {
     pub(crate) handle: File,
     pub(crate) header: Vec<u8>,
     /// data starts at this offset from the start
     pub(crate) data_offset: u64,
+    path: PathBuf,
 }
 
 /// size comes from the u16 encoded length of the
@@ -55,7 +88,7 @@ impl FileWithHeader {
             .read(true)
             .append(true)
             .create_new(true)
-            .open(path)
+            .open(path.as_ref())
         {
             Ok(file) => file,
             Err(err) => return Err(err)?,
@@ -75,24 +108,39 @@ impl FileWithHeader {
             handle: file,
             header: user_header.to_vec(),
             data_offset: len,
+            path: path.as_ref().to_path_buf(),
         })
     }
 
     /// # Panics
     /// If the path does not have the extension byteseries or byteseries_index.
-    #[instrument(fields(file_len, user_header_len, header_len))]
     pub(crate) fn open_existing(path: PathBuf) -> Result<FileWithHeader, OpenError> {
+        Self::open_existing_with(path, OpenOptions::new().read(true).append(true))
+    }
+
+    /// Opens without write permission. Used by readers that share the
+    /// file with a writer.
+    ///
+    /// # Panics
+    /// If the path does not have the extension byteseries or byteseries_index.
+    pub(crate) fn open_existing_read_only(
+        path: PathBuf,
+    ) -> Result<FileWithHeader, OpenError> {
+        Self::open_existing_with(path, OpenOptions::new().read(true))
+    }
+
+    #[instrument(skip(options), fields(file_len, user_header_len, header_len))]
+    fn open_existing_with(
+        path: PathBuf,
+        options: &OpenOptions,
+    ) -> Result<FileWithHeader, OpenError> {
         assert!(
             path.extension().is_some_and(|e| e == "byteseries")
                 || path.extension().is_some_and(|e| e == "byteseries_index"),
             "Path extension ({:?}) must be 'byteseries' or 'byteseries_index'",
             path.extension()
         );
-        let mut file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(false)
-            .open(path)?;
+        let mut file = options.open(&path)?;
         let metadata = file.metadata()?;
 
         let mut header_len = [0u8, 2];
@@ -113,14 +161,47 @@ impl FileWithHeader {
             handle: file,
             data_offset: header_len as u64,
             header,
+            path,
         })
     }
 
+    /// Replaces the header of the file at `path`, the data is copied as is.
+    /// The new file is written as a `.part` file that then replaces the old,
+    /// a crash leaves either the old or the new file.
+    ///
+    /// # Panics
+    /// If the path does not have the extension byteseries or byteseries_index.
+    pub(crate) fn replace_header(path: &Path, header: &[u8]) -> Result<(), OpenError> {
+        let old = Self::open_existing_read_only(path.to_path_buf())?;
+        let mut extension = path.extension().unwrap_or_default().to_owned();
+        extension.push(".part");
+        let part_path = path.with_extension(extension);
+        // left behind by an earlier upgrade that crashed
+        match std::fs::remove_file(&part_path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            res => res?,
+        }
+
+        let mut new = Self::new(&part_path, header)?;
+        let mut old_handle = old.handle;
+        old_handle.seek(SeekFrom::Start(old.data_offset))?;
+        io::copy(&mut old_handle, &mut new.handle)?;
+        new.handle.sync_all()?;
+        std::fs::rename(&part_path, path)?;
+        sync_dir_of(path)?;
+        Ok(())
+    }
+
     pub(crate) fn split_off_header(self) -> (OffsetFile, Vec<u8>) {
         (
             OffsetFile {
                 handle: self.handle,
                 offset: self.data_offset,
+                map: None,
+                pending: None,
+                path: self.path,
+                overwrite_handle: None,
+                blocks: None,
             },
             self.header,
         )
@@ -135,10 +216,240 @@ impl FileWithHeader {
 pub(crate) struct OffsetFile {
     pub(crate) handle: File,
     offset: u64,
+    /// Maps the entire file, including the header. Remapped once a read
+    /// goes past its end.
+    map: Option<RwLock<Mmap>>,
+    /// If set writes are kept here till they are committed
+    pending: Option<Pending>,
+    path: PathBuf,
+    /// Opened on first use. Positional writes using `handle` always append
+    /// as it is opened in append mode.
+    overwrite_handle: Option<File>,
+    /// If set the data is stored compressed, see [`Compression`](crate::Compression)
+    blocks: Option<Blocks>,
+}
+
+/// Bytes appended to an [`OffsetFile`] that have not yet been written to
+/// the file.
+#[derive(Debug)]
+struct Pending {
+    bytes: Vec<u8>,
+    /// length of the data in the file, the pending bytes follow it
+    file_len: u64,
+}
+
+/// Bytes borrowed from a memory mapped file. The map can not be replaced
+/// while this exists.
+pub(crate) struct MappedBytes<'a> {
+    map: RwLockReadGuard<'a, Mmap>,
+    range: Range<usize>,
+}
+
+impl Deref for MappedBytes<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.map[self.range.clone()]
+    }
+}
+
+fn map(file: &File) -> std::io::Result<Mmap> {
+    // SAFETY: bytes that can be read through a map are never changed and
+    // never cut off:
+    // - Without readers this is the only map. Changing or shortening the
+    //   file needs a mutable borrow, so no read through the map can be in
+    //   progress. Shortening replaces the map (see `SetLen::set_len`).
+    // - While readers (`Data::reader`) exist, lines published to them are
+    //   not overwritten (see `Data::overwrite_last_payload`) and the data
+    //   is not shortened in place. Instead the kept bytes are copied to a
+    //   new file that is moved over the old one (see `Data::rewrite`). The
+    //   readers' maps keep the old file alive.
+    // Like everywhere in this crate, no other process may change the
+    // files while they are open.
+    unsafe { Mmap::map(file) }
 }
 
 impl OffsetFile {
+    /// Store everything written from now on compressed. Must be called
+    /// before anything is written.
+    ///
+    /// # Errors
+    /// Returns an error if the file for the not yet compressed bytes could
+    /// not be created.
+    pub(crate) fn create_compressed(
+        mut self,
+        encoding: Encoding,
+    ) -> std::io::Result<Self> {
+        if !encoding.is_none() {
+            self.blocks = Some(Blocks::create(&self.path, encoding)?);
+        }
+        Ok(self)
+    }
+
+    /// Read the data as compressed blocks. If `writable` the last block is
+    /// repaired if a crash left it incomplete.
+    ///
+    /// # Errors
+    /// Returns an error if the blocks could not be read or repaired.
+    pub(crate) fn open_compressed(
+        mut self,
+        encoding: Encoding,
+        writable: bool,
+    ) -> std::io::Result<Self> {
+        if !encoding.is_none() {
+            self.blocks = Some(Blocks::open(
+                &self.handle,
+                self.offset,
+                &self.path,
+                encoding,
+                writable,
+            )?);
+        }
+        Ok(self)
+    }
+
+    pub(crate) fn encoding(&self) -> Encoding {
+        self.blocks
+            .as_ref()
+            .map(Blocks::encoding)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Compresses everything written so far into a block. Does nothing if
+    /// the file is not compressed.
+    ///
+    /// # Errors
+    /// Returns an error if the block could not be written.
+    pub(crate) fn seal_block(&mut self) -> std::io::Result<()> {
+        match &mut self.blocks {
+            Some(blocks) => blocks.seal(&self.handle),
+            None => Ok(()),
+        }
+    }
+
+    /// True if enough has been written since the last block to compress it
+    pub(crate) fn block_full(&self) -> bool {
+        self.blocks.as_ref().is_some_and(Blocks::block_full)
+    }
+
+    /// The bytes not yet compressed, None if the file is not compressed
+    pub(crate) fn unsealed_len(&self) -> Option<usize> {
+        self.blocks.as_ref().map(Blocks::unsealed_len)
+    }
+
+    /// Bytes used on disk by compressed data, None if the file is not
+    /// compressed.
+    pub(crate) fn compressed_len(&self) -> Option<u64> {
+        self.blocks.as_ref().map(Blocks::stored_len)
+    }
+
+    /// Serve all further reads from a memory map of the file. Does nothing
+    /// if the file is compressed.
+    ///
+    /// # Errors
+    /// Returns an error if the OS could not map the file
+    pub(crate) fn map_reads(&mut self) -> std::io::Result<()> {
+        if self.blocks.is_some() {
+            return Ok(());
+        }
+        self.map = Some(RwLock::new(map(&self.handle)?));
+        Ok(())
+    }
+
+    pub(crate) fn is_mapped(&self) -> bool {
+        self.map.is_some()
+    }
+
+    /// Keep all further writes in memory until [`commit`](Self::commit) is
+    /// called. Reads do include the uncommitted bytes.
+    ///
+    /// # Errors
+    /// Returns an error if the length of the file could not be determined
+    pub(crate) fn buffer_writes(&mut self) -> std::io::Result<()> {
+        if let Some(blocks) = &mut self.blocks {
+            blocks.buffer_writes();
+            return Ok(());
+        }
+        if self.pending.is_none() {
+            self.pending = Some(Pending {
+                bytes: Vec::new(),
+                file_len: self.data_len_bytes()?,
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes all buffered bytes to the file using a single write. A torn
+    /// write is repaired like any other failed append when the file is
+    /// opened again.
+    ///
+    /// # Errors
+    /// Returns an error if the write failed, the bytes stay buffered.
+    pub(crate) fn commit(&mut self) -> std::io::Result<()> {
+        if let Some(blocks) = &mut self.blocks {
+            return blocks.commit();
+        }
+        let Some(pending) = &mut self.pending else {
+            return Ok(());
+        };
+        if pending.bytes.is_empty() {
+            return Ok(());
+        }
+
+        self.handle.write_all(&pending.bytes)?;
+        pending.file_len += pending.bytes.len() as u64;
+        pending.bytes.clear();
+        Ok(())
+    }
+
+    /// Overwrites bytes that were already written (or are pending).
+    ///
+    /// # Errors
+    /// Returns an error if the write failed
+    pub(crate) fn write_all_at(
+        &mut self,
+        mut buf: &[u8],
+        offset: u64,
+    ) -> std::io::Result<()> {
+        if let Some(blocks) = &mut self.blocks {
+            return blocks.write_all_at(&self.handle, self.offset, buf, offset);
+        }
+        if let Some(pending) = &mut self.pending {
+            let end = offset + buf.len() as u64;
+            if end > pending.file_len {
+                let in_file = pending.file_len.saturating_sub(offset);
+                let in_file = usize::try_from(in_file)
+                    .expect("smaller then buf.len()")
+                    .min(buf.len());
+                let (to_file, to_pending) = buf.split_at(in_file);
+                let start = usize::try_from(offset.saturating_sub(pending.file_len))
+                    .expect("pending bytes fit in memory");
+                pending
+                    .bytes
+                    .get_mut(start..start + to_pending.len())
+                    .ok_or(std::io::ErrorKind::UnexpectedEof)?
+                    .copy_from_slice(to_pending);
+                buf = to_file;
+            }
+        }
+
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let handle = match &mut self.overwrite_handle {
+            Some(handle) => handle,
+            None => self
+                .overwrite_handle
+                .insert(OpenOptions::new().write(true).open(&self.path)?),
+        };
+        handle.write_all_at(buf, offset + self.offset)
+    }
+
     pub(crate) fn sync_data(&self) -> std::io::Result<()> {
+        if let Some(blocks) = &self.blocks {
+            blocks.sync_data()?;
+        }
         self.handle.sync_data()
     }
 
@@ -149,7 +460,13 @@ impl OffsetFile {
     /// # Errors
     /// Returns an error if the underlying file returned an io error.
     pub(crate) fn data_len_bytes(&self) -> std::io::Result<u64> {
-        self.handle.metadata().map(|m| m.len() - self.offset)
+        if let Some(blocks) = &self.blocks {
+            return blocks.len(&self.handle, self.offset);
+        }
+        let pending = self.pending.as_ref().map_or(0, |p| p.bytes.len() as u64);
+        self.handle
+            .metadata()
+            .map(|m| m.len() - self.offset + pending)
     }
 }
 
@@ -159,7 +476,109 @@ impl SetLen for OffsetFile {
     }
 
     fn set_len(&mut self, len: u64) -> Result<(), std::io::Error> {
-        self.handle.set_len(len + self.offset)
+        if let Some(blocks) = &mut self.blocks {
+            return blocks.set_len(&self.handle, self.offset, len);
+        }
+        if let Some(pending) = &mut self.pending {
+            if let Some(keep) = len.checked_sub(pending.file_len) {
+                let keep = usize::try_from(keep).expect("pending bytes fit in memory");
+                pending.bytes.truncate(keep);
+                return Ok(());
+            }
+            pending.bytes.clear();
+            pending.file_len = len;
+        }
+
+        self.handle.set_len(len + self.offset)?;
+        if let Some(mapped) = &mut self.map {
+            // the part of the old map beyond the new length is no longer
+            // backed by the file
+            *mapped.get_mut().unwrap_or_else(PoisonError::into_inner) =
+                map(&self.handle)?;
+        }
+        Ok(())
+    }
+}
+
+impl ReadAt for OffsetFile {
+    fn read_exact_at(
+        &self,
+        mut buf: &mut [u8],
+        offset: u64,
+    ) -> Result<(), std::io::Error> {
+        if let Some(blocks) = &self.blocks {
+            return blocks.read_exact_at(&self.handle, self.offset, buf, offset);
+        }
+        if let Some(pending) = &self.pending {
+            let end = offset + buf.len() as u64;
+            if end > pending.file_len {
+                let in_file = pending.file_len.saturating_sub(offset);
+                let in_file = usize::try_from(in_file)
+                    .expect("smaller then buf.len()")
+                    .min(buf.len());
+                let (from_file, from_pending) = buf.split_at_mut(in_file);
+                let start = usize::try_from(offset.saturating_sub(pending.file_len))
+                    .expect("pending bytes fit in memory");
+                let bytes = pending
+                    .bytes
+                    .get(start..start + from_pending.len())
+                    .ok_or(std::io::ErrorKind::UnexpectedEof)?;
+                from_pending.copy_from_slice(bytes);
+                buf = from_file;
+            }
+        }
+
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let len = buf.len() as u64;
+        if let Some(bytes) = self.map_range(offset..offset + len)? {
+            buf.copy_from_slice(&bytes);
+            Ok(())
+        } else {
+            self.handle.read_exact_at(buf, offset + self.offset)
+        }
+    }
+
+    fn map_range(
+        &self,
+        range: Range<u64>,
+    ) -> Result<Option<MappedBytes<'_>>, std::io::Error> {
+        let Some(mapped) = &self.map else {
+            return Ok(None);
+        };
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| range.end > pending.file_len)
+        {
+            // part of the range is not yet in the file
+            return Ok(None);
+        }
+
+        let to_usize = |pos: u64| {
+            usize::try_from(pos + self.offset).expect("file is mapped so fits in usize")
+        };
+        let range = to_usize(range.start)..to_usize(range.end);
+
+        let map = mapped.read().unwrap_or_else(PoisonError::into_inner);
+        if map.len() >= range.end {
+            return Ok(Some(MappedBytes { map, range }));
+        }
+        drop(map);
+
+        // the file grew since it was mapped
+        let mut map = mapped.write().unwrap_or_else(PoisonError::into_inner);
+        if map.len() < range.end {
+            *map = self::map(&self.handle)?;
+        }
+        drop(map);
+
+        let map = mapped.read().unwrap_or_else(PoisonError::into_inner);
+        if map.len() < range.end {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Some(MappedBytes { map, range }))
     }
 }
 
@@ -186,7 +605,15 @@ impl Read for OffsetFile {
 
 impl Write for OffsetFile {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.handle.write(buf)
+        if let Some(blocks) = &mut self.blocks {
+            blocks.append(buf)?;
+            Ok(buf.len())
+        } else if let Some(pending) = &mut self.pending {
+            pending.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        } else {
+            self.handle.write(buf)
+        }
     }
     fn flush(&mut self) -> std::io::Result<()> {
         self.handle.flush()
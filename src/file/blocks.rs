@@ -0,0 +1,616 @@
+//! Storage for compressed series. Callers see the same bytes as for an
+//! uncompressed series, offsets are into these uncompressed bytes.
+//!
+//! The data file holds the full blocks, each as a frame: a 16 byte header
+//! (little endian start offset: u64, uncompressed length: u32, compressed
+//! length: u32) followed by the compressed bytes. The bytes after the last
+//! block are stored uncompressed in the tail file, behind the offset they
+//! start at (u64, little endian).
+//!
+//! The block table file holds a copy of every frame header, in order. Opening
+//! reads it instead of every header in the data file. Only the frames after
+//! the last one in the table are scanned, those were written just before a
+//! crash.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use tracing::warn;
+
+use crate::series::compression::{Encoding, BLOCK_SIZE};
+
+const FRAME_HEADER: u64 = 16;
+const TAIL_HEADER: u64 = 8;
+
+/// The file holding the uncompressed bytes of the data file at `data_path`
+pub(crate) fn tail_path(data_path: &Path) -> PathBuf {
+    let mut path = OsString::from(data_path.as_os_str());
+    path.push("_tail");
+    PathBuf::from(path)
+}
+
+/// The file listing the blocks of the data file at `data_path`
+pub(crate) fn table_path(data_path: &Path) -> PathBuf {
+    let mut path = OsString::from(data_path.as_os_str());
+    path.push("_blocks");
+    PathBuf::from(path)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    /// offset in the uncompressed bytes
+    start: u64,
+    raw_len: u32,
+    /// offset in the data file of the header before the compressed bytes
+    frame_start: u64,
+    stored_len: u32,
+}
+
+impl Block {
+    fn from_header(header: &[u8], frame_start: u64) -> Self {
+        Self {
+            start: u64::from_le_bytes(header[0..8].try_into().expect("8 bytes")),
+            raw_len: u32::from_le_bytes(header[8..12].try_into().expect("4 bytes")),
+            frame_start,
+            stored_len: u32::from_le_bytes(header[12..16].try_into().expect("4 bytes")),
+        }
+    }
+
+    fn header(&self) -> [u8; FRAME_HEADER as usize] {
+        let mut header = [0; FRAME_HEADER as usize];
+        header[0..8].copy_from_slice(&self.start.to_le_bytes());
+        header[8..12].copy_from_slice(&self.raw_len.to_le_bytes());
+        header[12..16].copy_from_slice(&self.stored_len.to_le_bytes());
+        header
+    }
+
+    fn end(&self) -> u64 {
+        self.start + u64::from(self.raw_len)
+    }
+
+    fn frame_end(&self) -> u64 {
+        self.frame_start + FRAME_HEADER + u64::from(self.stored_len)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    blocks: Vec<Block>,
+    /// the uncompressed bytes after the last block
+    tail: Vec<u8>,
+    /// the last block that was decompressed
+    cache: Option<(usize, Vec<u8>)>,
+}
+
+impl State {
+    fn sealed_end(&self) -> u64 {
+        self.blocks.last().map_or(0, Block::end)
+    }
+
+    fn frames_end(&self) -> u64 {
+        self.blocks.last().map_or(0, Block::frame_end)
+    }
+
+    fn len(&self) -> u64 {
+        self.sealed_end() + self.tail.len() as u64
+    }
+
+    /// The uncompressed bytes of block `idx`
+    fn block(
+        &mut self,
+        idx: usize,
+        encoding: &Encoding,
+        main: &File,
+        offset: u64,
+    ) -> io::Result<&[u8]> {
+        if !matches!(&self.cache, Some((cached, _)) if *cached == idx) {
+            let block = self.blocks[idx];
+            let mut stored = vec![0; block.stored_len as usize];
+            main.read_exact_at(&mut stored, offset + block.frame_start + FRAME_HEADER)?;
+            let raw = encoding.decode(&stored, block.raw_len as usize)?;
+            self.cache = Some((idx, raw));
+        }
+        Ok(&self.cache.as_ref().expect("just set if it was not").1)
+    }
+
+    /// Adds the blocks appended to the data file since the last scan.
+    /// Returns true if the scan stopped at a partially written block.
+    fn scan_frames(&mut self, main: &File, offset: u64) -> io::Result<bool> {
+        let file_len = main.metadata()?.len() - offset;
+        let mut header = [0u8; FRAME_HEADER as usize];
+        let mut pos = self.frames_end();
+        while pos < file_len {
+            if pos + FRAME_HEADER > file_len {
+                return Ok(true);
+            }
+            main.read_exact_at(&mut header, offset + pos)?;
+            let block = Block::from_header(&header, pos);
+            if block.frame_end() > file_len {
+                return Ok(true);
+            }
+            if block.start != self.sealed_end() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "compressed block does not start where the previous ends",
+                ));
+            }
+            self.blocks.push(block);
+            pos = block.frame_end();
+        }
+        Ok(false)
+    }
+}
+
+/// The blocks in the table file that match the frames in `main`. The table
+/// can end with a partially written entry or list blocks that a crash
+/// removed from the data file. If its last block does not match the frame
+/// in the data file the table is ignored.
+fn read_table(table: &File, main: &File, offset: u64) -> io::Result<Vec<Block>> {
+    let len = table.metadata()?.len();
+    let mut bytes = vec![0; usize::try_from(len).expect("one entry per 64 KiB block")];
+    table.read_exact_at(&mut bytes, 0)?;
+
+    let file_len = main.metadata()?.len() - offset;
+    let mut blocks: Vec<Block> = Vec::new();
+    for header in bytes.chunks_exact(FRAME_HEADER as usize) {
+        let previous = blocks.last();
+        let block = Block::from_header(header, previous.map_or(0, Block::frame_end));
+        if block.start != previous.map_or(0, Block::end) || block.frame_end() > file_len {
+            break;
+        }
+        blocks.push(block);
+    }
+
+    if let Some(last) = blocks.last() {
+        let mut header = [0u8; FRAME_HEADER as usize];
+        main.read_exact_at(&mut header, offset + last.frame_start)?;
+        if header != last.header() {
+            warn!("block table does not match the compressed data, ignoring it");
+            blocks.clear();
+        }
+    }
+    Ok(blocks)
+}
+
+/// Reads the tail file, None if it does not have a complete header
+fn read_tail(file: &File) -> io::Result<Option<(u64, Vec<u8>)>> {
+    let len = file.metadata()?.len();
+    if len < TAIL_HEADER {
+        return Ok(None);
+    }
+    let mut bytes = vec![0; usize::try_from(len).expect("the tail is about one block")];
+    file.read_exact_at(&mut bytes, 0)?;
+    let start = u64::from_le_bytes(bytes[..8].try_into().expect("checked length"));
+    bytes.drain(..8);
+    Ok(Some((start, bytes)))
+}
+
+/// The bytes of `tail` that come after `sealed_end`
+fn after_sealed(start: u64, mut tail: Vec<u8>, sealed_end: u64) -> Vec<u8> {
+    let overlap = usize::try_from(sealed_end - start).unwrap_or(usize::MAX);
+    tail.drain(..overlap.min(tail.len()));
+    tail
+}
+
+/// Compressed storage, used by an [`OffsetFile`](super::OffsetFile)
+/// that is passed in as `main` together with its header size (`offset`).
+#[derive(Debug)]
+pub(crate) struct Blocks {
+    encoding: Encoding,
+    state: Mutex<State>,
+    /// Sealing replaces the tail file, readers therefore open it again
+    /// each time they refresh
+    tail_file: File,
+    tail_path: PathBuf,
+    /// Only used by writers, readers find new blocks in the data file
+    table: Option<File>,
+    /// Writers keep the tail in memory, readers read it again when they
+    /// need bytes past what they have seen.
+    writable: bool,
+    /// This many bytes at the start of the tail are in the tail file
+    tail_written: usize,
+    buffering: bool,
+}
+
+impl Blocks {
+    /// Start a new, empty, compressed data file
+    pub(crate) fn create(data_path: &Path, encoding: Encoding) -> io::Result<Self> {
+        let tail_path = tail_path(data_path);
+        let tail_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tail_path)?;
+        tail_file.write_all_at(&0u64.to_le_bytes(), 0)?;
+        let table = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(table_path(data_path))?;
+        Ok(Self {
+            encoding,
+            state: Mutex::new(State::default()),
+            tail_file,
+            tail_path,
+            table: Some(table),
+            writable: true,
+            tail_written: 0,
+            buffering: false,
+        })
+    }
+
+    /// Writers repair a partially written block, a tail that does not
+    /// match the blocks and a block table missing the last blocks. All are
+    /// left behind by a crash.
+    pub(crate) fn open(
+        main: &File,
+        offset: u64,
+        data_path: &Path,
+        encoding: Encoding,
+        writable: bool,
+    ) -> io::Result<Self> {
+        let tail_path = tail_path(data_path);
+        let tail_file = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .create(writable)
+            .truncate(false)
+            .open(&tail_path)?;
+
+        let table = match OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .create(writable)
+            .truncate(false)
+            .open(table_path(data_path))
+        {
+            Ok(table) => Some(table),
+            // series created before there was a block table
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let mut state = State::default();
+        if let Some(table) = &table {
+            state.blocks = read_table(table, main, offset)?;
+        }
+        let in_table = state.blocks.len();
+        let torn = state.scan_frames(main, offset)?;
+        let mut blocks = Self {
+            encoding,
+            state: Mutex::new(State::default()),
+            tail_file,
+            tail_path,
+            table: table.filter(|_| writable),
+            writable,
+            tail_written: 0,
+            buffering: false,
+        };
+        if !writable {
+            *blocks
+                .state
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner) = state;
+            return Ok(blocks);
+        }
+
+        if torn {
+            warn!("repaired incompletely written compressed block at end");
+            main.set_len(offset + state.frames_end())?;
+        }
+        let table = blocks
+            .table
+            .as_ref()
+            .expect("writers always open the table");
+        table.set_len(in_table as u64 * FRAME_HEADER)?;
+        for (idx, block) in state.blocks.iter().enumerate().skip(in_table) {
+            table.write_all_at(&block.header(), idx as u64 * FRAME_HEADER)?;
+        }
+        let sealed_end = state.sealed_end();
+        let tail = match read_tail(&blocks.tail_file)? {
+            Some((start, tail)) if start == sealed_end => tail,
+            Some((start, tail)) if start < sealed_end => {
+                let tail = after_sealed(start, tail, sealed_end);
+                blocks.replace_tail(sealed_end, &tail)?;
+                tail
+            }
+            Some(_) => {
+                warn!("tail of compressed data does not follow its blocks, dropping it");
+                blocks.replace_tail(sealed_end, &[])?;
+                Vec::new()
+            }
+            None => {
+                blocks.replace_tail(sealed_end, &[])?;
+                Vec::new()
+            }
+        };
+        blocks.tail_written = tail.len();
+        state.tail = tail;
+        *blocks
+            .state
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner) = state;
+        Ok(blocks)
+    }
+
+    pub(crate) fn encoding(&self) -> &Encoding {
+        &self.encoding
+    }
+
+    /// Replaces the tail file, a crash leaves either the old or new one
+    fn replace_tail(&mut self, start: u64, tail: &[u8]) -> io::Result<()> {
+        let mut part_path = OsString::from(self.tail_path.as_os_str());
+        part_path.push(".part");
+        let mut part = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&part_path)?;
+        part.write_all(&start.to_le_bytes())?;
+        part.write_all(tail)?;
+        part.sync_data()?;
+        std::fs::rename(&part_path, &self.tail_path)?;
+        super::sync_dir_of(&self.tail_path)?;
+        self.tail_file = part;
+        self.tail_written = tail.len();
+        Ok(())
+    }
+
+    /// Brings a reader up to date with blocks and tail the writer added.
+    /// Fails with [`io::ErrorKind::Interrupted`] if the writer kept sealing
+    /// blocks while we read, then reading again can succeed.
+    fn refresh(&self, state: &mut State, main: &File, offset: u64) -> io::Result<()> {
+        // the writer can compress the tail while we read it, then we
+        // need to look for the new block
+        for _ in 0..3 {
+            state.scan_frames(main, offset)?;
+            let sealed_end = state.sealed_end();
+            // sealing replaces the tail file, open it again to get the
+            // current one
+            let tail_file = File::open(&self.tail_path)?;
+            match read_tail(&tail_file)? {
+                Some((start, tail)) if start <= sealed_end => {
+                    state.tail = after_sealed(start, tail, sealed_end);
+                    return Ok(());
+                }
+                Some(_) => continue,
+                None => {
+                    state.tail.clear();
+                    return Ok(());
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "the tail kept changing while reading it, the writer is sealing \
+            blocks faster then we can follow",
+        ))
+    }
+
+    /// Length of the uncompressed bytes
+    pub(crate) fn len(&self, main: &File, offset: u64) -> io::Result<u64> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !self.writable {
+            self.refresh(&mut state, main, offset)?;
+        }
+        Ok(state.len())
+    }
+
+    /// Bytes used on disk, headers not included
+    pub(crate) fn stored_len(&self) -> u64 {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.frames_end() + state.tail.len() as u64
+    }
+
+    /// Length of the bytes not yet compressed
+    pub(crate) fn unsealed_len(&self) -> usize {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.tail.len()
+    }
+
+    pub(crate) fn block_full(&self) -> bool {
+        self.unsealed_len() >= BLOCK_SIZE
+    }
+
+    pub(crate) fn read_exact_at(
+        &self,
+        main: &File,
+        offset: u64,
+        mut buf: &mut [u8],
+        mut pos: u64,
+    ) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let end = pos + buf.len() as u64;
+        if !self.writable && end > state.len() {
+            self.refresh(&mut state, main, offset)?;
+        }
+        if end > state.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        while !buf.is_empty() {
+            let sealed_end = state.sealed_end();
+            if pos >= sealed_end {
+                let start = usize::try_from(pos - sealed_end).expect("tail is in memory");
+                buf.copy_from_slice(&state.tail[start..start + buf.len()]);
+                break;
+            }
+
+            let idx = state.blocks.partition_point(|block| block.end() <= pos);
+            let block_start = state.blocks[idx].start;
+            let raw = state.block(idx, &self.encoding, main, offset)?;
+            let in_block = usize::try_from(pos - block_start).expect("blocks are small");
+            let n = buf.len().min(raw.len() - in_block);
+            buf[..n].copy_from_slice(&raw[in_block..in_block + n]);
+            buf = &mut buf[n..];
+            pos += n as u64;
+        }
+        Ok(())
+    }
+
+    /// Appends to the tail
+    pub(crate) fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.state
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .tail
+            .extend_from_slice(buf);
+        if self.buffering {
+            Ok(())
+        } else {
+            self.commit()
+        }
+    }
+
+    /// Keep appended bytes in memory until [`commit`](Self::commit)
+    pub(crate) fn buffer_writes(&mut self) {
+        self.buffering = true;
+    }
+
+    /// Writes the bytes appended to the tail since the last commit
+    pub(crate) fn commit(&mut self) -> io::Result<()> {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let unwritten = &state.tail[self.tail_written..];
+        if unwritten.is_empty() {
+            return Ok(());
+        }
+        self.tail_file
+            .write_all_at(unwritten, TAIL_HEADER + self.tail_written as u64)?;
+        self.tail_written = state.tail.len();
+        Ok(())
+    }
+
+    /// Compresses the tail into a new block appended to `main`
+    pub(crate) fn seal(&mut self, main: &File) -> io::Result<()> {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if state.tail.is_empty() {
+            return Ok(());
+        }
+
+        let stored = self.encoding.encode(&state.tail)?;
+        let block = Block {
+            start: state.sealed_end(),
+            raw_len: u32::try_from(state.tail.len()).expect("blocks are small"),
+            frame_start: state.frames_end(),
+            stored_len: u32::try_from(stored.len()).expect("blocks are small"),
+        };
+        let mut frame = Vec::with_capacity(FRAME_HEADER as usize + stored.len());
+        frame.extend_from_slice(&block.header());
+        frame.extend_from_slice(&stored);
+        // main is opened in append mode
+        (&*main).write_all(&frame)?;
+        // if we crash before this the block is found by scanning on open
+        self.table
+            .as_ref()
+            .expect("only writers seal")
+            .write_all_at(&block.header(), state.blocks.len() as u64 * FRAME_HEADER)?;
+
+        state.blocks.push(block);
+        let raw = std::mem::take(&mut state.tail);
+        state.cache = Some((state.blocks.len() - 1, raw));
+        // Readers must always find a tail that starts at or before the
+        // end of the blocks they have seen, the tail is therefore replaced
+        // instead of emptied in place. If we crash before this the tail
+        // overlaps the new block, that is repaired on open.
+        self.replace_tail(block.end(), &[])
+    }
+
+    /// Moves the blocks from `first` on back into the tail, keeping only the
+    /// bytes before `keep_until`.
+    fn unseal(
+        &mut self,
+        main: &File,
+        offset: u64,
+        first: usize,
+        keep_until: u64,
+    ) -> io::Result<()> {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let start = state.blocks[first].start;
+        let frame_start = state.blocks[first].frame_start;
+        let mut tail = Vec::new();
+        for idx in first..state.blocks.len() {
+            tail.extend_from_slice(state.block(idx, &self.encoding, main, offset)?);
+        }
+        tail.extend_from_slice(&state.tail);
+        tail.truncate(usize::try_from(keep_until - start).expect("tail fits in memory"));
+
+        // if we crash before the blocks are removed the new tail overlaps
+        // them, on open that overlap is ignored. The table may never list
+        // blocks that are not in the data, it is shortened first.
+        self.replace_tail(start, &tail)?;
+        self.table
+            .as_ref()
+            .expect("only writers unseal")
+            .set_len(first as u64 * FRAME_HEADER)?;
+        main.set_len(offset + frame_start)?;
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        state.blocks.truncate(first);
+        state.cache = None;
+        state.tail = tail;
+        Ok(())
+    }
+
+    pub(crate) fn set_len(
+        &mut self,
+        main: &File,
+        offset: u64,
+        len: u64,
+    ) -> io::Result<()> {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let sealed_end = state.sealed_end();
+        if len < sealed_end {
+            let first = state.blocks.partition_point(|block| block.end() <= len);
+            return self.unseal(main, offset, first, len);
+        }
+
+        let keep = usize::try_from(len - sealed_end).expect("tail fits in memory");
+        state.tail.truncate(keep);
+        if self.tail_written > keep {
+            self.tail_written = keep;
+            self.tail_file.set_len(TAIL_HEADER + keep as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Overwrites bytes, if they are in a block it is moved back into the
+    /// tail first.
+    pub(crate) fn write_all_at(
+        &mut self,
+        main: &File,
+        offset: u64,
+        buf: &[u8],
+        pos: u64,
+    ) -> io::Result<()> {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if pos < state.sealed_end() {
+            let first = state.blocks.partition_point(|block| block.end() <= pos);
+            let len = state.len();
+            self.unseal(main, offset, first, len)?;
+        }
+
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let start =
+            usize::try_from(pos - state.sealed_end()).expect("tail fits in memory");
+        state
+            .tail
+            .get_mut(start..start + buf.len())
+            .ok_or(io::ErrorKind::UnexpectedEof)?
+            .copy_from_slice(buf);
+        let written = self.tail_written.saturating_sub(start).min(buf.len());
+        if written > 0 {
+            self.tail_file
+                .write_all_at(&buf[..written], TAIL_HEADER + start as u64)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn sync_data(&self) -> io::Result<()> {
+        self.tail_file.sync_data()
+    }
+}
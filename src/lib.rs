@@ -1,13 +1,23 @@
 mod builder;
 pub mod file;
 pub mod seek;
+pub mod segmented;
 pub mod series;
+pub mod variable;
 
+pub use builder::HeaderError;
 pub use seek::Pos;
-pub use series::{downsample, ByteSeries};
+pub use segmented::{Rollover, SegmentedSeries};
+pub use series::{
+    compact::CompactReport, compression::Compression, corruption::CorruptionEvent,
+    downsample, duplicates::DuplicatePolicy, durability::Durability,
+    reader::SeriesReader, repair::RepairPolicy, retention::Retention, verify::verify,
+    ByteSeries,
+};
+pub use variable::VariableSeries;
 
 pub type Timestamp = u64;
-type CorruptionCallback = Box<dyn FnMut() -> bool + Send>;
+type CorruptionCallback = Box<dyn FnMut(&CorruptionEvent) -> bool + Send>;
 
 pub trait Decoder: core::fmt::Debug {
     type Item: core::fmt::Debug;
@@ -1,9 +1,9 @@
-use std::io::{Read, Seek, SeekFrom};
 use std::ops::{Bound, RangeInclusive};
 
 use tracing::instrument;
 
 use crate::series::data::index::{EndArea, LinePos, MetaPos, StartArea};
+use crate::series::data::inline_meta::ReadAt;
 use crate::series::data::{Data, MAX_SMALL_TS};
 use crate::Timestamp;
 
@@ -97,7 +97,7 @@ impl RoughPos {
 
     /// returns None if there is no data to read
     #[tracing::instrument]
-    pub(crate) fn refine(self, data: &mut Data) -> Result<Option<Pos>, Error> {
+    pub(crate) fn refine(self, data: &Data) -> Result<Option<Pos>, Error> {
         let start_byte = match self.start_search_area {
             StartArea::Found(pos) | StartArea::Gap { stops: pos } => pos,
             StartArea::Clipped => MetaPos::ZERO.line_start(data.payload_size()),
@@ -215,7 +215,7 @@ impl Pos {
 /// returns the offset from the start of the file where the first line starts
 #[instrument(err)]
 fn find_read_start(
-    data: &mut Data,
+    data: &Data,
     start_time: u16,
     start: LinePos,
     stop: u64,
@@ -224,22 +224,21 @@ fn find_read_start(
         return Ok(LinePos(stop));
     }
 
-    let buf_len =
-        usize::try_from(stop - start.raw_offset()).expect("search area < u16::MAX");
-    let mut buf = vec![0u8; buf_len];
-    data.file_handle.seek(SeekFrom::Start(start.raw_offset()))?;
-    data.file_handle.file_handle.read_exact(&mut buf)?;
+    let start_line =
+        data.file_handle
+            .file_handle
+            .with_bytes_at(start.raw_offset()..stop, |buf| {
+                buf.chunks_exact(data.payload_size().line_size())
+                    .map(|line| {
+                        line[0..2]
+                            .try_into()
+                            .expect("start and stop at least 2 apart")
+                    })
+                    .map(u16::from_le_bytes)
+                    .position(|line_ts| line_ts >= start_time)
+            })?;
 
-    if let Some(start_line) = buf
-        .chunks_exact(data.payload_size().line_size())
-        .map(|line| {
-            line[0..2]
-                .try_into()
-                .expect("start and stop at least 2 apart")
-        })
-        .map(u16::from_le_bytes)
-        .position(|line_ts| line_ts >= start_time)
-    {
+    if let Some(start_line) = start_line {
         let bytes_past_start = start_line as u64 * data.payload_size().line_size() as u64;
         let start_byte = start.raw_offset() + bytes_past_start;
         Ok(LinePos(start_byte))
@@ -251,7 +250,7 @@ fn find_read_start(
 /// returns the offset from the start of the file where last line **stops**
 #[instrument(err)]
 fn find_read_end(
-    data: &mut Data,
+    data: &Data,
     end_time: u16,
     start: LinePos,
     stop: u64,
@@ -261,18 +260,17 @@ fn find_read_end(
         "stop ({stop}) must be large then start ({start:?})"
     );
     //compare partial (16 bit) timestamps in between these bounds
-    let buf_len = usize::try_from(stop - start.raw_offset())
-        .expect("search area is smaller the u16::MAX");
-    let mut buf = vec![0u8; buf_len];
-    data.file_handle.seek(SeekFrom::Start(start.raw_offset()))?;
-    data.file_handle.file_handle.read_exact(&mut buf)?;
+    let stop_line =
+        data.file_handle
+            .file_handle
+            .with_bytes_at(start.raw_offset()..stop, |buf| {
+                buf.chunks_exact(data.payload_size().line_size())
+                    .map(|line| line[..2].try_into().expect("chunks are at least 2 long"))
+                    .map(u16::from_le_bytes)
+                    .rposition(|line_ts| line_ts <= end_time)
+            })?;
 
-    if let Some(stop_line) = buf
-        .chunks_exact(data.payload_size().line_size())
-        .map(|line| line[..2].try_into().expect("chunks are at least 2 long"))
-        .map(u16::from_le_bytes)
-        .rposition(|line_ts| line_ts <= end_time)
-    {
+    if let Some(stop_line) = stop_line {
         let stop_byte = start.raw_offset()
             + (stop_line + 1) as u64 * data.payload_size().line_size() as u64;
         Ok(stop_byte)
@@ -0,0 +1,642 @@
+//! A series split over many files, called segments. New lines go to the
+//! newest segment, once that spans a long enough period or grows too large a
+//! new one is started. Each segment is a complete [`ByteSeries`] with its own
+//! data file and index. A manifest lists the segments and the timestamp of
+//! their first line.
+//!
+//! Dropping old data removes whole segments, see
+//! [`SegmentedSeries::drop_segments_before`]. That is far cheaper then
+//! rewriting the data like [`ByteSeries::drop_before`] does.
+
+use std::io::Write;
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::builder::ByteSeriesBuilder;
+use crate::series::bounds;
+use crate::series::corruption::Skipped;
+use crate::series::downsample::resample::EmptyResampler;
+use crate::{series, ByteSeries, Decoder, Resampler, Timestamp};
+
+const MANIFEST: &str = "manifest.ron";
+const MANIFEST_VERSION: u16 = 1;
+
+/// When the newest segment is done and a new one is started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rollover {
+    /// Every segment holds the lines from a multiple of this period up to
+    /// the next. For example with a period of a day in seconds each segment
+    /// holds a single (UTC) day. Must not be zero.
+    Period(Timestamp),
+    /// A new segment is started once the data of the newest is at least
+    /// this many bytes, before any compression.
+    Size(u64),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not create the directory for the segments: {0}")]
+    CreateDir(std::io::Error),
+    #[error("Could not read the manifest: {0}")]
+    ReadManifest(std::io::Error),
+    #[error("Could not parse the manifest")]
+    ParseManifest(#[source] ron::error::SpannedError),
+    #[error("Could not serialize the manifest")]
+    SerializeManifest(#[source] ron::Error),
+    #[error("Could not write the manifest: {0}")]
+    WriteManifest(std::io::Error),
+    #[error("Manifest has version {0}, this version of byteseries can not read it")]
+    UnsupportedVersion(u16),
+    #[error("The segments have lines of {in_manifest} bytes, not {requested}")]
+    PayloadSizeMismatch {
+        in_manifest: usize,
+        requested: usize,
+    },
+    #[error("Could not open or create segment: {path}")]
+    Segment {
+        #[source]
+        source: Box<series::Error>,
+        path: PathBuf,
+    },
+    #[error(
+        "Could not push, new timestamp: {new} is the same or lies before \
+        the last in the series: {prev}"
+    )]
+    TimeNotAfterLast { new: Timestamp, prev: Timestamp },
+    #[error("Error in segment")]
+    Series(#[source] series::Error),
+    #[error("Could not remove the files of a dropped segment: {0}")]
+    Remove(std::io::Error),
+    #[error("The rollover period must be larger then zero")]
+    ZeroPeriod,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u16,
+    payload_size: usize,
+    rollover: Rollover,
+    /// id the next new segment gets
+    next_id: u64,
+    /// oldest first
+    segments: Vec<SegmentInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct SegmentInfo {
+    /// the files of the segment are named after this
+    id: u64,
+    /// timestamp of the first line in the segment
+    first: Timestamp,
+}
+
+#[derive(Debug)]
+struct Segment {
+    info: SegmentInfo,
+    /// opened when first needed
+    series: Option<ByteSeries>,
+}
+
+/// The builder every segment is opened with, see
+/// [`SegmentedSeries::open_with`].
+pub type SegmentBuilder = ByteSeriesBuilder<false, false, true, true, EmptyResampler>;
+type Configure = Box<dyn FnMut(SegmentBuilder) -> SegmentBuilder + Send>;
+
+/// A time series stored as a directory of [`ByteSeries`] segments, see the
+/// [module docs](self).
+pub struct SegmentedSeries {
+    dir: PathBuf,
+    manifest: Manifest,
+    segments: Vec<Segment>,
+    /// last timestamp in any segment
+    last: Option<Timestamp>,
+    /// applied to the builder of each segment before it is opened
+    configure: Configure,
+}
+
+impl std::fmt::Debug for SegmentedSeries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentedSeries")
+            .field("dir", &self.dir)
+            .field("manifest", &self.manifest)
+            .field("segments", &self.segments)
+            .field("last", &self.last)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SegmentedSeries {
+    /// Opens the series stored in `dir`, if there is none a new one is
+    /// created. The `rollover` is only used for new segments, it can
+    /// differ from the one the existing segments were created with.
+    ///
+    /// # Errors
+    /// If the rollover is a period of zero, the manifest can not be read or
+    /// the newest segment can not be opened. See [`Error`] for all that can
+    /// go wrong.
+    pub fn open(
+        dir: impl AsRef<Path>,
+        payload_size: usize,
+        rollover: Rollover,
+    ) -> Result<Self, Error> {
+        Self::open_with(dir, payload_size, rollover, |builder| builder)
+    }
+
+    /// Like [`open`](Self::open) but every segment is opened with the
+    /// builder `configure` returns. Use it to set options such as the
+    /// compression, checksums, durability or the corruption callback. It is
+    /// called again for every segment that is opened.
+    ///
+    /// Options that only apply when a series is created, like the
+    /// compression, only affect new segments. Lines must be pushed in
+    /// order, the reorder window and duplicate policy do not change that.
+    ///
+    /// # Errors
+    /// See [`open`](Self::open).
+    pub fn open_with(
+        dir: impl AsRef<Path>,
+        payload_size: usize,
+        rollover: Rollover,
+        configure: impl FnMut(SegmentBuilder) -> SegmentBuilder + Send + 'static,
+    ) -> Result<Self, Error> {
+        if rollover == Rollover::Period(0) {
+            return Err(Error::ZeroPeriod);
+        }
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir).map_err(Error::CreateDir)?;
+
+        let mut created = false;
+        let manifest = match std::fs::read_to_string(dir.join(MANIFEST)) {
+            Ok(text) => {
+                let manifest: Manifest =
+                    ron::from_str(&text).map_err(Error::ParseManifest)?;
+                if manifest.version != MANIFEST_VERSION {
+                    return Err(Error::UnsupportedVersion(manifest.version));
+                }
+                if manifest.payload_size != payload_size {
+                    return Err(Error::PayloadSizeMismatch {
+                        in_manifest: manifest.payload_size,
+                        requested: payload_size,
+                    });
+                }
+                Manifest {
+                    rollover,
+                    ..manifest
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                created = true;
+                Manifest {
+                    version: MANIFEST_VERSION,
+                    payload_size,
+                    rollover,
+                    next_id: 0,
+                    segments: Vec::new(),
+                }
+            }
+            Err(e) => return Err(Error::ReadManifest(e)),
+        };
+
+        let mut series = Self {
+            segments: manifest
+                .segments
+                .iter()
+                .map(|info| Segment {
+                    info: *info,
+                    series: None,
+                })
+                .collect(),
+            dir,
+            manifest,
+            last: None,
+            configure: Box::new(configure),
+        };
+        if created {
+            series.write_manifest()?;
+        }
+
+        // lines are only pushed to the newest, it is always open
+        if let Some(newest) = series.segments.len().checked_sub(1) {
+            series.last = series.segment(newest)?.range().map(|r| *r.end());
+        }
+        if series.last.is_none() {
+            // only the newest segment can be empty, it was created but
+            // we crashed before lines got pushed to it
+            if let Some(before_newest) = series.segments.len().checked_sub(2) {
+                series.last = series.with_segment(before_newest, |segment| {
+                    Ok(segment.range().map(|r| *r.end()))
+                })?;
+            }
+        }
+        Ok(series)
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("segment_{id:06}"))
+    }
+
+    /// Opens or creates the segment if that has not yet happened
+    fn segment(&mut self, idx: usize) -> Result<&mut ByteSeries, Error> {
+        if self.segments[idx].series.is_none() {
+            let path = self.segment_path(self.segments[idx].info.id);
+            let payload_size = self.manifest.payload_size;
+            let configure = &mut self.configure;
+            let mut open = |create_new| {
+                configure(ByteSeries::builder())
+                    .payload_size(payload_size)
+                    .create_new(create_new)
+                    .with_any_header()
+                    .open(&path)
+                    .map(|(series, _)| series)
+            };
+            let is_newest = idx + 1 == self.segments.len();
+            let series = match open(false) {
+                Err(series::Error::Open(series::data::OpenError::File {
+                    source: crate::file::OpenError::Io(e),
+                    ..
+                })) if is_newest && e.kind() == std::io::ErrorKind::NotFound => {
+                    open(true)
+                }
+                res => res,
+            }
+            .map_err(|source| Error::Segment {
+                source: Box::new(source),
+                path,
+            })?;
+            self.segments[idx].series = Some(series);
+        }
+        Ok(self.segments[idx]
+            .series
+            .as_mut()
+            .expect("just opened it if it was not"))
+    }
+
+    /// Runs `f` on the segment. Only the newest segment stays open, lines
+    /// are pushed to it. Any other is closed again once `f` is done so
+    /// reading a long series does not keep all its files open.
+    fn with_segment<T>(
+        &mut self,
+        idx: usize,
+        f: impl FnOnce(&mut ByteSeries) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let res = f(self.segment(idx)?);
+        if idx + 1 < self.segments.len() {
+            self.segments[idx].series = None;
+        }
+        res
+    }
+
+    /// The manifest is written to a temporary file that then replaces the
+    /// existing one. The manifest is therefore never partially written.
+    fn write_manifest(&mut self) -> Result<(), Error> {
+        self.manifest.segments = self.segments.iter().map(|s| s.info).collect();
+        let text = ron::ser::to_string_pretty(&self.manifest, Default::default())
+            .map_err(Error::SerializeManifest)?;
+
+        let part_path = self.dir.join(MANIFEST).with_extension("ron.part");
+        let mut part = std::fs::File::create(&part_path).map_err(Error::WriteManifest)?;
+        part.write_all(text.as_bytes())
+            .map_err(Error::WriteManifest)?;
+        part.sync_data().map_err(Error::WriteManifest)?;
+        std::fs::rename(part_path, self.dir.join(MANIFEST))
+            .map_err(Error::WriteManifest)?;
+        crate::file::sync_dir_of(&self.dir.join(MANIFEST)).map_err(Error::WriteManifest)
+    }
+
+    fn needs_rollover(&self, ts: Timestamp) -> bool {
+        let Some(newest) = self.segments.last() else {
+            return true;
+        };
+        match self.manifest.rollover {
+            Rollover::Period(period) => newest.info.first / period != ts / period,
+            Rollover::Size(max) => newest
+                .series
+                .as_ref()
+                .is_some_and(|series| series.data.data_len >= max),
+        }
+    }
+
+    /// The manifest lists the new segment before its files are created.
+    /// If we crash in between the segment is created when the series is
+    /// opened again.
+    fn roll_over(&mut self, ts: Timestamp) -> Result<(), Error> {
+        let newest_is_empty = self
+            .segments
+            .last_mut()
+            .and_then(|newest| newest.series.as_ref())
+            .is_some_and(ByteSeries::is_empty);
+        if newest_is_empty {
+            // left from a crash, reuse it
+            self.segments
+                .last_mut()
+                .expect("newest_is_empty is true")
+                .info
+                .first = ts;
+            return self.write_manifest();
+        }
+
+        self.segments.push(Segment {
+            info: SegmentInfo {
+                id: self.manifest.next_id,
+                first: ts,
+            },
+            series: None,
+        });
+        self.manifest.next_id += 1;
+        self.write_manifest()?;
+        if let Some(previous) = self.segments.len().checked_sub(2) {
+            // close it, the next push goes to the new segment
+            self.segments[previous].series = None;
+        }
+        self.segment(self.segments.len() - 1)?;
+        Ok(())
+    }
+
+    /// Appends a line to the newest segment. Starts a new segment first if
+    /// the [`Rollover`] requires it.
+    ///
+    /// # Errors
+    /// If the timestamp is not after the last or pushing to the segment
+    /// failed. See [`Error`] for all that can go wrong.
+    pub fn push_line(
+        &mut self,
+        ts: Timestamp,
+        line: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        if let Some(prev) = self.last.filter(|last| *last >= ts) {
+            return Err(Error::TimeNotAfterLast { new: ts, prev });
+        }
+
+        if self.needs_rollover(ts) {
+            self.roll_over(ts)?;
+        }
+        let newest = self.segments.len() - 1;
+        self.segment(newest)?
+            .push_line(ts, line)
+            .map_err(Error::Series)?;
+        self.last = Some(ts);
+        Ok(())
+    }
+
+    /// Reads all lines in `range`, from every segment that holds some.
+    /// Segments are opened when needed and closed again after they are read,
+    /// except for the newest. Returns the corrupt data skipped in all
+    /// segments together.
+    ///
+    /// # Errors
+    /// If a segment could not be opened or read. See [`Error`] for all that
+    /// can go wrong.
+    pub fn read_all<D: Decoder>(
+        &mut self,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &mut D,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        let range = bounds(&range);
+        let mut skipped = Skipped::default();
+        for idx in self.segments_in(&range) {
+            skipped += self.with_segment(idx, |segment| match segment.range() {
+                Some(lines) if overlaps(&range, &lines) => segment
+                    .read_all(range, decoder, timestamps, data)
+                    .map_err(Error::Series),
+                _ => Ok(Skipped::default()),
+            })?;
+        }
+        Ok(skipped)
+    }
+
+    /// Reads the first `n` lines in `range`. Starts at the oldest segment
+    /// that can hold lines in the range and continues with the next until
+    /// `n` lines are read. Returns the corrupt data skipped in all segments
+    /// together.
+    ///
+    /// # Errors
+    /// If a segment could not be opened or read. See [`Error`] for all that
+    /// can go wrong.
+    pub fn read_first_n<D: Decoder>(
+        &mut self,
+        n: usize,
+        decoder: &mut D,
+        range: impl RangeBounds<Timestamp>,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        let range = bounds(&range);
+        let already_read = timestamps.len();
+        let mut skipped = Skipped::default();
+        for idx in self.segments_in(&range) {
+            let left = n.saturating_sub(timestamps.len() - already_read);
+            if left == 0 {
+                break;
+            }
+            skipped += self.with_segment(idx, |segment| match segment.range() {
+                Some(lines) if overlaps(&range, &lines) => segment
+                    .read_first_n(left, decoder, range, timestamps, data)
+                    .map_err(Error::Series),
+                _ => Ok(Skipped::default()),
+            })?;
+        }
+        Ok(skipped)
+    }
+
+    /// Reads the last `n` lines in `range`, they are appended oldest first.
+    /// Starts at the newest segment that can hold lines in the range and
+    /// continues with the one before it until `n` lines are read. Returns the
+    /// corrupt data skipped in all segments together.
+    ///
+    /// # Errors
+    /// If a segment could not be opened or read. See [`Error`] for all that
+    /// can go wrong.
+    pub fn read_last_n<D: Decoder>(
+        &mut self,
+        n: usize,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &mut D,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        let range = bounds(&range);
+        let mut skipped = Skipped::default();
+        // newest first, each holds its lines oldest first
+        let mut parts = Vec::new();
+        let mut read = 0;
+        for idx in self.segments_in(&range).into_iter().rev() {
+            let left = n.saturating_sub(read);
+            if left == 0 {
+                break;
+            }
+            let mut part = (Vec::new(), Vec::new());
+            skipped += self.with_segment(idx, |segment| match segment.range() {
+                Some(lines) if overlaps(&range, &lines) => segment
+                    .read_last_n(left, range, decoder, &mut part.0, &mut part.1)
+                    .map_err(Error::Series),
+                _ => Ok(Skipped::default()),
+            })?;
+            read += part.0.len();
+            parts.push(part);
+        }
+        for (part_timestamps, part_data) in parts.into_iter().rev() {
+            timestamps.extend(part_timestamps);
+            data.extend(part_data);
+        }
+        Ok(skipped)
+    }
+
+    /// Reads about `n` lines spread evenly over `range`, see
+    /// [`ByteSeries::read_n`]. Each segment that holds lines in the range
+    /// gets a share of `n` in proportion to the number of lines it holds
+    /// there. To find those the segments are opened twice, once to count
+    /// and once to read.
+    ///
+    /// # Errors
+    /// If a segment could not be opened or read. See [`Error`] for all that
+    /// can go wrong.
+    pub fn read_n<R: Resampler>(
+        &mut self,
+        n: usize,
+        range: impl RangeBounds<Timestamp>,
+        resampler: &mut R,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<<R as Decoder>::Item>,
+        skip_corrupt_meta: bool,
+    ) -> Result<Skipped, Error> {
+        let range = bounds(&range);
+        let mut in_range = Vec::new();
+        for idx in self.segments_in(&range) {
+            let lines = self.with_segment(idx, |segment| {
+                segment.n_lines_between(range).map_err(Error::Series)
+            })?;
+            if lines > 0 {
+                in_range.push((idx, lines));
+            }
+        }
+
+        let total: u64 = in_range.iter().map(|(_, lines)| lines).sum();
+        let mut skipped = Skipped::default();
+        for (idx, lines) in in_range {
+            let share = (n as u64 * lines).div_ceil(total).max(1);
+            let share = usize::try_from(share).expect("at most n");
+            skipped += self.with_segment(idx, |segment| {
+                segment
+                    .read_n(share, range, resampler, timestamps, data, skip_corrupt_meta)
+                    .map_err(Error::Series)
+            })?;
+        }
+        Ok(skipped)
+    }
+
+    /// The segments that could hold lines in `range` going by the timestamp
+    /// of their first line, oldest first
+    fn segments_in(&self, range: &(Bound<Timestamp>, Bound<Timestamp>)) -> Vec<usize> {
+        (0..self.segments.len())
+            .filter(|idx| {
+                let first = self.segments[*idx].info.first;
+                let last = self
+                    .segments
+                    .get(idx + 1)
+                    .map_or(Timestamp::MAX, |next| next.info.first.saturating_sub(1));
+                overlaps(range, &(first..=last))
+            })
+            .collect()
+    }
+
+    /// Removes the segments that only hold lines before `ts` by deleting
+    /// their files. The newest segment is never removed. Returns the number
+    /// of segments removed.
+    ///
+    /// # Errors
+    /// If the manifest could not be updated or the files not be removed.
+    /// If only removing the files failed they are no longer part of the
+    /// series, they can be removed by hand.
+    pub fn drop_segments_before(&mut self, ts: Timestamp) -> Result<usize, Error> {
+        let n_dropped = self
+            .segments
+            .windows(2)
+            .take_while(|pair| pair[1].info.first <= ts)
+            .count();
+        if n_dropped == 0 {
+            return Ok(0);
+        }
+
+        let dropped: Vec<_> = self.segments.drain(..n_dropped).collect();
+        // manifest first, if we crash after it the files are only left over
+        self.write_manifest()?;
+        for segment in dropped {
+            let name = self.segment_path(segment.info.id);
+            drop(segment.series);
+            for path in series::files(&name).map_err(Error::Remove)? {
+                match std::fs::remove_file(path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(Error::Remove(e));
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(n_dropped)
+    }
+
+    /// The range of timestamps of all lines in the series
+    #[must_use]
+    pub fn range(&self) -> Option<RangeInclusive<Timestamp>> {
+        let first = self.segments.first()?.info.first;
+        self.last.map(|last| first..=last)
+    }
+
+    /// The number of segments the series is split over
+    #[must_use]
+    pub fn n_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Returns the number of lines in all segments. This opens every
+    /// segment one after the other.
+    ///
+    /// # Errors
+    /// If a segment could not be opened
+    pub fn len(&mut self) -> Result<u64, Error> {
+        let mut len = 0;
+        for idx in 0..self.segments.len() {
+            len += self.with_segment(idx, |segment| Ok(segment.len()))?;
+        }
+        Ok(len)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.last.is_none()
+    }
+
+    /// See [`ByteSeries::flush_to_disk`], only the newest segment can have
+    /// lines that are not yet flushed.
+    ///
+    /// # Errors
+    /// When the OS fails to flush files to disk the underlying
+    /// io error is returned
+    pub fn flush_to_disk(&mut self) -> std::io::Result<()> {
+        match self.segments.last_mut().and_then(|s| s.series.as_mut()) {
+            Some(newest) => newest.flush_to_disk(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn overlaps(
+    range: &(Bound<Timestamp>, Bound<Timestamp>),
+    lines: &RangeInclusive<Timestamp>,
+) -> bool {
+    let starts_before_end = match range.0 {
+        Bound::Included(start) => start <= *lines.end(),
+        Bound::Excluded(start) => start < *lines.end(),
+        Bound::Unbounded => true,
+    };
+    let ends_after_start = match range.1 {
+        Bound::Included(end) => end >= *lines.start(),
+        Bound::Excluded(end) => end > *lines.start(),
+        Bound::Unbounded => true,
+    };
+    starts_before_end && ends_after_start
+}
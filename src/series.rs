@@ -1,32 +1,60 @@
 use core::fmt;
+use std::borrow::Cow;
 use std::fmt::Debug;
 use std::ops::{Bound, RangeBounds};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use downsample::resample::EmptyResampler;
 use itertools::Itertools;
 use tracing::instrument;
 
+pub mod compact;
+pub mod compression;
+pub mod corruption;
 pub mod data;
 pub mod downsample;
+pub mod duplicates;
+pub mod durability;
 mod file_header;
+pub mod iter;
+mod read;
+pub mod reader;
+mod reorder;
+pub mod repair;
+pub mod retention;
+pub mod verify;
 
+use compact::CompactReport;
+use compression::{Encoding, Layout};
+use corruption::Skipped;
 use data::index::PayloadSize;
 use data::Data;
+use duplicates::DuplicatePolicy;
+use iter::{Iter, RevIter};
+use reader::SeriesReader;
 
-use crate::builder::PayloadSizeOption;
-use crate::seek::{self, Estimate};
+use crate::builder::{PayloadSizeOption, UserHeader};
+use crate::seek;
 use crate::{builder, CorruptionCallback, Decoder, Resampler, Timestamp};
 
 use self::downsample::DownSampledData;
 
 trait DownSampled: fmt::Debug + Send + 'static {
     fn process(&mut self, ts: Timestamp, line: &[u8]) -> Result<(), data::PushError>;
-    fn estimate_lines(
-        &self,
-        start: Bound<Timestamp>,
-        end: Bound<Timestamp>,
-    ) -> Option<Estimate>;
+    /// Keeps the downsampled data consistent with `source` after it got
+    /// truncated.
+    fn truncate_after(
+        &mut self,
+        source: &mut Data,
+        ts: Timestamp,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<(), downsample::TruncateError>;
+    /// Downsamples all of `source` again, used after it got compacted.
+    fn rebuild(
+        &mut self,
+        source: &mut Data,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<(), downsample::RebuildError>;
     fn data_mut(&mut self) -> &mut Data;
     fn data(&self) -> &Data;
 }
@@ -39,7 +67,7 @@ pub enum TimeRange {
 }
 
 impl TimeRange {
-    fn from_data(data: &mut Data) -> Self {
+    fn from_data(data: &Data) -> Self {
         if let Some(first) = data.first_meta_timestamp() {
             let last = data.last_time().expect(
                 "if there is a first time there is a last (can be equal to first)",
@@ -50,14 +78,26 @@ impl TimeRange {
         }
     }
 
+    fn last(&self) -> Option<Timestamp> {
+        match self {
+            Self::Some(range) => Some(*range.end()),
+            Self::None => None,
+        }
+    }
+
+    fn check_after_last(&self, new_ts: Timestamp) -> Result<(), Error> {
+        match self {
+            Self::Some(range) if *range.end() >= new_ts => Err(Error::TimeNotAfterLast {
+                new: new_ts,
+                prev: *range.end(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
     fn update(&mut self, new_ts: Timestamp) -> Result<(), Error> {
+        self.check_after_last(new_ts)?;
         let new = match self {
-            Self::Some(range) if *range.end() >= new_ts => {
-                return Err(Error::TimeNotAfterLast {
-                    new: new_ts,
-                    prev: *range.end(),
-                })
-            }
             Self::Some(range) => Self::Some(*range.start()..=new_ts),
             Self::None => Self::Some(new_ts..=new_ts),
         };
@@ -80,10 +120,28 @@ pub struct ByteSeries {
     pub(crate) data: Data,
     downsampled: Vec<Box<dyn DownSampled>>,
     corruption_callback: Option<CorruptionCallback>,
+    durability: durability::Tracker,
+    reorder: Option<reorder::Window>,
+    duplicates: DuplicatePolicy,
+    retention: retention::Tracker,
 
+    /// range of the lines that are written
     pub(crate) range: TimeRange,
 }
 
+impl Drop for ByteSeries {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_reorder_window() {
+            tracing::error!(
+                "Lost lines in the reorder window, could not write them: {e}"
+            );
+        }
+        if let Err(e) = self.commit() {
+            tracing::error!("Lost buffered lines, could not write them out: {e}");
+        }
+    }
+}
+
 impl Debug for ByteSeries {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ByteSeries")
@@ -95,6 +153,52 @@ impl Debug for ByteSeries {
     }
 }
 
+pub(crate) fn bounds(
+    range: &impl RangeBounds<Timestamp>,
+) -> (Bound<Timestamp>, Bound<Timestamp>) {
+    (range.start_bound().cloned(), range.end_bound().cloned())
+}
+
+/// Every file the series at `name` (without extension) can own: the data,
+/// its tail and block table, the index, the checksums, the quarantine and the files of the
+/// downsampled caches. Most of these do not exist for every series.
+pub(crate) fn files(name: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let data = name.with_extension("byteseries");
+    let mut files = vec![
+        crate::file::tail_path(&data),
+        crate::file::table_path(&data),
+        data,
+        name.with_extension("byteseries_index"),
+        data::checksums::path(name),
+        name.with_extension("quarantine"),
+    ];
+    files.extend(downsample::cache_files(name)?);
+    Ok(files)
+}
+
+/// How a new series stores its blocks given the builder options
+fn encoding(
+    options: &builder::Options,
+    payload_size: usize,
+) -> Result<Encoding, compression::LayoutError> {
+    let layout = options.payload_layout.clone().map(Layout::new);
+    if let Some(layout) = &layout {
+        if options.compression.is_none() {
+            return Err(compression::LayoutError::NeedsCompression);
+        }
+        if layout.payload_size() != payload_size {
+            return Err(compression::LayoutError::SizeMismatch {
+                layout: layout.payload_size(),
+                payload: payload_size,
+            });
+        }
+    }
+    Ok(Encoding {
+        compression: options.compression,
+        layout,
+    })
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Parameter check failed")]
@@ -126,6 +230,33 @@ pub enum Error {
     Header(#[source] builder::HeaderError),
     #[error("The line should be exactly: {required} bytes long, it was: {got}")]
     WrongLineLength { required: usize, got: usize },
+    #[error("Could not memory map the data: {0}")]
+    Mmap(std::io::Error),
+    #[error("Could not write out the buffered lines: {0}")]
+    Commit(std::io::Error),
+    #[error("Pushed the line(s) but could not flush them to disk: {0}")]
+    Flushing(std::io::Error),
+    #[error("Could not truncate the data")]
+    Truncating(#[source] data::TruncateError),
+    #[error(
+        "Could not push, new timestamp: {new} lies before the reorder window \
+        that ends at the newest line: {newest}"
+    )]
+    OutsideReorderWindow { new: u64, newest: u64 },
+    #[error("Could not drop the old lines")]
+    Dropping(#[source] data::DropError),
+    #[error("Could not compact the data")]
+    Compacting(#[source] data::CompactError),
+    #[error("The payload layout can not be used")]
+    PayloadLayout(#[source] compression::LayoutError),
+    #[error("Could not migrate the data")]
+    Migrating(#[source] data::CompactError),
+    #[error("Could not remove the downsampled caches: {0}")]
+    RemovingCaches(std::io::Error),
+    #[error("Could not read the header to upgrade it")]
+    ReadingHeader(#[source] crate::file::OpenError),
+    #[error("Could not write the upgraded header")]
+    Upgrading(#[source] crate::file::OpenError),
 }
 
 impl ByteSeries {
@@ -142,22 +273,32 @@ impl ByteSeries {
         resampler: R,
         resample_configs: Vec<downsample::Config>,
         mut corruption_callback: Option<CorruptionCallback>,
+        options: builder::Options,
     ) -> Result<ByteSeries, Error>
     where
         R: Resampler + Clone + Send + 'static,
         R::State: Send + 'static,
     {
+        let encoding = encoding(&options, payload_size).map_err(Error::PayloadLayout)?;
         let header = file_header::SeriesParams {
             payload_size,
-            version: 1,
+            encoding: encoding.clone(),
+            checksums: options.checksums,
         };
-        let mut header = header.to_text();
+        let mut header = header.to_bytes();
         header.extend_from_slice(user_header);
 
         let payload_size = PayloadSize::from_raw(payload_size);
-        let mut data =
-            Data::new(name.as_ref(), payload_size, &header).map_err(Error::Create)?;
-        Ok(ByteSeries {
+        let mut data = Data::new(
+            name.as_ref(),
+            payload_size,
+            &header,
+            encoding,
+            options.checksums,
+            corruption::Series::Main,
+        )
+        .map_err(Error::Create)?;
+        let mut series = ByteSeries {
             range: TimeRange::None,
             downsampled: resample_configs
                 .into_iter()
@@ -178,7 +319,13 @@ impl ByteSeries {
                 .map_err(Error::Downsampled)?,
             data,
             corruption_callback,
-        })
+            durability: durability::Tracker::default(),
+            reorder: None,
+            duplicates: DuplicatePolicy::default(),
+            retention: retention::Tracker::default(),
+        };
+        series.configure(options)?;
+        Ok(series)
     }
 
     /// Caches one or more downsampled versions of the data
@@ -189,54 +336,140 @@ impl ByteSeries {
     /// process) and the cache did not the library can panic. This should be
     /// exceedingly rare. Please let me know if this hits you and I'll see into
     /// fixing this behavior.
-    #[instrument(skip(corruption_callback))]
-    pub(crate) fn open_existing_with_resampler<R>(
+    ///
+    /// The user header is checked before anything is repaired or changed.
+    #[instrument(skip(header, corruption_callback))]
+    pub(crate) fn open_existing_with_resampler<R, H>(
         name: impl AsRef<Path> + fmt::Debug,
         payload_size: PayloadSizeOption,
+        header: H,
         resampler: R,
         resample_configs: Vec<downsample::Config>,
         mut corruption_callback: Option<CorruptionCallback>,
-    ) -> Result<(ByteSeries, Vec<u8>), Error>
+        options: builder::Options,
+    ) -> Result<(ByteSeries, H::Output), Error>
     where
         R: Resampler + Clone + Send + 'static,
         R::State: Send + 'static,
+        H: UserHeader,
     {
         let path = name.as_ref().with_extension("byteseries");
         let file = crate::file::FileWithHeader::open_existing(path.clone())
             .map_err(|source| data::OpenError::File { source, path })
             .map_err(Error::Open)?;
-        let (file, header) = file.split_off_header();
-        let (payload_size, user_header) =
-            file_header::check_and_split_off_user_header(header.clone(), payload_size)?;
-
-        let mut data =
-            Data::open_existing(&name, file, payload_size, &mut corruption_callback)
-                .map_err(Error::Open)?;
-        Ok((
-            ByteSeries {
-                range: TimeRange::from_data(&mut data),
-                downsampled: resample_configs
-                    .into_iter()
-                    .map(|config| {
-                        DownSampledData::open_or_create(
-                            resampler.clone(),
-                            config,
-                            name.as_ref(),
-                            payload_size,
-                            &mut data,
-                            &mut corruption_callback,
-                        )
-                        .map_err(downsample::Error::OpenOrCreate)
-                    })
-                    .map_ok(Box::new)
-                    .map_ok(|boxed| boxed as Box<dyn DownSampled>)
-                    .collect::<Result<Vec<_>, downsample::Error>>()
-                    .map_err(Error::Downsampled)?,
-                data,
-                corruption_callback,
-            },
-            user_header,
-        ))
+        let (file, header_in_file) = file.split_off_header();
+        let (payload_size, encoding, checksums, user_header) =
+            file_header::check_and_split_off_user_header(header_in_file, payload_size)?;
+        let user_header = header.check(user_header).map_err(Error::Header)?;
+        let file = file
+            .open_compressed(encoding, true)
+            .map_err(data::OpenError::CheckOrRepair)
+            .map_err(Error::Open)?;
+
+        let mut data = Data::open_existing(
+            &name,
+            file,
+            payload_size,
+            checksums,
+            options.repair,
+            corruption::Series::Main,
+            &mut corruption_callback,
+        )
+        .map_err(Error::Open)?;
+        let mut series = ByteSeries {
+            range: TimeRange::from_data(&data),
+            downsampled: resample_configs
+                .into_iter()
+                .map(|config| {
+                    DownSampledData::open_or_create(
+                        resampler.clone(),
+                        config,
+                        name.as_ref(),
+                        payload_size,
+                        &mut data,
+                        options.repair,
+                        &mut corruption_callback,
+                    )
+                    .map_err(downsample::Error::OpenOrCreate)
+                })
+                .map_ok(Box::new)
+                .map_ok(|boxed| boxed as Box<dyn DownSampled>)
+                .collect::<Result<Vec<_>, downsample::Error>>()
+                .map_err(Error::Downsampled)?,
+            data,
+            corruption_callback,
+            durability: durability::Tracker::default(),
+            reorder: None,
+            duplicates: DuplicatePolicy::default(),
+            retention: retention::Tracker::default(),
+        };
+        series.configure(options)?;
+        Ok((series, user_header))
+    }
+
+    /// Opens the series without changing any of its files, see
+    /// [`read_only`](builder::ByteSeriesBuilder::read_only). Path is
+    /// *without* any extension.
+    #[instrument(skip(header, corruption_callback))]
+    pub(crate) fn open_read_only<H: UserHeader>(
+        name: impl AsRef<Path> + fmt::Debug,
+        payload_size: PayloadSizeOption,
+        header: H,
+        mut corruption_callback: Option<CorruptionCallback>,
+        options: builder::Options,
+    ) -> Result<(SeriesReader, H::Output), Error> {
+        let path = name.as_ref().with_extension("byteseries");
+        let file = crate::file::FileWithHeader::open_existing_read_only(path.clone())
+            .map_err(|source| data::OpenError::File { source, path })
+            .map_err(Error::Open)?;
+        let (file, header_in_file) = file.split_off_header();
+        let (payload_size, encoding, checksums, user_header) =
+            file_header::check_and_split_off_user_header(header_in_file, payload_size)?;
+        let user_header = header.check(user_header).map_err(Error::Header)?;
+        let file = file
+            .open_compressed(encoding, false)
+            .map_err(data::OpenError::CheckOrRepair)
+            .map_err(Error::Open)?;
+
+        let mut data = Data::open_read_only(
+            &name,
+            file,
+            payload_size,
+            checksums,
+            &mut corruption_callback,
+        )
+        .map_err(Error::Open)?;
+        if options.mmap_reads {
+            data.map_reads().map_err(Error::Mmap)?;
+        }
+        let reader = SeriesReader::read_only(data.reader(), corruption_callback);
+        Ok((reader, user_header))
+    }
+
+    fn configure(&mut self, options: builder::Options) -> Result<(), Error> {
+        self.durability = durability::Tracker::new(options.durability);
+        self.reorder = options.reorder_window.map(reorder::Window::new);
+        self.duplicates = options.duplicates;
+        if let Some(policy) = options.buffer_writes {
+            self.data.buffer_writes(policy).map_err(Error::Commit)?;
+            for downsampled in &mut self.downsampled {
+                downsampled
+                    .data_mut()
+                    .buffer_writes(policy)
+                    .map_err(Error::Commit)?;
+            }
+        }
+        if options.mmap_reads {
+            self.data.map_reads().map_err(Error::Mmap)?;
+            for downsampled in &mut self.downsampled {
+                downsampled.data_mut().map_reads().map_err(Error::Mmap)?;
+            }
+        }
+        self.retention = retention::Tracker::new(options.retention);
+        if let Some(policy) = self.retention.policy() {
+            self.apply_retention(policy)?;
+        }
+        Ok(())
     }
 
     #[instrument(skip(self, line), level = "trace")]
@@ -245,11 +478,16 @@ impl ByteSeries {
         ts: Timestamp,
         line: impl AsRef<[u8]>,
     ) -> Result<(), Error> {
-        if line.as_ref().len() != self.data.payload_size().raw() {
-            return Err(Error::WrongLineLength {
-                required: self.data.payload_size().raw(),
-                got: line.as_ref().len(),
-            });
+        self.check_line_length(line.as_ref())?;
+        if self.range.last() == Some(ts) {
+            return self.push_duplicate_of_written(ts, line.as_ref());
+        }
+
+        if let Some(window) = &mut self.reorder {
+            self.range.check_after_last(ts)?;
+            window.insert(ts, line.as_ref().to_vec(), &mut self.duplicates)?;
+            let expired = window.take_expired();
+            return self.write_lines(expired);
         }
 
         //write 16 bit timestamp and then the line to file
@@ -265,12 +503,228 @@ impl ByteSeries {
                 .process(ts, line.as_ref())
                 .map_err(Error::Downampling)?;
         }
+        self.apply_durability(1)?;
+        self.check_retention(1)
+    }
+
+    /// Appends many lines at once. This is far faster then calling
+    /// [`push_line`](Self::push_line) for each of them as all lines are
+    /// written to the data file in one go.
+    ///
+    /// # Errors
+    /// If any of the lines has the wrong length or a timestamp that is not
+    /// after the one before it nothing is written. See the [`Error`] docs
+    /// for everything else that can go wrong.
+    #[instrument(skip_all, level = "trace")]
+    pub fn push_lines<L: AsRef<[u8]>>(
+        &mut self,
+        lines: impl IntoIterator<Item = (Timestamp, L)>,
+    ) -> Result<(), Error> {
+        let lines: Vec<_> = lines.into_iter().collect();
+        for (_, line) in &lines {
+            self.check_line_length(line.as_ref())?;
+        }
+        let (lines, written_replacement) = self.resolve_duplicates(&lines)?;
+
+        if let Some(window) = &mut self.reorder {
+            let mut inserted = Vec::new();
+            for (ts, line) in &lines {
+                let res = self.range.check_after_last(*ts).and_then(|()| {
+                    window.insert(*ts, line.to_vec(), &mut self.duplicates)
+                });
+                match res {
+                    Ok(undo) => inserted.push((*ts, undo)),
+                    Err(e) => {
+                        for (ts, undo) in inserted.into_iter().rev() {
+                            window.undo(ts, undo);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+        } else {
+            let mut range = self.range.clone();
+            for (ts, _) in &lines {
+                range.update(*ts)?;
+            }
+        }
+
+        if let Some(replacement) = written_replacement {
+            self.data
+                .overwrite_last_payload(&replacement)
+                .map_err(Error::Pushing)?;
+        }
+        match &mut self.reorder {
+            Some(window) => {
+                let expired = window.take_expired();
+                self.write_lines(expired)
+            }
+            None => self.write_lines(lines),
+        }
+    }
+
+    /// Applies the [`DuplicatePolicy`] to a line with the same timestamp as
+    /// the last written line.
+    fn push_duplicate_of_written(
+        &mut self,
+        ts: Timestamp,
+        line: &[u8],
+    ) -> Result<(), Error> {
+        let data = &self.data;
+        let earlier = || data.last_payload().map_err(Error::Reading);
+        let Some(replacement) = self.duplicates.resolve(ts, earlier, line)? else {
+            return Ok(());
+        };
+        if self
+            .data
+            .overwrite_last_payload(&replacement)
+            .map_err(Error::Pushing)?
+        {
+            self.apply_durability(1)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the [`DuplicatePolicy`] to lines with the same timestamp as
+    /// the line before them. Also returns what should replace the last
+    /// written line if any line has its timestamp.
+    #[allow(clippy::type_complexity)]
+    fn resolve_duplicates<'a, L: AsRef<[u8]>>(
+        &mut self,
+        lines: &'a [(Timestamp, L)],
+    ) -> Result<(Vec<(Timestamp, Cow<'a, [u8]>)>, Option<Vec<u8>>), Error> {
+        let last_written = self.range.last();
+        let mut written_replacement: Option<Vec<u8>> = None;
+        let mut resolved: Vec<(Timestamp, Cow<[u8]>)> = Vec::with_capacity(lines.len());
+
+        for (ts, line) in lines {
+            let (ts, line) = (*ts, line.as_ref());
+            if Some(ts) == last_written {
+                let data = &self.data;
+                let earlier = written_replacement.clone();
+                let earlier = || match earlier {
+                    Some(earlier) => Ok(earlier),
+                    None => data.last_payload().map_err(Error::Reading),
+                };
+                if let Some(replacement) = self.duplicates.resolve(ts, earlier, line)? {
+                    written_replacement = Some(replacement);
+                }
+            } else if let Some((_, earlier)) =
+                resolved.last_mut().filter(|(prev_ts, _)| *prev_ts == ts)
+            {
+                let replacement =
+                    self.duplicates.resolve(ts, || Ok(earlier.to_vec()), line)?;
+                if let Some(replacement) = replacement {
+                    *earlier = Cow::Owned(replacement);
+                }
+            } else {
+                resolved.push((ts, Cow::Borrowed(line)));
+            }
+        }
+        Ok((resolved, written_replacement))
+    }
+
+    fn check_line_length(&self, line: &[u8]) -> Result<(), Error> {
+        if line.len() == self.data.payload_size().raw() {
+            Ok(())
+        } else {
+            Err(Error::WrongLineLength {
+                required: self.data.payload_size().raw(),
+                got: line.len(),
+            })
+        }
+    }
+
+    /// Lines must have the right length
+    fn write_lines<L: AsRef<[u8]>>(
+        &mut self,
+        lines: Vec<(Timestamp, L)>,
+    ) -> Result<(), Error> {
+        let mut range = self.range.clone();
+        for (ts, _) in &lines {
+            range.update(*ts)?;
+        }
+
+        self.data
+            .push_lines(lines.iter().map(|(ts, line)| (*ts, line.as_ref())))
+            .map_err(Error::Pushing)?;
+        self.range = range;
+
+        for (ts, line) in &lines {
+            for downsampled in &mut self.downsampled {
+                downsampled
+                    .process(*ts, line.as_ref())
+                    .map_err(Error::Downampling)?;
+            }
+        }
+        self.apply_durability(lines.len())?;
+        self.check_retention(lines.len())
+    }
+
+    /// Writes all lines held back by the reorder window, see
+    /// [`reorder_window`](builder::ByteSeriesBuilder::reorder_window). After
+    /// this lines older then those written are rejected again. Does nothing
+    /// if there is no reorder window.
+    ///
+    /// # Errors
+    /// See the [`Error`] docs for everything that can go wrong. The lines
+    /// are dropped if writing fails.
+    pub fn flush_reorder_window(&mut self) -> Result<(), Error> {
+        let Some(window) = &mut self.reorder else {
+            return Ok(());
+        };
+        let lines = window.take_all();
+        self.write_lines(lines)
+    }
+
+    /// False if the range only contains lines that are still in the reorder
+    /// window.
+    fn on_disk(&self, range: &impl RangeBounds<Timestamp>) -> bool {
+        let Some(window) = &self.reorder else {
+            return true;
+        };
+        if window.range(range).next().is_none() {
+            return true; // let reading the data report any issues
+        }
+        let TimeRange::Some(written) = &self.range else {
+            return false;
+        };
+        match range.start_bound() {
+            Bound::Included(start) => start <= written.end(),
+            Bound::Excluded(start) => start < written.end(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn apply_durability(&mut self, pushed: usize) -> Result<(), Error> {
+        if pushed > 0 && self.durability.pushed(pushed) {
+            self.flush_to_disk().map_err(Error::Flushing)?;
+        }
         Ok(())
     }
 
+    fn check_retention(&mut self, pushed: usize) -> Result<(), Error> {
+        match self.retention.pushed(pushed) {
+            Some(policy) => self.apply_retention(policy),
+            None => Ok(()),
+        }
+    }
+
+    fn apply_retention(&mut self, policy: retention::Retention) -> Result<(), Error> {
+        let newest = self.range().map(|range| *range.end());
+        match policy.cutoff(&self.data, newest) {
+            Some(cutoff) => self.drop_before(cutoff),
+            None => Ok(()),
+        }
+    }
+
     /// Will return zero samples if there is nothing to read. If `skip_corrupt_meta` is true this
     /// will skip data between a corrupt meta section and the next meta section.
     ///
+    /// Returns how much corrupt data the
+    /// [corruption callback](crate::builder::ByteSeriesBuilder::with_callback_on_recoverable_corruption)
+    /// allowed to skip, the other read functions do too.
+    ///
     /// # Errors
     ///
     /// See the [`Error`] docs for an exhaustive list of everything that can go wrong.
@@ -283,32 +737,84 @@ impl ByteSeries {
         decoder: &mut D,
         timestamps: &mut Vec<Timestamp>,
         data: &mut Vec<D::Item>,
-    ) -> Result<(), Error> {
-        let Some(seek) = seek::RoughPos::new(
-            &self.data,
-            range.start_bound().cloned(),
-            range.end_bound().cloned(),
-        )
-        .map_err(Error::InvalidRange)?
-        .refine(&mut self.data)
-        .map_err(Error::Seeking)?
-        else {
-            tracing::debug!(
-                "No data to read within given range, probably due to \
-                a gap in the data."
-            );
-            return Ok(());
-        };
-
-        self.data
-            .read_all(
-                seek,
+    ) -> Result<Skipped, Error> {
+        let range = bounds(&range);
+        let mut skipped = Skipped::default();
+        if self.on_disk(&range) {
+            skipped = read::all(
+                &self.data,
                 &mut self.corruption_callback,
+                range,
                 decoder,
                 timestamps,
                 data,
+            )?;
+        }
+        if let Some(window) = &self.reorder {
+            window.read_first_n(usize::MAX, &range, decoder, timestamps, data);
+        }
+        Ok(skipped)
+    }
+
+    /// Lazily reads all lines within the range, oldest first. Unlike
+    /// [`read_all`](Self::read_all) this never holds more then one chunk
+    /// (16 KiB) of the data in memory. Use this to stream large ranges.
+    ///
+    /// The iterator is empty if there is nothing to read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the range lies outside the data or seeking failed.
+    /// Errors while reading are returned by the iterator, after an error the
+    /// iterator ends.
+    pub fn iter_range<'a, D: Decoder>(
+        &'a mut self,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &'a mut D,
+    ) -> Result<Iter<'a, D>, Error> {
+        let range = bounds(&range);
+        let iter = if self.on_disk(&range) {
+            read::iter(&self.data, &mut self.corruption_callback, range, decoder)?
+        } else {
+            Iter::new(
+                &self.data.file_handle,
+                &mut self.corruption_callback,
+                decoder,
+                None,
             )
-            .map_err(Error::Reading)
+        };
+        Ok(iter.with_pending(self.reorder.as_ref().map(|w| w.range(&range))))
+    }
+
+    /// Lazily reads all lines within the range, newest first. Just like
+    /// [`iter_range`](Self::iter_range) this never holds more then one chunk
+    /// (16 KiB) of the data in memory.
+    ///
+    /// The iterator is empty if there is nothing to read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the range lies outside the data or seeking failed.
+    /// Errors while reading are returned by the iterator, after an error the
+    /// iterator ends.
+    pub fn iter_range_rev<'a, D: Decoder>(
+        &'a mut self,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &'a mut D,
+    ) -> Result<RevIter<'a, D>, Error> {
+        let range = bounds(&range);
+        let iter = if self.on_disk(&range) {
+            read::iter_rev(&self.data, &mut self.corruption_callback, range, decoder)?
+        } else {
+            RevIter::new(
+                &self.data.file_handle,
+                &self.data.index,
+                &mut self.corruption_callback,
+                decoder,
+                None,
+            )
+        };
+        Ok(iter.with_pending(self.reorder.as_ref().map(|w| w.range(&range))))
     }
 
     /// Will return zero if there is nothing to read between the given points.
@@ -320,23 +826,17 @@ impl ByteSeries {
     ///
     /// # Panics
     pub fn n_lines_between(
-        &mut self,
+        &self,
         range: impl RangeBounds<Timestamp>,
     ) -> Result<u64, Error> {
-        let start = range.start_bound().cloned();
-        let end = range.end_bound().cloned();
-
-        let pos = match seek::RoughPos::new(&self.data, start, end) {
-            Ok(pos) => pos,
-            Err(seek::Error::EmptyFile) => return Ok(0),
-            Err(other) => return Err(Error::InvalidRange(other)),
+        let range = bounds(&range);
+        let on_disk = if self.on_disk(&range) {
+            read::n_lines_between(&self.data, range)?
+        } else {
+            0
         };
-
-        Ok(pos
-            .refine(&mut self.data)
-            .map_err(Error::Seeking)?
-            .map(|pos| pos.lines(&self.data))
-            .unwrap_or(0))
+        let pending = self.reorder.as_ref().map_or(0, |w| w.range(&range).count());
+        Ok(on_disk + pending as u64)
     }
     /// Will return between zero and two times `n` samples
     ///
@@ -361,7 +861,7 @@ impl ByteSeries {
         timestamps: &mut Vec<Timestamp>,
         data: &mut Vec<<R as Decoder>::Item>,
         skip_corrupt_meta: bool,
-    ) -> Result<(), Error> {
+    ) -> Result<Skipped, Error> {
         assert!(
             self.downsampled
                 .windows(2)
@@ -369,59 +869,50 @@ impl ByteSeries {
             "downsampled must be sorted in descending resolution/numb lines"
         );
 
-        let start = range.start_bound().cloned();
-        let end = range.end_bound().cloned();
-
-        let mut optimal_data = &mut self.data;
-        for downsampled in &mut self.downsampled {
-            let Some(estimate) = downsampled.estimate_lines(start, end) else {
-                break; // more downsampled files are empty
-            };
-            if estimate.max < n as u64 {
-                tracing::debug!(
-                    "not enough datapoints, not using next\
-                    downsamled cache, estimate was: {estimate:?}"
-                );
-                break;
-            }
-            if estimate.min < n as u64 {
-                tracing::debug!(
-                    "possibly not enough datapoints, not using \
-                    next level downsamled cache, estimate was: {estimate:?}"
-                );
-                break;
-            }
-            tracing::debug!("using downsampled data: {downsampled:?}");
-            optimal_data = downsampled.data_mut();
+        let range = bounds(&range);
+        let pending = self.reorder.as_ref().map_or(0, |w| w.range(&range).count());
+        if pending == 0 {
+            return read::n(
+                &self.data,
+                self.downsampled.iter().map(|d| d.data()),
+                &mut self.corruption_callback,
+                n,
+                range,
+                resampler,
+                timestamps,
+                data,
+            );
         }
 
-        let Some(seek) = seek::RoughPos::new(optimal_data, start, end)
-            .map_err(Error::InvalidRange)?
-            .refine(optimal_data)
-            .map_err(Error::Seeking)?
-        else {
-            tracing::debug!(
-                "No data to read within given range, probably due to \
-                a gap in the data."
-            );
-            return Ok(());
+        // lines in the reorder window are resampled separately, split n
+        // between the data and the window.
+        let on_disk = if self.on_disk(&range) {
+            read::n_lines_between(&self.data, range)?
+        } else {
+            0
         };
-
-        let lines = seek.lines(optimal_data);
-        let bucket_size = 1.max(lines / n as u64);
-        let bucket_size =
-            usize::try_from(bucket_size).map_err(|_| Error::TooMuchToResample)?;
-
-        optimal_data
-            .read_resampling(
-                seek,
+        let total = on_disk + pending as u64;
+        let mut skipped = Skipped::default();
+        if on_disk > 0 {
+            let n_on_disk = (n as u64 * on_disk).div_ceil(total).max(1);
+            skipped = read::n(
+                &self.data,
+                self.downsampled.iter().map(|d| d.data()),
                 &mut self.corruption_callback,
+                usize::try_from(n_on_disk).expect("at most n"),
+                range,
                 resampler,
-                bucket_size,
                 timestamps,
                 data,
-            )
-            .map_err(Error::Reading)
+            )?;
+        }
+        let bucket_size = 1.max(total / n.max(1) as u64);
+        let bucket_size =
+            usize::try_from(bucket_size).map_err(|_| Error::TooMuchToResample)?;
+        if let Some(window) = &self.reorder {
+            window.read_resampling(&range, resampler, bucket_size, timestamps, data);
+        }
+        Ok(skipped)
     }
 
     /// Will return between zero and `n` samples
@@ -443,33 +934,87 @@ impl ByteSeries {
         range: impl RangeBounds<Timestamp>,
         timestamps: &mut Vec<Timestamp>,
         data: &mut Vec<D::Item>,
-    ) -> Result<(), Error> {
-        let Some(seek) = seek::RoughPos::new(
-            &self.data,
-            range.start_bound().cloned(),
-            range.end_bound().cloned(),
-        )
-        .map_err(Error::InvalidRange)?
-        .refine(&mut self.data)
-        .map_err(Error::Seeking)?
-        else {
-            tracing::debug!(
-                "No data to read within given range, probably due to \
-                a gap in the data."
-            );
-            return Ok(());
-        };
-
-        self.data
-            .read_first_n(
+    ) -> Result<Skipped, Error> {
+        let range = bounds(&range);
+        let already_read = timestamps.len();
+        let mut skipped = Skipped::default();
+        if self.on_disk(&range) {
+            skipped = read::first_n(
+                &self.data,
+                &mut self.corruption_callback,
                 n,
-                seek,
+                decoder,
+                range,
+                timestamps,
+                data,
+            )?;
+        }
+        if let Some(window) = &self.reorder {
+            let left = n - (timestamps.len() - already_read);
+            window.read_first_n(left, &range, decoder, timestamps, data);
+        }
+        Ok(skipped)
+    }
+
+    /// Will return between zero and `n` samples, the newest `n` in the range.
+    /// The samples are appended to `timestamps` and `data` oldest first, just
+    /// like the other read functions.
+    ///
+    /// No interpolation or resampling is performed.
+    ///
+    /// # Errors
+    ///
+    /// See the [`Error`] docs for an exhaustive list of everything that can go wrong.
+    /// Its mostly IO-issues.
+    #[instrument(skip(self, decoder, timestamps, data),
+        fields(range = format!("{:?}..{:?}", range.start_bound(), range.end_bound())))]
+    pub fn read_last_n<D: Decoder>(
+        &mut self,
+        n: usize,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &mut D,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        let range = bounds(&range);
+        let pending: Vec<_> = self
+            .reorder
+            .iter()
+            .flat_map(|w| w.range(&range).rev().take(n))
+            .collect();
+        let mut skipped = Skipped::default();
+        if pending.len() < n && self.on_disk(&range) {
+            skipped = read::last_n(
+                &self.data,
                 &mut self.corruption_callback,
+                n - pending.len(),
+                range,
                 decoder,
                 timestamps,
                 data,
-            )
-            .map_err(Error::Reading)
+            )?;
+        }
+        for (ts, line) in pending.into_iter().rev() {
+            timestamps.push(*ts);
+            data.push(decoder.decode_payload(line));
+        }
+        Ok(skipped)
+    }
+
+    /// Returns a reader that can be moved to another thread and used while
+    /// this keeps appending. It has its own file handles and sees every line
+    /// written before a read starts. Lines still in a reorder window or
+    /// buffer are not yet written. Cloning it is cheap, every clone opens
+    /// its own file handles on first use.
+    #[must_use]
+    pub fn reader(&mut self) -> SeriesReader {
+        SeriesReader::new(
+            self.data.reader(),
+            self.downsampled
+                .iter_mut()
+                .map(|d| d.data_mut().reader())
+                .collect(),
+        )
     }
 
     /// # Errors
@@ -483,29 +1028,308 @@ impl ByteSeries {
         D: Decoder + Clone,
         <D as Decoder>::Item: Clone,
     {
+        if let Some(last) = self.reorder.as_ref().and_then(|w| w.last_line(decoder)) {
+            return Ok(last);
+        }
         self.data.last_line(decoder, &mut self.corruption_callback)
     }
 
+    /// Removes all lines with a timestamp after `ts`, including those in the
+    /// reorder window. The downsampled caches are updated too.
+    ///
+    /// # Errors
+    /// See the [`Error`] docs for an exhaustive list of everything that can go
+    /// wrong. Its mostly io-errors.
+    pub fn truncate_after(&mut self, ts: Timestamp) -> Result<(), Error> {
+        if let Some(window) = &mut self.reorder {
+            window.truncate_after(ts);
+        }
+        self.data
+            .truncate_after(ts, &mut self.corruption_callback)
+            .map_err(Error::Truncating)?;
+        self.range = TimeRange::from_data(&self.data);
+
+        for downsampled in &mut self.downsampled {
+            downsampled
+                .truncate_after(&mut self.data, ts, &mut self.corruption_callback)
+                .map_err(downsample::Error::Truncating)
+                .map_err(Error::Downsampled)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the lines before `ts` from the data, the downsampled caches
+    /// and the reorder window. To drop old lines automatically see
+    /// [`Retention`](crate::Retention).
+    ///
+    /// Lines are dropped per meta section, the lines that share one with the
+    /// first line at or after `ts` are kept even if they are older. A meta
+    /// section spans at most 2^16 timestamp units.
+    ///
+    /// The kept lines are copied to new files that then replace the old.
+    /// A crash leaves either the old or the new files.
+    ///
+    /// # Errors
+    /// See the [`Error`] docs for everything that can go wrong. If dropping
+    /// from a downsampled cache fails it can be out of sync with the data,
+    /// it is repaired when the series is opened again.
+    pub fn drop_before(&mut self, ts: Timestamp) -> Result<(), Error> {
+        if let Some(window) = &mut self.reorder {
+            window.drop_before(ts);
+        }
+        self.data.drop_before(ts).map_err(Error::Dropping)?;
+        self.range = TimeRange::from_data(&self.data);
+
+        for downsampled in &mut self.downsampled {
+            downsampled
+                .data_mut()
+                .drop_before(ts)
+                .map_err(Error::Dropping)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the data with a meta section only where the timestamp no
+    /// longer fits in the 16 bits stored with each line. Files that had
+    /// many gaps, crashes or repairs shrink. The downsampled caches are
+    /// created again from the compacted data. Lines in the reorder window
+    /// are not touched.
+    ///
+    /// The new data file and index are written as `.part` files and then
+    /// replace the old, a crash leaves either the old or the new files.
+    ///
+    /// # Errors
+    /// See the [`Error`] docs for everything that can go wrong. If
+    /// downsampling fails the cache is repaired when the series is opened
+    /// again.
+    pub fn compact(&mut self) -> Result<CompactReport, Error> {
+        let bytes_before = self.size_on_disk();
+        let out_of_order_lines = self
+            .data
+            .compact(&mut self.corruption_callback)
+            .map_err(Error::Compacting)?;
+        self.range = TimeRange::from_data(&self.data);
+
+        for downsampled in &mut self.downsampled {
+            downsampled
+                .rebuild(&mut self.data, &mut self.corruption_callback)
+                .map_err(downsample::Error::Rebuilding)
+                .map_err(Error::Downsampled)?;
+        }
+        Ok(CompactReport {
+            bytes_before,
+            bytes_after: self.size_on_disk(),
+            out_of_order_lines,
+        })
+    }
+
+    /// Like [`compact`](Self::compact) but writes the compacted data to a
+    /// new series at `path`. The downsampled caches are not copied, they
+    /// are created once the new series is opened with them. The report
+    /// only covers the data and index.
+    ///
+    /// # Errors
+    /// If there already is a series at `path`. See the [`Error`] docs for
+    /// everything else that can go wrong.
+    pub fn compact_to(&mut self, path: impl AsRef<Path>) -> Result<CompactReport, Error> {
+        let name = if path
+            .as_ref()
+            .extension()
+            .is_some_and(|ext| ext == "byteseries")
+        {
+            path.as_ref().with_extension("")
+        } else {
+            path.as_ref().to_owned()
+        };
+
+        let (compacted, out_of_order_lines) = self
+            .data
+            .compact_to(&name, &mut self.corruption_callback)
+            .map_err(Error::Compacting)?;
+        Ok(CompactReport {
+            bytes_before: self.data.size_on_disk(),
+            bytes_after: compacted.size_on_disk(),
+            out_of_order_lines,
+        })
+    }
+
+    /// Rewrites the series at `path` to lines of `new_payload_size`. Every
+    /// payload is passed through `transform` which must return exactly
+    /// `new_payload_size` bytes. Use this when the payload changes, for
+    /// example because a field was added. The user header is kept, to change
+    /// it see [`migrate_with_header`](Self::migrate_with_header).
+    ///
+    /// The series must not be open. The new data file and index are written
+    /// as `.part` files that then replace the old, a crash leaves either the
+    /// old or the new files. The downsampled caches are removed, they are
+    /// created again once the series is opened with them. The compression
+    /// is kept, the payload layout described the old payload and is
+    /// dropped.
+    ///
+    /// # Errors
+    /// If `transform` returns a payload of the wrong length nothing is
+    /// changed. See the [`Error`] docs for everything else that can go
+    /// wrong.
+    pub fn migrate(
+        path: impl AsRef<Path>,
+        new_payload_size: usize,
+        transform: impl FnMut(&[u8]) -> Vec<u8>,
+    ) -> Result<CompactReport, Error> {
+        Self::migrate_with_header(path, new_payload_size, |header| header, transform)
+    }
+
+    /// Like [`migrate`](Self::migrate), `update_header` gets the current user
+    /// header and returns the one for the migrated series.
+    ///
+    /// # Errors
+    /// See [`migrate`](Self::migrate).
+    pub fn migrate_with_header(
+        path: impl AsRef<Path>,
+        new_payload_size: usize,
+        update_header: impl FnOnce(Vec<u8>) -> Vec<u8>,
+        mut transform: impl FnMut(&[u8]) -> Vec<u8>,
+    ) -> Result<CompactReport, Error> {
+        let name = if path
+            .as_ref()
+            .extension()
+            .is_some_and(|ext| ext == "byteseries")
+        {
+            path.as_ref().with_extension("")
+        } else {
+            path.as_ref().to_owned()
+        };
+
+        let (mut series, user_header) = ByteSeries::builder()
+            .retrieve_payload_size()
+            .with_any_header()
+            .open(&name)?;
+        let encoding = Encoding {
+            layout: None,
+            ..series.data.encoding()
+        };
+        let header = file_header::SeriesParams {
+            payload_size: new_payload_size,
+            encoding: encoding.clone(),
+            checksums: series.data.has_checksums(),
+        };
+        let mut header = header.to_bytes();
+        header.extend_from_slice(&update_header(user_header));
+
+        let (migrated, out_of_order_lines) = series
+            .data
+            .migrate_to_parts(
+                PayloadSize::from_raw(new_payload_size),
+                &header,
+                encoding.clone(),
+                &mut transform,
+                &mut series.corruption_callback,
+            )
+            .map_err(Error::Migrating)?;
+        let report = CompactReport {
+            bytes_before: series.data.size_on_disk(),
+            bytes_after: migrated.size_on_disk(),
+            out_of_order_lines,
+        };
+        drop(migrated);
+        drop(series);
+
+        // the caches hold payloads of the old size, they are useless now
+        for cache in downsample::cache_files(&name).map_err(Error::RemovingCaches)? {
+            std::fs::remove_file(cache).map_err(Error::RemovingCaches)?;
+        }
+        data::move_parts_in_place(&name, !encoding.is_none())
+            .map_err(data::ReplaceError::Move)
+            .map_err(data::CompactError::Replace)
+            .map_err(Error::Migrating)?;
+        Ok(report)
+    }
+
+    /// Rewrites the header of the series at `path` in the current file
+    /// format. Series created by an older version of this library open fine
+    /// without this, however their header has no checksum. Returns `false`
+    /// if the header already was in the current format.
+    ///
+    /// The series must not be open. Only the data file is rewritten, the
+    /// data is copied as is to a `.part` file that then replaces the old. A
+    /// crash leaves either the old or the new file.
+    ///
+    /// # Errors
+    /// If the current header could not be read or parsed, or the new file
+    /// could not be written.
+    pub fn upgrade(path: impl AsRef<Path>) -> Result<bool, Error> {
+        let path = path.as_ref().with_extension("byteseries");
+        let header = crate::file::FileWithHeader::open_existing_read_only(path.clone())
+            .map_err(Error::ReadingHeader)?
+            .header;
+        let Some(upgraded) = file_header::upgrade(&header)? else {
+            return Ok(false);
+        };
+        crate::file::FileWithHeader::replace_header(&path, &upgraded)
+            .map_err(Error::Upgrading)?;
+        Ok(true)
+    }
+
+    fn size_on_disk(&self) -> u64 {
+        self.data.size_on_disk()
+            + self
+                .downsampled
+                .iter()
+                .map(|downsampled| downsampled.data().size_on_disk())
+                .sum::<u64>()
+    }
+
+    /// Writes out all lines buffered in memory, see
+    /// [`buffer_writes`](builder::ByteSeriesBuilder::buffer_writes). Does
+    /// nothing if writes are not buffered.
+    ///
+    /// # Errors
+    /// Returns the io error if writing failed, the lines stay buffered.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        self.data.commit().map_err(Error::Commit)?;
+        for downsampled in &mut self.downsampled {
+            downsampled.data_mut().commit().map_err(Error::Commit)?;
+        }
+        Ok(())
+    }
+
+    /// Commits any buffered lines then waits for the OS to write them and
+    /// those of the downsampled caches to disk. To do this regularly
+    /// without calling this see [`Durability`](crate::Durability).
+    ///
     /// # Errors
     /// When the OS fails to flush files to disk the underlying
     /// io error is returned
     pub fn flush_to_disk(&mut self) -> std::io::Result<()> {
-        self.data.flush_to_disk()
+        self.data.flush_to_disk()?;
+        for downsampled in &mut self.downsampled {
+            downsampled.data_mut().flush_to_disk()?;
+        }
+        self.durability.flushed();
+        Ok(())
     }
 
     #[must_use]
     #[allow(clippy::missing_panics_doc)] // is bug if panic
     pub fn range(&self) -> Option<core::ops::RangeInclusive<Timestamp>> {
-        self.range.clone().into()
+        let written: Option<core::ops::RangeInclusive<Timestamp>> =
+            self.range.clone().into();
+        let Some((first, last)) = self
+            .reorder
+            .as_ref()
+            .and_then(|w| w.first().zip(w.newest()))
+        else {
+            return written;
+        };
+        Some(written.map_or(first, |range| *range.start())..=last)
     }
 
-    /// Returns the number of lines in the file.
+    /// Returns the number of lines in the file and reorder window.
     pub fn len(&self) -> u64 {
-        self.data.len()
+        self.data.len() + self.reorder.as_ref().map_or(0, |w| w.len() as u64)
     }
 
     pub fn is_empty(&self) -> bool {
-        self.data.len() == 0
+        self.len() == 0
     }
 
     pub fn payload_size(&self) -> usize {
@@ -0,0 +1,18 @@
+/// What [`ByteSeries::compact`](crate::ByteSeries::compact) or
+/// [`ByteSeries::compact_to`](crate::ByteSeries::compact_to) did. The sizes
+/// are those of the data and index files without their headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactReport {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// Lines left out as their timestamp was not after the line before
+    /// them. Only older versions of byteseries wrote those.
+    pub out_of_order_lines: u64,
+}
+
+impl CompactReport {
+    #[must_use]
+    pub fn bytes_saved(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
@@ -0,0 +1,155 @@
+use std::io;
+
+mod columns;
+pub(crate) use columns::Layout;
+pub use columns::{Field, LayoutError};
+
+/// Lines are grouped into blocks of about this many bytes before they are
+/// compressed. Each block starts with a meta section.
+pub(crate) const BLOCK_SIZE: usize = 64 * 1024;
+
+/// How a [`ByteSeries`](crate::ByteSeries) compresses its lines. Lines are
+/// grouped into blocks of around 64 KiB that are compressed once full. The
+/// lines in the last block are stored uncompressed in a `.byteseries_tail`
+/// file next to the data. A `.byteseries_blocks` file lists where each block
+/// starts, opening reads it instead of the whole data file.
+///
+/// Reading a line means decompressing its entire block. The most recently
+/// read block is kept in memory so reading lines in order stays fast.
+///
+/// The compression is set when the series is created, it can not be
+/// changed afterwards. The downsampled caches are never compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Fast compression, needs the `lz4` feature
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Better compression at the cost of speed, needs the `zstd` feature.
+    /// The level is stored in the file header, it is used again for the
+    /// blocks compressed after the series is reopened.
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+}
+
+/// How the blocks of a compressed series are stored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Encoding {
+    pub(crate) compression: Compression,
+    /// If set the payloads are encoded relative to the line before them
+    /// before the block is compressed
+    pub(crate) layout: Option<Layout>,
+}
+
+impl Encoding {
+    pub(crate) fn is_none(&self) -> bool {
+        self.compression.is_none()
+    }
+
+    pub(crate) fn encode(&self, raw: &[u8]) -> io::Result<Vec<u8>> {
+        if let Some(layout) = &self.layout {
+            let mut raw = raw.to_vec();
+            layout.encode(&mut raw);
+            self.compression.compress(&raw)
+        } else {
+            self.compression.compress(raw)
+        }
+    }
+
+    pub(crate) fn decode(&self, stored: &[u8], raw_len: usize) -> io::Result<Vec<u8>> {
+        let mut raw = self.compression.decompress(stored, raw_len)?;
+        if let Some(layout) = &self.layout {
+            layout.decode(&mut raw);
+        }
+        Ok(raw)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "The data is compressed using {0}, that needs the '{0}' feature of \
+    byteseries which is not enabled"
+)]
+pub struct UnsupportedError(pub(crate) String);
+
+impl Compression {
+    /// How the compression is called in the file header
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => "lz4",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { .. } => "zstd",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Result<Self, UnsupportedError> {
+        match name {
+            "none" => Ok(Compression::None),
+            #[cfg(feature = "lz4")]
+            "lz4" => Ok(Compression::Lz4),
+            // the level is stored separately, files from before that
+            // use the default
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(Compression::Zstd {
+                level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            }),
+            other => Err(UnsupportedError(other.to_owned())),
+        }
+    }
+
+    /// The level if the compression has one, it is stored in the file
+    /// header next to the name
+    pub(crate) fn level(self) -> Option<i32> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level } => Some(level),
+            _ => None,
+        }
+    }
+
+    /// Sets the level if the compression has one
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+    pub(crate) fn with_level(self, level: i32) -> Self {
+        match self {
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { .. } => Compression::Zstd { level },
+            other => other,
+        }
+    }
+
+    pub(crate) fn is_none(self) -> bool {
+        self == Compression::None
+    }
+
+    pub(crate) fn compress(self, raw: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(raw.to_vec()),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::block::compress(raw)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level } => zstd::bulk::compress(raw, level),
+        }
+    }
+
+    pub(crate) fn decompress(self, stored: &[u8], raw_len: usize) -> io::Result<Vec<u8>> {
+        let raw = match self {
+            Compression::None => stored.to_vec(),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4_flex::block::decompress(stored, raw_len)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { .. } => zstd::bulk::decompress(stored, raw_len)?,
+        };
+        if raw.len() == raw_len {
+            Ok(raw)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decompressed block does not have the expected length",
+            ))
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Encodes the payload of each line relative to the line before it in the
+//! same block. Slowly changing values then become mostly zero bytes, which
+//! compress far better. Floats are XOR-ed with their previous value while
+//! integers are stored as the change in their delta (delta-of-delta). The
+//! timestamps and meta sections are left as is.
+
+use crate::series::data::inline_meta::meta::{self, PREAMBLE};
+
+/// The type of one field in the payload, fields are little endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl Field {
+    /// Number of bytes the field takes up in the payload
+    #[must_use]
+    pub fn size(self) -> usize {
+        match self {
+            Field::U8 | Field::I8 => 1,
+            Field::U16 | Field::I16 => 2,
+            Field::U32 | Field::I32 | Field::F32 => 4,
+            Field::U64 | Field::I64 | Field::F64 => 8,
+        }
+    }
+
+    /// How the field is called in the file header
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Field::U8 => "u8",
+            Field::U16 => "u16",
+            Field::U32 => "u32",
+            Field::U64 => "u64",
+            Field::I8 => "i8",
+            Field::I16 => "i16",
+            Field::I32 => "i32",
+            Field::I64 => "i64",
+            Field::F32 => "f32",
+            Field::F64 => "f64",
+        }
+    }
+
+    /// How the field is stored in the binary file header
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Field::U8 => 0,
+            Field::U16 => 1,
+            Field::U32 => 2,
+            Field::U64 => 3,
+            Field::I8 => 4,
+            Field::I16 => 5,
+            Field::I32 => 6,
+            Field::I64 => 7,
+            Field::F32 => 8,
+            Field::F64 => 9,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        Some(match id {
+            0 => Field::U8,
+            1 => Field::U16,
+            2 => Field::U32,
+            3 => Field::U64,
+            4 => Field::I8,
+            5 => Field::I16,
+            6 => Field::I32,
+            7 => Field::I64,
+            8 => Field::F32,
+            9 => Field::F64,
+            _ => return None,
+        })
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, Field::F32 | Field::F64)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LayoutError {
+    #[error(
+        "The fields of the payload layout take up {layout} bytes however \
+        the payload is {payload} bytes"
+    )]
+    SizeMismatch { layout: usize, payload: usize },
+    #[error(
+        "A payload layout is only used while compressing, set a compression \
+        to use it"
+    )]
+    NeedsCompression,
+}
+
+/// The fields the payload consists of, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Layout(Vec<Field>);
+
+/// What the previous line held for a field
+#[derive(Default, Clone, Copy)]
+struct Prev {
+    value: u64,
+    delta: u64,
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Encode,
+    Decode,
+}
+
+impl Layout {
+    pub(crate) fn new(fields: Vec<Field>) -> Self {
+        Self(fields)
+    }
+
+    pub(crate) fn fields(&self) -> &[Field] {
+        &self.0
+    }
+
+    pub(crate) fn payload_size(&self) -> usize {
+        self.0.iter().copied().map(Field::size).sum()
+    }
+
+    /// `block` must start at a line
+    pub(crate) fn encode(&self, block: &mut [u8]) {
+        self.apply(block, Direction::Encode);
+    }
+
+    /// Undoes [`encode`](Self::encode)
+    pub(crate) fn decode(&self, block: &mut [u8]) {
+        self.apply(block, Direction::Decode);
+    }
+
+    fn apply(&self, block: &mut [u8], direction: Direction) {
+        let payload_size = self.payload_size();
+        let mut prev = vec![Prev::default(); self.0.len()];
+        let mut meta_lines_left = 0;
+        for line in block.chunks_exact_mut(payload_size + 2) {
+            // the first two bytes are never changed so the meta sections
+            // are found the same way while encoding and decoding
+            if line[0..2] == PREAMBLE && meta_lines_left == 0 {
+                meta_lines_left = meta::lines_per_metainfo(payload_size);
+            }
+            if meta_lines_left > 0 {
+                meta_lines_left -= 1;
+                continue;
+            }
+
+            let mut start = 2;
+            for (field, prev) in self.0.iter().zip(&mut prev) {
+                let bytes = &mut line[start..start + field.size()];
+                start += field.size();
+                let value = apply_to_field(*field, read(bytes), prev, direction);
+                write(bytes, value);
+            }
+        }
+    }
+}
+
+/// Only the low bytes of the result are stored. With wrapping arithmetic
+/// those only depend on the low bytes of the inputs, so there is no need to
+/// mask the upper bytes.
+fn apply_to_field(
+    field: Field,
+    value: u64,
+    prev: &mut Prev,
+    direction: Direction,
+) -> u64 {
+    match (field.is_float(), direction) {
+        (true, Direction::Encode) => {
+            let encoded = value ^ prev.value;
+            prev.value = value;
+            encoded
+        }
+        (true, Direction::Decode) => {
+            prev.value ^= value;
+            prev.value
+        }
+        (false, Direction::Encode) => {
+            let delta = value.wrapping_sub(prev.value);
+            let encoded = delta.wrapping_sub(prev.delta);
+            *prev = Prev { value, delta };
+            encoded
+        }
+        (false, Direction::Decode) => {
+            let delta = value.wrapping_add(prev.delta);
+            let value = prev.value.wrapping_add(delta);
+            *prev = Prev { value, delta };
+            value
+        }
+    }
+}
+
+fn read(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+fn write(bytes: &mut [u8], value: u64) {
+    let len = bytes.len();
+    bytes.copy_from_slice(&value.to_le_bytes()[..len]);
+}
@@ -0,0 +1,101 @@
+//! What the corruption callback is told, see
+//! [`with_callback_on_recoverable_corruption`](crate::builder::ByteSeriesBuilder::with_callback_on_recoverable_corruption).
+
+use std::ops::AddAssign;
+
+use super::downsample;
+use crate::{CorruptionCallback, Timestamp};
+
+/// Passed to the corruption callback. If it returns true the lines are
+/// skipped, otherwise the read fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionEvent {
+    /// The series the corrupt data is in
+    pub series: Series,
+    /// Start of the corrupt data, as offset in the data after the header
+    pub offset: u64,
+    /// Full timestamp of the last meta section before the corrupt data
+    pub last_meta_ts: Timestamp,
+    /// Number of lines skipped if the callback returns true. Meta section
+    /// lines are counted too.
+    pub lines_to_skip: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Series {
+    /// The lines pushed to the series
+    Main,
+    /// The downsampled cache made with this config
+    Downsampled(downsample::Config),
+}
+
+/// How much corrupt data a read skipped. Returned by the read functions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Skipped {
+    /// Number of times the corruption callback returned true
+    pub corruptions: u64,
+    /// Lines skipped in total, meta section lines are counted too
+    pub lines: u64,
+}
+
+impl Skipped {
+    /// True if nothing was skipped
+    #[must_use]
+    pub fn is_none(&self) -> bool {
+        self.corruptions == 0
+    }
+}
+
+impl AddAssign for Skipped {
+    fn add_assign(&mut self, other: Self) {
+        self.corruptions += other.corruptions;
+        self.lines += other.lines;
+    }
+}
+
+/// Calls the corruption callback during a read and keeps track of what it
+/// allowed to skip.
+pub(crate) struct Handler<'a> {
+    callback: &'a mut Option<CorruptionCallback>,
+    series: &'a Series,
+    pub(crate) skipped: Skipped,
+}
+
+impl<'a> Handler<'a> {
+    pub(crate) fn new(
+        callback: &'a mut Option<CorruptionCallback>,
+        series: &'a Series,
+    ) -> Self {
+        Self {
+            callback,
+            series,
+            skipped: Skipped::default(),
+        }
+    }
+
+    /// Returns true if the callback allows skipping the corrupt lines
+    pub(crate) fn skip(
+        &mut self,
+        offset: u64,
+        last_meta_ts: Timestamp,
+        lines: u64,
+    ) -> bool {
+        let Some(callback) = self.callback.as_mut() else {
+            return false;
+        };
+        let event = CorruptionEvent {
+            series: self.series.clone(),
+            offset,
+            last_meta_ts,
+            lines_to_skip: lines,
+        };
+        if !callback(&event) {
+            return false;
+        }
+        self.skipped += Skipped {
+            corruptions: 1,
+            lines,
+        };
+        true
+    }
+}
@@ -1,19 +1,32 @@
 use core::fmt;
 use inline_meta::meta::lines_per_metainfo;
+use inline_meta::ReadAt;
 use std::io::Write;
-use std::ops::RangeInclusive;
+use std::ops::{Bound, RangeInclusive};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
 use tracing::{instrument, warn};
 
 use crate::file::{self, FileWithHeader, OffsetFile};
+use crate::seek::{self, RoughPos};
+use crate::series::compression::{self, Encoding};
+use crate::series::corruption::{self, Series, Skipped};
+use crate::series::repair::{self, RepairAction, RepairPolicy};
 use crate::{CorruptionCallback, Decoder, Pos, Timestamp};
 
+pub(crate) mod checksums;
+use checksums::Checksums;
 pub(crate) mod inline_meta;
 use inline_meta::FileWithInlineMeta;
 pub mod index;
 use index::{Index, LinePos, PayloadSize};
+pub(crate) mod reader;
+use reader::{DataReader, Published};
 
-use self::index::create::{self, last_meta_timestamp, ExtractingTsError};
+use self::index::create::{
+    self, last_meta_timestamp, last_meta_timestamp_before, ExtractingTsError,
+};
 use self::inline_meta::{meta, SetLen};
 
 /// largest small timestamp that can be stored. This corresponds to
@@ -30,6 +43,27 @@ pub(crate) struct Data {
     pub(crate) data_len: u64,
     /// last timestamp in the data
     last_time: Option<Timestamp>,
+    /// path *without* any extension
+    path: PathBuf,
+    /// Set once a reader is created, readers only see what is published
+    published: Option<Arc<RwLock<Published>>>,
+    /// If set pushed lines are kept in memory till they are committed
+    buffering: Option<Buffering>,
+}
+
+/// When to write out lines that are kept in memory
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BufferPolicy {
+    pub(crate) max_lines: usize,
+    pub(crate) max_delay: Duration,
+}
+
+#[derive(Debug)]
+struct Buffering {
+    policy: BufferPolicy,
+    pending_lines: usize,
+    /// when the oldest pending line was pushed
+    oldest_pending: Option<Instant>,
 }
 
 #[derive(Debug)]
@@ -53,6 +87,8 @@ pub enum CreateError {
     Index(#[source] file::OpenError),
     #[error("Failed to get the length of the data: {0}")]
     GetLength(std::io::Error),
+    #[error("Could not create the checksums file: {0}")]
+    Checksums(std::io::Error),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -76,6 +112,15 @@ pub enum OpenError {
     GetLastMeta(#[source] ExtractingTsError),
     #[error("Could not read the last line to get the last time in Data")]
     ReadLastTime(#[source] ReadError),
+    #[error("Could not open or repair the checksums: {0}")]
+    Checksums(std::io::Error),
+    #[error(
+        "The end of the data needs repairs the repair policy does not \
+        allow: {actions:?}"
+    )]
+    RepairNeeded { actions: Vec<RepairAction> },
+    #[error("Could not save the bytes a repair removes to the quarantine file: {0}")]
+    Quarantine(std::io::Error),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -88,10 +133,94 @@ pub enum PushError {
     Index(std::io::Error),
     #[error("Could not append new data to file")]
     Write(std::io::Error),
+    #[error("Could not compress the full block of lines: {0}")]
+    Compress(std::io::Error),
+    #[error("Could not update the checksums: {0}")]
+    Checksums(std::io::Error),
     #[error("Can only append items newer then the last")]
     OutOfOrder { last: Timestamp, item: Timestamp },
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum TruncateError {
+    #[error("Could not find the last line to keep")]
+    Seek(#[source] seek::Error),
+    #[error("Could not shorten the files: {0}")]
+    Io(std::io::Error),
+    #[error("Could not remove all lines")]
+    Clear(#[source] ClearError),
+    #[error("Could not copy the lines to keep to new files")]
+    Rewrite(#[source] RewriteError),
+    #[error("Could not read the new last line")]
+    ReadLastTime(#[source] ReadError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClearError {
+    #[error("Could not empty the files: {0}")]
+    Io(std::io::Error),
+    #[error("Could not replace the files with empty ones")]
+    Rewrite(#[source] RewriteError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DropError {
+    #[error("Could not find the first line to keep")]
+    Seek(#[source] seek::Error),
+    #[error("Could not remove all lines")]
+    Clear(#[source] ClearError),
+    #[error("Could not copy the lines to keep to new files")]
+    Rewrite(#[source] RewriteError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RewriteError {
+    #[error("Could not write out the buffered lines or compress the last block: {0}")]
+    Seal(std::io::Error),
+    #[error("Could not create the new files")]
+    Create(#[source] file::OpenError),
+    #[error("Could not copy the lines to keep: {0}")]
+    Copy(std::io::Error),
+    #[error("Could not replace the old files")]
+    Replace(#[source] ReplaceError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompactError {
+    #[error("Could not create the new files")]
+    Create(#[source] file::OpenError),
+    #[error("Could not read the lines to copy: {0}")]
+    Read(std::io::Error),
+    #[error(
+        "File must be corrupt, second line MUST also be meta. \
+        You can try to skip data until the next uncorrupted meta \
+        timestamp to do so enable `skipping_over_corrupted_data`"
+    )]
+    CorruptMetaSection,
+    #[error("The checksum of the meta section at byte {section_start} is wrong")]
+    ChecksumMismatch { section_start: u64 },
+    #[error("Could not write the copied lines")]
+    Write(#[source] PushError),
+    #[error("Could not write out or flush the files to disk: {0}")]
+    Sync(std::io::Error),
+    #[error("Could not replace the old files")]
+    Replace(#[source] ReplaceError),
+    #[error("The migrated payload should be {required} bytes long, it was: {got}")]
+    MigratedLength { required: usize, got: usize },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplaceError {
+    #[error("Could not move the new files in place: {0}")]
+    Move(std::io::Error),
+    #[error("Could not open the new data file")]
+    Reopen(#[source] file::OpenError),
+    #[error("Could not memory map or buffer the new files: {0}")]
+    Configure(std::io::Error),
+    #[error("Could not open the new checksums: {0}")]
+    Checksums(std::io::Error),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ReadError {
     #[error("The file is empty")]
@@ -104,6 +233,49 @@ pub enum ReadError {
         timestamp to do so enable `skipping_over_corrupted_data`"
     )]
     CorruptMetaSection,
+    #[error(
+        "The checksum of the meta section at byte {section_start} is wrong, \
+        it or the lines after it are corrupt"
+    )]
+    ChecksumMismatch { section_start: u64 },
+}
+
+/// Removes what an interrupted write left at the end of `file`, the removed
+/// bytes are quarantined first.
+fn repair_tail(
+    name: &Path,
+    file: &mut OffsetFile,
+    payload_size: PayloadSize,
+    policy: RepairPolicy,
+) -> Result<(), OpenError> {
+    let len = file.len().map_err(OpenError::CheckOrRepair)?;
+    let actions: Vec<_> = inline_meta::needed_repairs(&*file, len, payload_size)
+        .map_err(OpenError::CheckOrRepair)?
+        .into_iter()
+        .map(|(reason, removed)| RepairAction {
+            path: name.with_extension("byteseries"),
+            reason,
+            removed,
+        })
+        .collect();
+    let Some(last) = actions.last() else {
+        return Ok(());
+    };
+    if !policy.allows(&actions) {
+        return Err(OpenError::RepairNeeded { actions });
+    }
+
+    for action in &actions {
+        warn!("repairing end of data: {action:?}");
+    }
+    let removed = last.removed.start..len;
+    file.with_bytes_at(removed.clone(), |bytes| {
+        repair::quarantine(name, removed.clone(), bytes)
+    })
+    .and_then(|res| res)
+    .map_err(OpenError::Quarantine)?;
+    file.set_len(removed.start)
+        .map_err(OpenError::CheckOrRepair)
 }
 
 impl Data {
@@ -115,56 +287,84 @@ impl Data {
         name: impl AsRef<Path> + fmt::Debug,
         payload_size: PayloadSize,
         header: &[u8],
+        encoding: Encoding,
+        checksums: bool,
+        series: Series,
     ) -> Result<Self, CreateError> {
         let path = name.as_ref().with_extension("byteseries");
         let file = FileWithHeader::new(&path, header)
             .map_err(|source| CreateError::File { source, path })?;
         let (file_handle, _) = file.split_off_header();
+        let file_handle = file_handle
+            .create_compressed(encoding)
+            .map_err(CreateError::CheckOrRepair)?;
         let data_len = file_handle
             .data_len_bytes()
             .map_err(CreateError::GetLength)?;
-        let file_handle = FileWithInlineMeta::new(file_handle, payload_size)
-            .map_err(CreateError::CheckOrRepair)?;
-        let index = Index::new(name).map_err(CreateError::Index)?;
+        let mut file_handle = FileWithInlineMeta::new(file_handle, payload_size, series);
+        let index = Index::new(&name).map_err(CreateError::Index)?;
+        if checksums {
+            let checksums = Checksums::create(checksums::path(name.as_ref()))
+                .map_err(CreateError::Checksums)?;
+            file_handle.checksums = Some(checksums);
+        }
         Ok(Self {
             file_handle,
             index,
             payload_size,
             data_len,
             last_time: None,
+            path: name.as_ref().to_path_buf(),
+            published: None,
+            buffering: None,
         })
     }
 
+    /// Repairs an interrupted write at the end of the data if `repair`
+    /// allows it.
     #[instrument(skip(corruption_callback))]
     pub(crate) fn open_existing(
         name: impl AsRef<Path> + fmt::Debug,
-        file: OffsetFile,
+        mut file: OffsetFile,
         payload_size: PayloadSize,
+        checksums: bool,
+        repair: RepairPolicy,
+        series: Series,
         corruption_callback: &mut Option<CorruptionCallback>,
     ) -> Result<Data, OpenError> {
-        let mut file = FileWithInlineMeta::new(file, payload_size)
-            .map_err(OpenError::CheckOrRepair)?;
+        repair_tail(name.as_ref(), &mut file, payload_size, repair)?;
+        let mut file = FileWithInlineMeta::new(file, payload_size, series);
         let data_len = file
             .file_handle
             .data_len_bytes()
             .map_err(OpenError::GetLength)?;
         let last_line_starts = data_len.checked_sub((payload_size.line_size()) as u64);
-        let last_full_ts_in_data = last_meta_timestamp(file.inner_mut(), payload_size)
+        let last_full_ts_in_data = last_meta_timestamp(&file.file_handle, payload_size)
             .map_err(OpenError::GetLastMeta)?;
         let index =
             match Index::open_existing(&name, last_line_starts, last_full_ts_in_data) {
                 Ok(index) => index,
                 Err(e) => {
                     warn!("Creating new index, existing is broken: {e}");
-                    Index::create_from_byteseries(file.inner_mut(), payload_size, name)?
+                    Index::create_from_byteseries(&file.file_handle, payload_size, &name)?
                 }
             };
+        if checksums {
+            let checksums = Checksums::open(
+                checksums::path(name.as_ref()),
+                &file.file_handle,
+                index.entries(),
+                data_len,
+            )
+            .map_err(OpenError::Checksums)?;
+            file.checksums = Some(checksums);
+        }
 
         let last_time = match last_line(
             &index,
             data_len,
             payload_size,
-            &mut file,
+            &file,
             &mut EmptyDecoder,
             corruption_callback,
         ) {
@@ -179,16 +379,85 @@ impl Data {
             payload_size,
             data_len,
             last_time,
+            path: name.as_ref().to_path_buf(),
+            published: None,
+            buffering: None,
         };
         Ok(data)
     }
 
+    /// Like [`open_existing`](Self::open_existing) but no file is changed.
+    /// A torn tail is left out of the data and a broken index is rebuilt in
+    /// memory. If `checksums` the meta sections are checked against the
+    /// checksums stored for them.
+    #[instrument(skip(corruption_callback))]
+    pub(crate) fn open_read_only(
+        name: impl AsRef<Path> + fmt::Debug,
+        file: OffsetFile,
+        payload_size: PayloadSize,
+        checksums: bool,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<Data, OpenError> {
+        let file_len = file.data_len_bytes().map_err(OpenError::GetLength)?;
+        let data_len = inline_meta::repaired_len(&file, file_len, payload_size)
+            .map_err(OpenError::CheckOrRepair)?;
+        let last_line_starts = data_len.checked_sub((payload_size.line_size()) as u64);
+        let last_full_ts_in_data =
+            last_meta_timestamp_before(&file, payload_size, data_len)
+                .map_err(OpenError::GetLastMeta)?;
+        let index =
+            match Index::open_in_memory(&name, last_line_starts, last_full_ts_in_data) {
+                Ok(index) => index,
+                Err(e) => {
+                    warn!("Creating index in memory, existing is broken: {e}");
+                    let entries =
+                        create::extract_entries_inner(&file, payload_size, 0, data_len)
+                            .map_err(create::Error::from)?;
+                    Index::in_memory(entries)
+                }
+            };
+
+        let checksums = checksums
+            .then(|| Checksums::read_only(name.as_ref(), index.entries()))
+            .transpose()
+            .map_err(OpenError::Checksums)?;
+        let file = FileWithInlineMeta {
+            file_handle: file,
+            payload_size,
+            checksums,
+            series: Series::Main,
+        };
+        let last_time = match last_line(
+            &index,
+            data_len,
+            payload_size,
+            &file,
+            &mut EmptyDecoder,
+            corruption_callback,
+        ) {
+            Ok((time, _)) => Some(time),
+            Err(ReadError::NoData) => None,
+            Err(other) => return Err(OpenError::ReadLastTime(other)),
+        };
+
+        Ok(Self {
+            file_handle: file,
+            index,
+            payload_size,
+            data_len,
+            last_time,
+            path: name.as_ref().to_path_buf(),
+            published: None,
+            buffering: None,
+        })
+    }
+
     /// # Errors
     ///
     /// See the [`ReadError`] docs for an exhaustive list of everything
     /// that can go wrong.
     pub(crate) fn last_line<T: std::fmt::Debug + std::clone::Clone>(
-        &mut self,
+        &self,
         decoder: &mut impl Decoder<Item = T>,
         corruption_callback: &mut Option<CorruptionCallback>,
     ) -> Result<(Timestamp, T), ReadError> {
@@ -196,7 +465,7 @@ impl Data {
             &self.index,
             self.data_len,
             self.payload_size,
-            &mut self.file_handle,
+            &self.file_handle,
             decoder,
             corruption_callback,
         )
@@ -225,57 +494,260 @@ impl Data {
         ts: Timestamp,
         line: &[u8],
     ) -> Result<(), PushError> {
-        //we store the timestamp - the last recorded full timestamp as u16. If
-        //that overflows a new timestamp will be inserted. The 16 bit small
-        //timestamp is stored little endian
-        let small_ts = self
-            .index
-            .last_timestamp()
-            .map(|last_timestamp| {
-                ts.checked_sub(last_timestamp).ok_or(PushError::OutOfOrder {
-                    last: last_timestamp,
-                    item: ts,
-                })
-            })
-            .transpose()?
-            .and_then(|diff| {
-                if diff > MAX_SMALL_TS {
-                    None
-                } else {
-                    Some(u16::try_from(diff).expect("MAX_SMALL_TS < u16::MAX"))
-                }
-            });
-
+        // a compressed block always starts with a meta section
+        let block_full = self.file_handle.file_handle.block_full();
+        let small_ts = small_ts(self.index.last_timestamp(), ts)?.filter(|_| !block_full);
         let small_ts = small_ts.map(Ok).unwrap_or_else(|| {
             tracing::debug!(
                 "inserting full timestamp and updating index\
                 , timestamp: {ts}"
             );
+            if block_full {
+                self.file_handle
+                    .file_handle
+                    .seal_block()
+                    .map_err(PushError::Compress)?;
+            }
             self.index
                 .update(ts, index::MetaPos(self.data_len))
                 .map_err(PushError::Index)?;
-            let meta = ts.to_le_bytes();
-            let written = meta::write(&mut self.file_handle, meta, self.payload_size)
+            let mut section = Vec::new();
+            meta::write(&mut section, ts.to_le_bytes(), self.payload_size)
+                .expect("writing to a Vec never fails");
+            self.file_handle
+                .write_all(&section)
                 .map_err(PushError::Meta)?;
-            self.data_len += written;
+            self.update_checksums(&section, [self.data_len])?;
+            self.data_len += section.len() as u64;
             Ok(0) // value does not matter, full timestamp just ahead is used
         })?;
 
+        let mut new_line = small_ts.to_le_bytes().to_vec();
+        new_line.extend_from_slice(&line[..self.payload_size.raw()]);
         self.file_handle
-            .write_all(&small_ts.to_le_bytes())
-            .map_err(PushError::Write)?;
-        self.file_handle
-            .write_all(&line[..self.payload_size.raw()])
+            .write_all(&new_line)
             .map_err(PushError::Write)?;
+        self.update_checksums(&new_line, [])?;
         self.data_len += self.payload_size.line_size() as u64;
         self.last_time = Some(ts);
+        self.pushed(1)
+    }
+
+    /// Publishes the new lines or if writes are buffered commits them when
+    /// the buffer policy requires it.
+    fn pushed(&mut self, lines: usize) -> Result<(), PushError> {
+        let Some(buffering) = &mut self.buffering else {
+            self.publish();
+            return Ok(());
+        };
+
+        buffering.pending_lines += lines;
+        let oldest_pending = *buffering.oldest_pending.get_or_insert_with(Instant::now);
+        if buffering.pending_lines >= buffering.policy.max_lines
+            || oldest_pending.elapsed() >= buffering.policy.max_delay
+        {
+            self.commit().map_err(PushError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Keep pushed lines in memory, they are written out once the policy
+    /// requires it or [`commit`](Self::commit) is called.
+    pub(crate) fn buffer_writes(&mut self, policy: BufferPolicy) -> std::io::Result<()> {
+        self.file_handle.file_handle.buffer_writes()?;
+        self.index.buffer_writes()?;
+        self.buffering = Some(Buffering {
+            policy,
+            pending_lines: 0,
+            oldest_pending: None,
+        });
         Ok(())
     }
 
+    /// Writes out any lines kept in memory, first the data then the index.
+    /// Does nothing if writes are not buffered.
+    pub(crate) fn commit(&mut self) -> std::io::Result<()> {
+        let Some(buffering) = &mut self.buffering else {
+            return Ok(());
+        };
+
+        self.file_handle.file_handle.commit()?;
+        self.index.commit()?;
+        buffering.pending_lines = 0;
+        buffering.oldest_pending = None;
+        self.publish();
+        Ok(())
+    }
+
+    /// Append many lines using a single write to the data file and one to
+    /// the index. The data is written before the index, if that fails the
+    /// index is repaired when the data is opened again.
+    ///
+    /// If the data is compressed there is a write for every block that
+    /// fills up.
+    #[instrument(skip_all, level = "trace")]
+    pub(crate) fn push_lines<'a>(
+        &mut self,
+        lines: impl IntoIterator<Item = (Timestamp, &'a [u8])>,
+    ) -> Result<(), PushError> {
+        let mut buf = Vec::new();
+        let mut new_entries = Vec::new();
+        let mut last_meta_ts = self.index.last_timestamp();
+        let mut last_time = self.last_time;
+        let mut new_lines = 0;
+        let mut unsealed = self.file_handle.file_handle.unsealed_len();
+
+        for (ts, line) in lines {
+            let block_full =
+                unsealed.is_some_and(|len| len + buf.len() >= compression::BLOCK_SIZE);
+            let small_ts = small_ts(last_meta_ts, ts)?.filter(|_| !block_full);
+            let small_ts = if let Some(small_ts) = small_ts {
+                small_ts
+            } else {
+                if block_full {
+                    self.write_lines(&buf, &new_entries, last_time)?;
+                    buf.clear();
+                    new_entries.clear();
+                    self.file_handle
+                        .file_handle
+                        .seal_block()
+                        .map_err(PushError::Compress)?;
+                    unsealed = Some(0);
+                }
+                let meta_start = index::MetaPos(self.data_len + buf.len() as u64);
+                meta::write(&mut buf, ts.to_le_bytes(), self.payload_size)
+                    .expect("writing to a Vec never fails");
+                new_entries.push(index::Entry {
+                    timestamp: ts,
+                    meta_start,
+                });
+                last_meta_ts = Some(ts);
+                0 // value does not matter, full timestamp just ahead is used
+            };
+            buf.extend_from_slice(&small_ts.to_le_bytes());
+            buf.extend_from_slice(&line[..self.payload_size.raw()]);
+            last_time = Some(ts);
+            new_lines += 1;
+        }
+
+        if new_lines == 0 {
+            return Ok(());
+        }
+
+        self.write_lines(&buf, &new_entries, last_time)?;
+        self.pushed(new_lines)
+    }
+
+    /// Appends encoded lines and then adds their meta sections to the index
+    fn write_lines(
+        &mut self,
+        buf: &[u8],
+        new_entries: &[index::Entry],
+        last_time: Option<Timestamp>,
+    ) -> Result<(), PushError> {
+        self.file_handle.write_all(buf).map_err(PushError::Write)?;
+        let new_sections = new_entries.iter().map(|e| e.meta_start.raw_offset());
+        self.update_checksums(buf, new_sections)?;
+        self.data_len += buf.len() as u64;
+        self.last_time = last_time;
+        self.index.extend(new_entries).map_err(PushError::Index)
+    }
+
+    /// Call with the bytes appended to the data before `data_len` is
+    /// updated. `new_sections` are the starts of the meta sections in them.
+    fn update_checksums(
+        &mut self,
+        appended: &[u8],
+        new_sections: impl IntoIterator<Item = u64>,
+    ) -> Result<(), PushError> {
+        let Some(checksums) = &mut self.file_handle.checksums else {
+            return Ok(());
+        };
+        checksums
+            .extend(self.data_len, appended, new_sections)
+            .map_err(PushError::Checksums)
+    }
+
+    pub(crate) fn has_checksums(&self) -> bool {
+        self.file_handle.checksums.is_some()
+    }
+
+    fn series(&self) -> Series {
+        self.file_handle.series.clone()
+    }
+
+    /// Returns a reader with its own file handles. It sees everything
+    /// pushed to this before the read starts.
+    pub(crate) fn reader(&mut self) -> DataReader {
+        let published = match &self.published {
+            Some(published) => Arc::clone(published),
+            None => {
+                let published = Arc::new(RwLock::new(Published::new(self)));
+                self.published.insert(published).clone()
+            }
+        };
+        DataReader::new(
+            self.path.clone(),
+            self.payload_size,
+            self.file_handle.file_handle.is_mapped(),
+            self.file_handle.file_handle.encoding(),
+            self.file_handle.series.clone(),
+            published,
+        )
+    }
+
+    /// True while a reader from [`reader`](Self::reader), or a clone of
+    /// one, exists. Creating a reader needs `&mut self`, so this does not
+    /// change while we hold that.
+    fn has_readers(&self) -> bool {
+        self.published
+            .as_ref()
+            .is_some_and(|published| Arc::strong_count(published) > 1)
+    }
+
+    /// Length of the data readers can see
+    fn published_len(&self) -> u64 {
+        self.published.as_ref().map_or(0, |published| {
+            published
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .data_len()
+        })
+    }
+
+    /// Makes appended lines visible to readers
+    fn publish(&self) {
+        if let Some(published) = &self.published {
+            published
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .update(self);
+        }
+    }
+
+    /// Makes changes other then appending lines visible to readers
+    fn publish_replaced(&self) {
+        if let Some(published) = &self.published {
+            published
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .replace(self);
+        }
+    }
+
+    /// Serve all further reads from a memory map of the data
+    pub(crate) fn map_reads(&mut self) -> std::io::Result<()> {
+        self.file_handle.file_handle.map_reads()
+    }
+
     /// asks the OS to write its buffers and block till its done
     pub(crate) fn flush_to_disk(&mut self) -> std::io::Result<()> {
+        self.commit()?;
         self.file_handle.inner_mut().sync_data()?;
-        self.index.file.sync_data()?;
+        self.index.sync_data()?;
+        if let Some(checksums) = &self.file_handle.checksums {
+            checksums.sync_data()?;
+        }
         Ok(())
     }
 
@@ -283,13 +755,13 @@ impl Data {
     ///
     /// See the [`ReadError`] docs for an exhaustive list of everything that can go wrong.
     pub(crate) fn read_all<D: Decoder>(
-        &mut self,
+        &self,
         seek: Pos,
         corruption_callback: &mut Option<CorruptionCallback>,
         decoder: &mut D,
         timestamps: &mut Vec<Timestamp>,
         data: &mut Vec<D::Item>,
-    ) -> Result<(), ReadError> {
+    ) -> Result<Skipped, ReadError> {
         self.file_handle
             .read(decoder, timestamps, data, seek, corruption_callback)
     }
@@ -298,14 +770,14 @@ impl Data {
     ///
     /// See the [`ReadError`] docs for an exhaustive list of everything that can go wrong.
     pub(crate) fn read_first_n<D: Decoder>(
-        &mut self,
+        &self,
         n: usize,
         seek: Pos,
         corruption_callback: &mut Option<CorruptionCallback>,
         decoder: &mut D,
         timestamps: &mut Vec<Timestamp>,
         data: &mut Vec<D::Item>,
-    ) -> Result<(), ReadError> {
+    ) -> Result<Skipped, ReadError> {
         self.file_handle.read_first_n(
             n,
             decoder,
@@ -318,14 +790,14 @@ impl Data {
 
     #[instrument(skip(self, resampler, timestamps, data, corruption_callback), err)]
     pub(crate) fn read_resampling<R: crate::Resampler>(
-        &mut self,
+        &self,
         seek: Pos,
         corruption_callback: &mut Option<CorruptionCallback>,
         resampler: &mut R,
         bucket_size: usize,
         timestamps: &mut Vec<u64>,
         data: &mut Vec<<R as Decoder>::Item>,
-    ) -> Result<(), ReadError> {
+    ) -> Result<Skipped, ReadError> {
         self.file_handle.read_resampling(
             resampler,
             bucket_size,
@@ -340,6 +812,45 @@ impl Data {
         self.payload_size
     }
 
+    pub(crate) fn last_payload(&self) -> Result<Vec<u8>, ReadError> {
+        if self.is_empty() {
+            return Err(ReadError::NoData);
+        }
+        let mut payload = vec![0; self.payload_size.raw()];
+        self.file_handle
+            .file_handle
+            .read_exact_at(&mut payload, self.last_line_start().raw_offset() + 2)
+            .map_err(ReadError::Io)?;
+        Ok(payload)
+    }
+
+    /// Replaces the payload of the last line, its timestamp stays the same.
+    /// The downsampled caches are not updated.
+    ///
+    /// Returns false and keeps the line if readers exist and the line was
+    /// published to them. They could be reading the bytes we would change.
+    pub(crate) fn overwrite_last_payload(
+        &mut self,
+        payload: &[u8],
+    ) -> Result<bool, PushError> {
+        assert!(!self.is_empty(), "there must be a last line");
+        let offset = self.last_line_start().raw_offset() + 2;
+        if self.has_readers() && offset < self.published_len() {
+            warn!("readers can see the last line, keeping it instead of its duplicate");
+            return Ok(false);
+        }
+        self.file_handle
+            .file_handle
+            .write_all_at(&payload[..self.payload_size.raw()], offset)
+            .map_err(PushError::Write)?;
+        if let Some(checksums) = &mut self.file_handle.checksums {
+            checksums
+                .rehash_open(&self.file_handle.file_handle, self.data_len)
+                .map_err(PushError::Checksums)?;
+        }
+        Ok(true)
+    }
+
     pub(crate) fn last_line_start(&self) -> LinePos {
         // any metasection is written at the
         // same time and before a line. (they are 'atomic')
@@ -350,13 +861,512 @@ impl Data {
         self.data_len == 0
     }
 
-    pub(crate) fn clear(&mut self) -> Result<(), std::io::Error> {
-        self.file_handle.file_handle.set_len(0)?;
-        self.index.clear()?;
+    /// Removes all lines. If readers exist the files are replaced by empty
+    /// ones instead, see [`rewrite`](Self::rewrite).
+    pub(crate) fn clear(&mut self) -> Result<(), ClearError> {
+        if self.has_readers() {
+            let previous = self.last_time.take();
+            return self.rewrite(self.index.len(), self.data_len).map_err(|e| {
+                self.last_time = previous;
+                ClearError::Rewrite(e)
+            });
+        }
+
+        self.file_handle
+            .file_handle
+            .set_len(0)
+            .map_err(ClearError::Io)?;
+        self.index.clear().map_err(ClearError::Io)?;
+        if let Some(checksums) = &mut self.file_handle.checksums {
+            checksums.clear().map_err(ClearError::Io)?;
+        }
         self.data_len = 0;
+        self.last_time = None;
+        if let Some(buffering) = &mut self.buffering {
+            buffering.pending_lines = 0;
+            buffering.oldest_pending = None;
+        }
+        self.publish_replaced();
         Ok(())
     }
 
+    /// Removes all lines with a timestamp after `ts`. The files are
+    /// shortened in place unless readers exist, those may have them memory
+    /// mapped. Then the kept lines are copied to new files, see
+    /// [`rewrite`](Self::rewrite).
+    pub(crate) fn truncate_after(
+        &mut self,
+        ts: Timestamp,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<(), TruncateError> {
+        match self.last_time {
+            Some(last_time) if last_time > ts => (),
+            _ => return Ok(()),
+        }
+
+        let pos = match RoughPos::new(self, Bound::Unbounded, Bound::Included(ts)) {
+            Ok(pos) => pos.refine(self).map_err(TruncateError::Seek)?,
+            Err(seek::Error::StopBeforeData) => None,
+            Err(other) => return Err(TruncateError::Seek(other)),
+        };
+        let Some(pos) = pos else {
+            return self.clear().map_err(TruncateError::Clear);
+        };
+
+        let kept = self.index.last_section_before(pos.end).map_or(0, |i| i + 1);
+        let (last_time, _) = last_line(
+            &Index::in_memory(self.index.entries()[..kept].to_vec()),
+            pos.end,
+            self.payload_size,
+            &self.file_handle,
+            &mut EmptyDecoder,
+            corruption_callback,
+        )
+        .map_err(TruncateError::ReadLastTime)?;
+
+        if self.has_readers() {
+            // readers may have mapped the bytes we would cut off. The last
+            // time is published together with the new files.
+            let previous = self.last_time.replace(last_time);
+            return self.rewrite(0, pos.end).map_err(|e| {
+                self.last_time = previous;
+                TruncateError::Rewrite(e)
+            });
+        }
+
+        // data first, on open an index pointing past the data is repaired
+        self.file_handle
+            .file_handle
+            .set_len(pos.end)
+            .map_err(TruncateError::Io)?;
+        self.index.truncate(pos.end).map_err(TruncateError::Io)?;
+        if let Some(checksums) = &mut self.file_handle.checksums {
+            checksums
+                .truncate(&self.file_handle.file_handle, self.index.entries(), pos.end)
+                .map_err(TruncateError::Io)?;
+        }
+        self.data_len = pos.end;
+        self.last_time = Some(last_time);
+        self.publish_replaced();
+        Ok(())
+    }
+
+    /// Removes the meta sections before the one holding the first line at
+    /// or after `ts`. Lines in the same meta section as that line are kept
+    /// even if they are older then `ts`.
+    ///
+    /// The kept lines are copied to a new data file and index, see
+    /// [`rewrite`](Self::rewrite).
+    pub(crate) fn drop_before(&mut self, ts: Timestamp) -> Result<(), DropError> {
+        match self.last_time {
+            Some(last_time) if last_time >= ts => (),
+            Some(_) => return self.clear().map_err(DropError::Clear),
+            None => return Ok(()),
+        }
+
+        let pos = RoughPos::new(self, Bound::Included(ts), Bound::Unbounded)
+            .map_err(DropError::Seek)?
+            .refine(self)
+            .map_err(DropError::Seek)?
+            .expect("the last line is at or after ts");
+        let first_kept = self
+            .index
+            .last_section_before(pos.start.raw_offset())
+            .expect("every line comes after a meta section");
+        if first_kept == 0 {
+            return Ok(());
+        }
+
+        self.rewrite(first_kept, self.data_len)
+            .map_err(DropError::Rewrite)
+    }
+
+    /// Replaces the files with ones holding only the meta sections from
+    /// `first_kept` on and only their bytes before `end`. The kept bytes
+    /// are copied to `.part` files that are then moved in place, see
+    /// [`replace_with_parts`](Self::replace_with_parts). Readers keep
+    /// reading the old files until they open the new ones.
+    fn rewrite(&mut self, first_kept: usize, end: u64) -> Result<(), RewriteError> {
+        self.commit().map_err(RewriteError::Seal)?;
+        self.seal_for_replace().map_err(RewriteError::Seal)?;
+        let (mut part, mut index, _) = self.new_parts().map_err(RewriteError::Create)?;
+        let kept = &self.index.entries()[first_kept..];
+        let kept =
+            &kept[..kept.partition_point(|entry| entry.meta_start.raw_offset() < end)];
+        let dropped = kept
+            .first()
+            .map_or(end, |entry| entry.meta_start.raw_offset());
+        // compressed blocks must start at a meta section, so copy those
+        // one by one
+        let sections: Vec<u64> = if part.unsealed_len().is_some() {
+            kept.iter()
+                .map(|entry| entry.meta_start.raw_offset())
+                .collect()
+        } else {
+            kept.first()
+                .map(|entry| entry.meta_start.raw_offset())
+                .into_iter()
+                .collect()
+        };
+        let mut buf = vec![0; 1 << 16];
+        for (i, start) in sections.iter().enumerate() {
+            let section_end = sections.get(i + 1).copied().unwrap_or(end);
+            if part.block_full() {
+                part.seal_block().map_err(RewriteError::Copy)?;
+            }
+            let mut offset = *start;
+            while offset < section_end {
+                let chunk = buf.len().min((section_end - offset) as usize);
+                self.file_handle
+                    .file_handle
+                    .read_exact_at(&mut buf[..chunk], offset)
+                    .map_err(RewriteError::Copy)?;
+                part.write_all(&buf[..chunk]).map_err(RewriteError::Copy)?;
+                offset += chunk as u64;
+            }
+        }
+        part.seal_block().map_err(RewriteError::Copy)?;
+        part.sync_data().map_err(RewriteError::Copy)?;
+        index
+            .extend(&self.index.entries_rebased(first_kept, end))
+            .map_err(RewriteError::Copy)?;
+        index.sync_data().map_err(RewriteError::Copy)?;
+        if let Some(checksums) = &self.file_handle.checksums {
+            checksums
+                .write_part(&self.path, first_kept, dropped, end)
+                .map_err(RewriteError::Copy)?;
+        }
+
+        self.replace_with_parts(index, end - dropped)
+            .map_err(RewriteError::Replace)
+    }
+
+    /// Copies all lines to a new series at `name` that only has the meta
+    /// sections it needs. Lines that are not after the line before them are
+    /// left out, returns how many.
+    pub(crate) fn compact_to(
+        &self,
+        name: &Path,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<(Data, u64), CompactError> {
+        let header = self.header().map_err(CompactError::Create)?;
+        let (file, _) = FileWithHeader::new(name.with_extension("byteseries"), &header)
+            .map_err(CompactError::Create)?
+            .split_off_header();
+        let file = file
+            .create_compressed(self.encoding())
+            .map_err(|e| CompactError::Create(e.into()))?;
+        let index = Index::new(name).map_err(CompactError::Create)?;
+        let checksums = self
+            .has_checksums()
+            .then(|| Checksums::create(checksums::path(name)))
+            .transpose()
+            .map_err(|e| CompactError::Create(e.into()))?;
+        let mut compacted = Data::from_parts(
+            file,
+            index,
+            checksums,
+            self.payload_size,
+            name,
+            self.series(),
+        );
+        let out_of_order =
+            self.copy_lines_to(&mut compacted, corruption_callback, &mut copy_payload)?;
+        compacted.flush_to_disk().map_err(CompactError::Sync)?;
+        Ok((compacted, out_of_order))
+    }
+
+    /// Rewrites the data so it only has the meta sections it needs. Lines
+    /// that are not after the line before them are left out, returns how
+    /// many.
+    ///
+    /// The new data file and index are written as `.part` files and then
+    /// moved in place, see [`replace_with_parts`](Self::replace_with_parts).
+    pub(crate) fn compact(
+        &mut self,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<u64, CompactError> {
+        self.commit().map_err(CompactError::Sync)?;
+        self.seal_for_replace().map_err(CompactError::Sync)?;
+        let (file, index, checksums) = self.new_parts().map_err(CompactError::Create)?;
+        let mut compacted = Data::from_parts(
+            file,
+            index,
+            checksums,
+            self.payload_size,
+            &self.path,
+            self.series(),
+        );
+        let out_of_order =
+            self.copy_lines_to(&mut compacted, corruption_callback, &mut copy_payload)?;
+        compacted
+            .file_handle
+            .file_handle
+            .seal_block()
+            .map_err(CompactError::Sync)?;
+        compacted.flush_to_disk().map_err(CompactError::Sync)?;
+
+        self.replace_with_parts(compacted.index, compacted.data_len)
+            .map_err(CompactError::Replace)?;
+        Ok(out_of_order)
+    }
+
+    /// Writes every line through `transform` into `.part` files with lines
+    /// of `payload_size`. The `.part` files are returned, once the series
+    /// is closed [`move_parts_in_place`] replaces the current files with
+    /// them. `header` is the complete header for the new data file.
+    ///
+    /// The lines not yet compressed are compressed first, the tail file is
+    /// then empty and can be replaced before the data file.
+    pub(crate) fn migrate_to_parts(
+        &mut self,
+        payload_size: PayloadSize,
+        header: &[u8],
+        encoding: Encoding,
+        transform: &mut dyn FnMut(&[u8]) -> Vec<u8>,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<(Data, u64), CompactError> {
+        self.commit().map_err(CompactError::Sync)?;
+        self.seal_for_replace().map_err(CompactError::Sync)?;
+        let (file, index, checksums) = self
+            .new_parts_with(header, encoding)
+            .map_err(CompactError::Create)?;
+        let mut migrated = Data::from_parts(
+            file,
+            index,
+            checksums,
+            payload_size,
+            &self.path,
+            self.series(),
+        );
+        let mut transform = |payload: &[u8], out: &mut Vec<u8>| {
+            let migrated = transform(payload);
+            if migrated.len() != payload_size.raw() {
+                return Err(CompactError::MigratedLength {
+                    required: payload_size.raw(),
+                    got: migrated.len(),
+                });
+            }
+            out.extend_from_slice(&migrated);
+            Ok(())
+        };
+        let out_of_order =
+            self.copy_lines_to(&mut migrated, corruption_callback, &mut transform)?;
+        migrated
+            .file_handle
+            .file_handle
+            .seal_block()
+            .map_err(CompactError::Sync)?;
+        migrated.flush_to_disk().map_err(CompactError::Sync)?;
+        Ok((migrated, out_of_order))
+    }
+
+    /// Pushes every line to `compacted` in batches, `copy` appends the
+    /// payload to write. Returns the number of lines left out as they are
+    /// not after the line before them.
+    fn copy_lines_to(
+        &self,
+        compacted: &mut Data,
+        corruption_callback: &mut Option<CorruptionCallback>,
+        copy: &mut CopyPayload,
+    ) -> Result<u64, CompactError> {
+        const BATCH: usize = 4096;
+
+        let Some(first_full_ts) = self.first_meta_timestamp() else {
+            return Ok(0);
+        };
+        let seek = Pos {
+            start: index::MetaPos::ZERO.line_start(self.payload_size),
+            end: self.data_len,
+            first_full_ts,
+        };
+
+        let line_len = compacted.payload_size.raw();
+        let mut timestamps = Vec::with_capacity(BATCH);
+        let mut payloads = Vec::with_capacity(BATCH * line_len);
+        let mut last = None;
+        let mut out_of_order = 0;
+        let mut push_batch = |timestamps: &mut Vec<Timestamp>, payloads: &mut Vec<u8>| {
+            let lines = timestamps
+                .iter()
+                .enumerate()
+                .map(|(i, ts)| (*ts, &payloads[i * line_len..(i + 1) * line_len]));
+            let res = compacted.push_lines(lines);
+            timestamps.clear();
+            payloads.clear();
+            res
+        };
+
+        let mut corruption =
+            corruption::Handler::new(corruption_callback, &self.file_handle.series);
+        let res =
+            self.file_handle
+                .read_with_processor(seek, &mut corruption, |ts, payload| {
+                    if last.is_some_and(|last| ts <= last) {
+                        out_of_order += 1;
+                        return Ok(());
+                    }
+                    last = Some(ts);
+                    timestamps.push(ts);
+                    copy(payload, &mut payloads)?;
+                    if timestamps.len() >= BATCH {
+                        push_batch(&mut timestamps, &mut payloads)
+                            .map_err(CompactError::Write)?;
+                    }
+                    Ok(())
+                });
+        match res {
+            Ok(()) => (),
+            Err(inline_meta::with_processor::Error::Io(e)) => {
+                return Err(CompactError::Read(e))
+            }
+            Err(inline_meta::with_processor::Error::Processor(e)) => return Err(e),
+            Err(inline_meta::with_processor::Error::CorruptMetaSection) => {
+                return Err(CompactError::CorruptMetaSection)
+            }
+            Err(inline_meta::with_processor::Error::ChecksumMismatch {
+                section_start,
+            }) => return Err(CompactError::ChecksumMismatch { section_start }),
+        }
+        push_batch(&mut timestamps, &mut payloads).map_err(CompactError::Write)?;
+        Ok(out_of_order)
+    }
+
+    /// An empty data file with the same header, an empty index and if
+    /// this has checksums empty checksums. All are `.part` files, see
+    /// [`replace_with_parts`](Self::replace_with_parts).
+    fn new_parts(&self) -> Result<PartFiles, file::OpenError> {
+        self.new_parts_with(&self.header()?, self.encoding())
+    }
+
+    fn new_parts_with(
+        &self,
+        header: &[u8],
+        encoding: Encoding,
+    ) -> Result<PartFiles, file::OpenError> {
+        let part_path = self.path.with_extension("byteseries.part");
+        let index_part_path = Index::part_path(&self.path);
+        let checksums_part_path = checksums::part_path(&self.path);
+        // left behind by an earlier rewrite that crashed
+        remove_if_exists(&part_path)?;
+        remove_if_exists(&index_part_path)?;
+        remove_if_exists(&checksums_part_path)?;
+
+        let (file, _) = FileWithHeader::new(&part_path, header)?.split_off_header();
+        let file = file.create_compressed(encoding)?;
+        let index = Index::new_part(&self.path)?;
+        let checksums = self
+            .has_checksums()
+            .then(|| Checksums::create(checksums_part_path))
+            .transpose()?;
+        Ok((file, index, checksums))
+    }
+
+    pub(crate) fn encoding(&self) -> Encoding {
+        self.file_handle.file_handle.encoding()
+    }
+
+    /// Compresses the lines that are not yet. Then the data is only in the
+    /// data file, which can safely be replaced by a part file, see
+    /// [`replace_with_parts`](Self::replace_with_parts).
+    fn seal_for_replace(&mut self) -> std::io::Result<()> {
+        self.file_handle.file_handle.seal_block()
+    }
+
+    /// The complete header of the data file
+    fn header(&self) -> Result<Vec<u8>, file::OpenError> {
+        let data_path = self.path.with_extension("byteseries");
+        Ok(FileWithHeader::open_existing_read_only(data_path)?.header)
+    }
+
+    fn from_parts(
+        file: OffsetFile,
+        index: Index,
+        checksums: Option<Checksums>,
+        payload_size: PayloadSize,
+        name: &Path,
+        series: Series,
+    ) -> Data {
+        Data {
+            file_handle: FileWithInlineMeta {
+                file_handle: file,
+                payload_size,
+                checksums,
+                series,
+            },
+            index,
+            payload_size,
+            data_len: 0,
+            last_time: None,
+            path: name.to_path_buf(),
+            published: None,
+            buffering: None,
+        }
+    }
+
+    /// Moves the `.part` data file from [`new_parts`](Self::new_parts) in
+    /// place and then `index`, which must be the part index. Both must have
+    /// been flushed to disk, see [`move_parts_in_place`].
+    fn replace_with_parts(
+        &mut self,
+        index: Index,
+        data_len: u64,
+    ) -> Result<(), ReplaceError> {
+        // readers must not open the new files before the new entries are
+        // published
+        let published = self.published.clone();
+        let mut published = published
+            .as_deref()
+            .map(|published| published.write().unwrap_or_else(PoisonError::into_inner));
+
+        let compressed = !self.encoding().is_none();
+        move_parts_in_place(&self.path, compressed).map_err(ReplaceError::Move)?;
+
+        let data_path = self.path.with_extension("byteseries");
+        let (file, _) = FileWithHeader::open_existing(data_path)
+            .map_err(ReplaceError::Reopen)?
+            .split_off_header();
+        let mut file = file
+            .open_compressed(self.encoding(), true)
+            .map_err(ReplaceError::Configure)?;
+        let mut index = index;
+        if self.file_handle.file_handle.is_mapped() {
+            file.map_reads().map_err(ReplaceError::Configure)?;
+        }
+        if self.buffering.is_some() {
+            file.buffer_writes().map_err(ReplaceError::Configure)?;
+            index.buffer_writes().map_err(ReplaceError::Configure)?;
+        }
+        if self.has_checksums() {
+            let checksums = Checksums::open(
+                checksums::path(&self.path),
+                &file,
+                index.entries(),
+                data_len,
+            )
+            .map_err(ReplaceError::Checksums)?;
+            self.file_handle.checksums = Some(checksums);
+        }
+        self.file_handle.file_handle = file;
+        self.index = index;
+        self.data_len = data_len;
+
+        if let Some(published) = &mut published {
+            published.replace(self);
+        }
+        Ok(())
+    }
+
+    /// Bytes used by the data and index, their headers are not counted
+    pub(crate) fn size_on_disk(&self) -> u64 {
+        let data = self
+            .file_handle
+            .file_handle
+            .compressed_len()
+            .unwrap_or(self.data_len);
+        data + self.index.len() as u64 * 16
+    }
+
     /// number of entries/samples/pushed lines in the file.
     pub(crate) fn len(&self) -> u64 {
         let lines = self.data_len / self.payload_size().line_size() as u64;
@@ -367,12 +1377,90 @@ impl Data {
     }
 }
 
+/// We store the timestamp minus the last recorded full timestamp as u16. If
+/// that overflows a new full timestamp needs to be inserted, then this
+/// returns None. The 16 bit small timestamp is stored little endian.
+fn small_ts(
+    last_meta_ts: Option<Timestamp>,
+    ts: Timestamp,
+) -> Result<Option<u16>, PushError> {
+    Ok(last_meta_ts
+        .map(|last_timestamp| {
+            ts.checked_sub(last_timestamp).ok_or(PushError::OutOfOrder {
+                last: last_timestamp,
+                item: ts,
+            })
+        })
+        .transpose()?
+        .and_then(|diff| {
+            if diff > MAX_SMALL_TS {
+                None
+            } else {
+                Some(u16::try_from(diff).expect("MAX_SMALL_TS < u16::MAX"))
+            }
+        }))
+}
+
+/// Moves the `.part` data file and index in place of those of the series at
+/// `name`. The old index is removed first, if we crash before the new one is
+/// in place it is created again from the data on open. The same goes for the
+/// checksums, those are moved last if there is a part for them.
+///
+/// If `compressed` the tail files of both the part and the series must be
+/// empty, see [`seal_for_replace`](Data::seal_for_replace). The tail is
+/// moved before the data file. If we crash in between the old data and the
+/// new tail still form the old series, the data file is only replaced once
+/// nothing else of the old data is needed. The block table is removed
+/// first and moved after the data, without it the blocks are found by
+/// scanning the data.
+pub(crate) fn move_parts_in_place(name: &Path, compressed: bool) -> std::io::Result<()> {
+    let data_path = name.with_extension("byteseries");
+    let index_path = name.with_extension("byteseries_index");
+    let part_path = name.with_extension("byteseries.part");
+    let checksums_path = checksums::path(name);
+    let checksums_part_path = checksums::part_path(name);
+
+    remove_if_exists(&index_path)?;
+    remove_if_exists(&checksums_path)?;
+    if compressed {
+        remove_if_exists(&file::table_path(&data_path))?;
+        std::fs::rename(file::tail_path(&part_path), file::tail_path(&data_path))?;
+    }
+    std::fs::rename(&part_path, &data_path)?;
+    if compressed {
+        std::fs::rename(file::table_path(&part_path), file::table_path(&data_path))?;
+    }
+    std::fs::rename(Index::part_path(name), &index_path)?;
+    if checksums_part_path.exists() {
+        std::fs::rename(checksums_part_path, checksums_path)?;
+    }
+    file::sync_dir_of(&data_path)
+}
+
+/// The `.part` data file, index and checksums of a rewrite
+type PartFiles = (OffsetFile, Index, Option<Checksums>);
+
+/// Appends the payload to write for a line read while compacting
+type CopyPayload<'a> = dyn FnMut(&[u8], &mut Vec<u8>) -> Result<(), CompactError> + 'a;
+
+fn copy_payload(payload: &[u8], out: &mut Vec<u8>) -> Result<(), CompactError> {
+    out.extend_from_slice(payload);
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
 // not member of Data since we need it for Data's initialization
 fn last_line<T>(
     index: &Index,
     data_len: u64,
     payload_size: PayloadSize,
-    file_handle: &mut FileWithInlineMeta<OffsetFile>,
+    file_handle: &FileWithInlineMeta<OffsetFile>,
     decoder: &mut impl Decoder<Item = T>,
     corruption_callback: &mut Option<CorruptionCallback>,
 ) -> Result<(Timestamp, T), ReadError> {
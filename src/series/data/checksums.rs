@@ -0,0 +1,346 @@
+//! Optional crc32 checksums, one for each meta section together with the
+//! lines up to the next meta section. They are kept in the
+//! `.byteseries_checksums` file as the start of the section (u64) followed
+//! by its checksum (u32), both little endian.
+//!
+//! A section only gets its checksum once the next one starts. Until then
+//! lines are still appended to it. That last section is checksummed again
+//! from the data every time the series is opened.
+//!
+//! Readers get the checksums of the sections the writer published, they
+//! only check and never write them. A series opened read only uses the
+//! stored checksums the same way.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crc32fast::Hasher;
+use tracing::warn;
+
+use super::index::Entry;
+use super::inline_meta::ReadAt;
+
+const ENTRY_SIZE: usize = 12;
+
+/// A meta section and the lines after it that got its checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Section {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    pub(crate) crc: u32,
+}
+
+impl Section {
+    pub(crate) fn range(&self) -> Range<u64> {
+        self.start..self.end
+    }
+}
+
+pub(crate) struct Checksums {
+    /// None for readers
+    file: Option<File>,
+    path: PathBuf,
+    /// every section but the last, shared with readers
+    sections: Arc<Vec<Section>>,
+    /// start of the last section, the one lines are appended to
+    open_start: Option<u64>,
+    /// checksum of the last section so far
+    open: Hasher,
+}
+
+impl std::fmt::Debug for Checksums {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Checksums")
+            .field("path", &self.path)
+            .field("# sections", &self.sections.len())
+            .field("open_start", &self.open_start)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) fn path(name: &Path) -> PathBuf {
+    name.with_extension("byteseries_checksums")
+}
+
+pub(crate) fn part_path(name: &Path) -> PathBuf {
+    name.with_extension("byteseries_checksums.part")
+}
+
+impl Checksums {
+    /// For a new series, replaces any existing checksum file
+    pub(crate) fn create(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(Self {
+            file: Some(file),
+            path,
+            sections: Arc::default(),
+            open_start: None,
+            open: Hasher::new(),
+        })
+    }
+
+    /// For readers, only the `sections` are checked. The last section is
+    /// never checked as lines can still be appended to it.
+    pub(crate) fn published(path: PathBuf, sections: Arc<Vec<Section>>) -> Self {
+        Self {
+            file: None,
+            path,
+            sections,
+            open_start: None,
+            open: Hasher::new(),
+        }
+    }
+
+    /// For a series opened read only. Only the sections in `entries` with a
+    /// stored checksum are checked, nothing is calculated or written.
+    pub(crate) fn read_only(name: &Path, entries: &[Entry]) -> io::Result<Self> {
+        let starts: Vec<_> = entries.iter().map(|e| e.meta_start.raw_offset()).collect();
+        let sections = matching_stored(&starts, &read(name)?);
+        Ok(Self::published(path(name), Arc::new(sections)))
+    }
+
+    /// The sections that have a checksum, without copying them
+    pub(crate) fn shared_sections(&self) -> Arc<Vec<Section>> {
+        Arc::clone(&self.sections)
+    }
+
+    /// Reads the checksums and brings them in line with the data. Checksums
+    /// of sections that no longer exist are removed. Sections without a
+    /// checksum, for example because we crashed before it was written, get
+    /// one calculated from the data.
+    pub(crate) fn open(
+        path: PathBuf,
+        data: &impl ReadAt,
+        entries: &[Entry],
+        data_len: u64,
+    ) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let stored = read_stored(&mut file)?;
+
+        let mut checksums = Self {
+            file: Some(file),
+            path,
+            sections: Arc::default(),
+            open_start: None,
+            open: Hasher::new(),
+        };
+        checksums.sync(data, entries, data_len, &stored)?;
+        Ok(checksums)
+    }
+
+    /// Call after the data got truncated
+    pub(crate) fn truncate(
+        &mut self,
+        data: &impl ReadAt,
+        entries: &[Entry],
+        data_len: u64,
+    ) -> io::Result<()> {
+        let stored: Vec<_> = self.sections.iter().map(|s| (s.start, s.crc)).collect();
+        self.sync(data, entries, data_len, &stored)
+    }
+
+    fn sync(
+        &mut self,
+        data: &impl ReadAt,
+        entries: &[Entry],
+        data_len: u64,
+        stored: &[(u64, u32)],
+    ) -> io::Result<()> {
+        let starts: Vec<_> = entries.iter().map(|e| e.meta_start.raw_offset()).collect();
+        let mut sections = matching_stored(&starts, stored);
+        let kept = sections.len();
+        let closed = starts.len().saturating_sub(1);
+        if kept < closed {
+            warn!(
+                "{} meta sections have no checksum, calculating them from the data",
+                closed - kept
+            );
+        }
+        for section in starts.windows(2).skip(kept) {
+            let crc = crc_of(data, section[0]..section[1])?;
+            sections.push(Section {
+                start: section[0],
+                end: section[1],
+                crc,
+            });
+        }
+        self.sections = Arc::new(sections);
+        self.write_entries(kept)?;
+
+        self.open_start = starts.last().copied();
+        self.open = Hasher::new();
+        if let Some(start) = self.open_start {
+            data.with_bytes_at(start..data_len, |bytes| self.open.update(bytes))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the sections from `from` on to the file, anything after them
+    /// is removed
+    fn write_entries(&mut self, from: usize) -> io::Result<()> {
+        let offset = (from * ENTRY_SIZE) as u64;
+        let encoded = encode(&self.sections[from..], 0);
+        let file = self.file_mut();
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&encoded)
+    }
+
+    fn file_mut(&mut self) -> &mut File {
+        self.file
+            .as_mut()
+            .expect("only writers change the checksums, they have the file")
+    }
+
+    /// Call for all bytes appended to the data. The data before `bytes` is
+    /// `data_len` long. `new_sections` are the starts of the meta sections
+    /// in `bytes`.
+    pub(crate) fn extend(
+        &mut self,
+        data_len: u64,
+        bytes: &[u8],
+        new_sections: impl IntoIterator<Item = u64>,
+    ) -> io::Result<()> {
+        let mut hashed = 0;
+        for start in new_sections {
+            let split = usize::try_from(start - data_len).expect("lies in bytes");
+            self.open.update(&bytes[hashed..split]);
+            hashed = split;
+            self.close_open(start)?;
+        }
+        self.open.update(&bytes[hashed..]);
+        Ok(())
+    }
+
+    /// The section that lines were appended to ends where the new one
+    /// `starts`
+    fn close_open(&mut self, next_start: u64) -> io::Result<()> {
+        let hasher = std::mem::replace(&mut self.open, Hasher::new());
+        if let Some(start) = self.open_start.replace(next_start) {
+            Arc::make_mut(&mut self.sections).push(Section {
+                start,
+                end: next_start,
+                crc: hasher.finalize(),
+            });
+            self.write_entries(self.sections.len() - 1)?;
+        }
+        Ok(())
+    }
+
+    /// Call after bytes in the last section were changed
+    pub(crate) fn rehash_open(
+        &mut self,
+        data: &impl ReadAt,
+        data_len: u64,
+    ) -> io::Result<()> {
+        self.open = Hasher::new();
+        if let Some(start) = self.open_start {
+            data.with_bytes_at(start..data_len, |bytes| self.open.update(bytes))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn clear(&mut self) -> io::Result<()> {
+        self.file_mut().set_len(0)?;
+        self.sections = Arc::default();
+        self.open_start = None;
+        self.open = Hasher::new();
+        Ok(())
+    }
+
+    /// Writes the checksums of the sections from `first` on that end at or
+    /// before `end` to the part file of the series `name`. The sections are
+    /// moved forward by `dropped` bytes.
+    pub(crate) fn write_part(
+        &self,
+        name: &Path,
+        first: usize,
+        dropped: u64,
+        end: u64,
+    ) -> io::Result<()> {
+        let sections = self.sections.get(first..).unwrap_or_default();
+        let kept = sections.partition_point(|section| section.end <= end);
+        let mut part = File::create(part_path(name))?;
+        part.write_all(&encode(&sections[..kept], dropped))?;
+        part.sync_data()
+    }
+
+    /// The sections that have a checksum and overlap `range`
+    pub(crate) fn overlapping(&self, range: Range<u64>) -> &[Section] {
+        let first = self.sections.partition_point(|s| s.end <= range.start);
+        let end = self.sections.partition_point(|s| s.start < range.end);
+        &self.sections[first..end.max(first)]
+    }
+
+    pub(crate) fn sync_data(&self) -> io::Result<()> {
+        match &self.file {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The start and checksum of every section in the checksums file of the
+/// series `name`, without changing anything. Empty if there is no such file.
+pub(crate) fn read(name: &Path) -> io::Result<Vec<(u64, u32)>> {
+    match File::open(path(name)) {
+        Ok(mut file) => read_stored(&mut file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_stored(file: &mut File) -> io::Result<Vec<(u64, u32)>> {
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(ENTRY_SIZE)
+        .map(|entry| {
+            let start = entry[0..8].try_into().expect("chunk is 12 long");
+            let crc = entry[8..12].try_into().expect("chunk is 12 long");
+            (u64::from_le_bytes(start), u32::from_le_bytes(crc))
+        })
+        .collect())
+}
+
+/// The closed sections starting at `starts` for as long as their start
+/// matches that of the `stored` checksums
+fn matching_stored(starts: &[u64], stored: &[(u64, u32)]) -> Vec<Section> {
+    starts
+        .windows(2)
+        .zip(stored)
+        .take_while(|(section, (start, _))| section[0] == *start)
+        .map(|(section, (_, crc))| Section {
+            start: section[0],
+            end: section[1],
+            crc: *crc,
+        })
+        .collect()
+}
+
+fn crc_of(data: &impl ReadAt, range: Range<u64>) -> io::Result<u32> {
+    data.with_bytes_at(range, crc32fast::hash)
+}
+
+/// The sections as stored in the file, moved forward by `dropped` bytes
+fn encode(sections: &[Section], dropped: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(sections.len() * ENTRY_SIZE);
+    for section in sections {
+        buf.extend_from_slice(&(section.start - dropped).to_le_bytes());
+        buf.extend_from_slice(&section.crc.to_le_bytes());
+    }
+    buf
+}
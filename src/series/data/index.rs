@@ -1,7 +1,8 @@
 use core::fmt;
 use std::io::{Read, Seek, Write};
 use std::ops::Sub;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tracing::instrument;
 
 use crate::file::{self, FileWithHeader, OffsetFile};
@@ -76,7 +77,7 @@ impl PayloadSize {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub(crate) struct Entry {
     pub timestamp: Timestamp,
     /// the offset from the start where the meta section with the same timestamp
@@ -85,9 +86,12 @@ pub(crate) struct Entry {
 }
 
 pub(crate) struct Index {
-    pub(crate) file: OffsetFile,
+    /// None if the index only exists in memory, see [`Index::in_memory`]
+    file: Option<OffsetFile>,
 
-    entries: Vec<Entry>,
+    /// Shared with the readers, copied only when changed while a reader
+    /// still holds it.
+    entries: Arc<Vec<Entry>>,
     /// time for next point is 1 larger the this
     last_timestamp: Option<Timestamp>,
 }
@@ -154,9 +158,9 @@ impl Index {
             FileWithHeader::new(name.as_ref().with_extension("byteseries_index"), &[])?;
 
         Ok(Index {
-            file: file.split_off_header().0,
+            file: Some(file.split_off_header().0),
 
-            entries: Vec::new(),
+            entries: Arc::default(),
             last_timestamp: None,
         })
     }
@@ -177,33 +181,102 @@ impl Index {
         file.seek(std::io::SeekFrom::Start(0))
             .map_err(OpenError::Reading)?;
         file.read_to_end(&mut bytes).map_err(OpenError::Reading)?;
-
-        let entries: Vec<_> = bytes
-            .chunks_exact(16)
-            .map(|line| {
-                let timestamp: [u8; 8] =
-                    line[0..8].try_into().expect("line is 2*8 bytes");
-                let timestamp = u64::from_le_bytes(timestamp);
-                let line_start: [u8; 8] =
-                    line[8..].try_into().expect("line is 2*8 bytes");
-                let line_start = u64::from_le_bytes(line_start);
-                Entry {
-                    timestamp,
-                    meta_start: MetaPos(line_start),
-                }
-            })
-            .collect();
+        let entries = parse_entries(&bytes);
 
         Ok(Index {
-            file,
+            file: Some(file),
             last_timestamp: entries
                 .last()
                 .map(|Entry { timestamp, .. }| timestamp)
                 .copied(),
-            entries,
+            entries: Arc::new(entries),
         })
     }
 
+    /// Reads the index without changing it. The entries are checked like
+    /// [`check_and_repair`] would. Instead of truncating the file an entry
+    /// past the end of the data is left out.
+    #[instrument]
+    pub(crate) fn open_in_memory(
+        name: impl AsRef<Path> + fmt::Debug,
+        last_line_in_data_start: Option<u64>,
+        last_full_ts_in_data: Option<Timestamp>,
+    ) -> Result<Index, OpenError> {
+        let file = FileWithHeader::open_existing_read_only(
+            name.as_ref().with_extension("byteseries_index"),
+        )
+        .map_err(OpenError::File)?;
+        let (mut file, _) = file.split_off_header();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(OpenError::Reading)?;
+        let mut entries = parse_entries(&bytes);
+
+        let Some(last_line_in_data_start) = last_line_in_data_start else {
+            return Ok(Index::in_memory(Vec::new()));
+        };
+        if entries
+            .last()
+            .is_some_and(|last| last.meta_start.raw_offset() > last_line_in_data_start)
+        {
+            entries.pop();
+        }
+
+        let last_full_ts_in_data = last_full_ts_in_data.expect(
+            "last time in the data since last_line_in_data_start is not None \
+             so there is at least one time in the data",
+        );
+        match entries.last() {
+            None => Err(CheckAndRepairError::Empty.into()),
+            Some(last) if last.timestamp != last_full_ts_in_data => {
+                Err(CheckAndRepairError::IndexLastTimeMismatch {
+                    last_ts_in_index: last.timestamp,
+                    last_ts_in_data: last_full_ts_in_data,
+                }
+                .into())
+            }
+            Some(_) => Ok(Index::in_memory(entries)),
+        }
+    }
+
+    /// An index without a file, it can not be written to. Used by readers,
+    /// they add entries using [`Index::sync_entries`].
+    pub(crate) fn in_memory(entries: Vec<Entry>) -> Index {
+        Index {
+            file: None,
+            last_timestamp: entries.last().map(|entry| entry.timestamp),
+            entries: Arc::new(entries),
+        }
+    }
+
+    fn file_mut(&mut self) -> Result<&mut OffsetFile, std::io::Error> {
+        self.file
+            .as_mut()
+            .ok_or_else(|| std::io::Error::other("the index only exists in memory"))
+    }
+
+    pub(crate) fn buffer_writes(&mut self) -> Result<(), std::io::Error> {
+        self.file_mut()?.buffer_writes()
+    }
+
+    pub(crate) fn commit(&mut self) -> Result<(), std::io::Error> {
+        self.file_mut()?.commit()
+    }
+
+    /// Does nothing if the index only exists in memory
+    pub(crate) fn sync_data(&self) -> Result<(), std::io::Error> {
+        match &self.file {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes the in memory entries equal to `entries` without touching the
+    /// file or copying the entries.
+    pub(crate) fn sync_entries(&mut self, entries: &Arc<Vec<Entry>>) {
+        self.entries = Arc::clone(entries);
+        self.last_timestamp = self.entries.last().map(|entry| entry.timestamp);
+    }
+
     /// `line_start` points to the start of the meta section in the data file
     #[instrument(level = "trace", skip(self), ret)]
     pub(crate) fn update(
@@ -212,10 +285,11 @@ impl Index {
         meta_start: MetaPos,
     ) -> Result<(), std::io::Error> {
         let ts = timestamp;
-        self.file.write_all(&ts.to_le_bytes())?;
-        self.file.write_all(&meta_start.to_le_bytes())?;
+        let file = self.file_mut()?;
+        file.write_all(&ts.to_le_bytes())?;
+        file.write_all(&meta_start.to_le_bytes())?;
 
-        self.entries.push(Entry {
+        Arc::make_mut(&mut self.entries).push(Entry {
             timestamp,
             meta_start,
         });
@@ -223,6 +297,25 @@ impl Index {
         Ok(())
     }
 
+    /// Appends multiple entries using a single write
+    pub(crate) fn extend(&mut self, entries: &[Entry]) -> Result<(), std::io::Error> {
+        let bytes: Vec<u8> = entries
+            .iter()
+            .flat_map(|entry| {
+                let timestamp = entry.timestamp.to_le_bytes();
+                let meta_start = entry.meta_start.to_le_bytes();
+                timestamp.into_iter().chain(meta_start)
+            })
+            .collect();
+        self.file_mut()?.write_all(&bytes)?;
+
+        Arc::make_mut(&mut self.entries).extend_from_slice(entries);
+        if let Some(last) = entries.last() {
+            self.last_timestamp = Some(last.timestamp);
+        }
+        Ok(())
+    }
+
     #[instrument]
     pub(crate) fn start_search_bounds(
         &self,
@@ -347,9 +440,72 @@ impl Index {
         }
     }
 
+    /// The meta sections in the order they appear in the data
+    pub(crate) fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Like [`entries`](Self::entries) but for handing to readers, see
+    /// [`sync_entries`](Self::sync_entries).
+    pub(crate) fn shared_entries(&self) -> Arc<Vec<Entry>> {
+        Arc::clone(&self.entries)
+    }
+
+    /// Position in [`entries`](Self::entries) of the last meta section that
+    /// starts before `offset`. None if there is no such section.
+    pub(crate) fn last_section_before(&self, offset: u64) -> Option<usize> {
+        self.entries
+            .partition_point(|entry| entry.meta_start.0 < offset)
+            .checked_sub(1)
+    }
+
+    /// Removes the entries of the meta sections that start at or after
+    /// `data_len`.
+    pub(crate) fn truncate(&mut self, data_len: u64) -> Result<(), std::io::Error> {
+        let keep = self
+            .entries
+            .partition_point(|entry| entry.meta_start.raw_offset() < data_len);
+        self.file_mut()?.set_len(keep as u64 * 16)?;
+        Arc::make_mut(&mut self.entries).truncate(keep);
+        self.last_timestamp = self.entries.last().map(|entry| entry.timestamp);
+        Ok(())
+    }
+
+    /// Creates an empty index at [`part_path`](Self::part_path). Move it
+    /// in place once it is complete.
+    pub(crate) fn new_part(name: impl AsRef<Path>) -> Result<Index, file::OpenError> {
+        let file = FileWithHeader::new(Self::part_path(name), &[])?;
+        Ok(Index {
+            file: Some(file.split_off_header().0),
+            entries: Arc::default(),
+            last_timestamp: None,
+        })
+    }
+
+    /// The entries from `first` on of the meta sections that start before
+    /// `end`. They are moved back to where they are once the data before
+    /// entry `first` is dropped.
+    pub(crate) fn entries_rebased(&self, first: usize, end: u64) -> Vec<Entry> {
+        let Some(dropped) = self.entries.get(first).map(|entry| entry.meta_start) else {
+            return Vec::new();
+        };
+        self.entries[first..]
+            .iter()
+            .take_while(|entry| entry.meta_start.raw_offset() < end)
+            .map(|entry| Entry {
+                timestamp: entry.timestamp,
+                meta_start: MetaPos(entry.meta_start - dropped),
+            })
+            .collect()
+    }
+
+    pub(crate) fn part_path(name: impl AsRef<Path>) -> PathBuf {
+        name.as_ref().with_extension("byteseries_index.part")
+    }
+
     pub(crate) fn clear(&mut self) -> Result<(), std::io::Error> {
-        self.file.set_len(0)?;
-        self.entries.clear();
+        self.file_mut()?.set_len(0)?;
+        self.entries = Arc::default();
         self.last_timestamp = None;
         Ok(())
     }
@@ -360,6 +516,23 @@ impl Index {
     }
 }
 
+/// The entries stored in `bytes`, a partial entry at the end is ignored
+pub(crate) fn parse_entries(bytes: &[u8]) -> Vec<Entry> {
+    bytes
+        .chunks_exact(16)
+        .map(|line| {
+            let timestamp: [u8; 8] = line[0..8].try_into().expect("line is 2*8 bytes");
+            let timestamp = u64::from_le_bytes(timestamp);
+            let line_start: [u8; 8] = line[8..].try_into().expect("line is 2*8 bytes");
+            let line_start = u64::from_le_bytes(line_start);
+            Entry {
+                timestamp,
+                meta_start: MetaPos(line_start),
+            }
+        })
+        .collect()
+}
+
 fn in_gap(val: Timestamp, gap_start: Timestamp) -> bool {
     let reach = MAX_SMALL_TS;
     val > gap_start + reach
@@ -375,6 +548,8 @@ pub enum CheckAndRepairError {
         last_ts_in_index: Timestamp,
         last_ts_in_data: Timestamp,
     },
+    #[error("The index is empty while the data is not")]
+    Empty,
     #[error("Could not repair the index by truncating it: {0}")]
     Truncate(std::io::Error),
     #[error("Could not check the index, failed to get its length: {0}")]
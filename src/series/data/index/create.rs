@@ -1,11 +1,11 @@
 use core::fmt;
-use std::io::{Read, Seek};
 use std::path::Path;
+use std::sync::Arc;
 
 use tracing::instrument;
 
 use crate::file::{FileWithHeader, OffsetFile, OpenError};
-use crate::series::data::inline_meta::meta;
+use crate::series::data::inline_meta::{meta, ReadAt};
 use crate::Timestamp;
 
 use super::{Entry, Index, PayloadSize};
@@ -35,18 +35,18 @@ pub enum Error {
 impl Index {
     #[instrument]
     pub(crate) fn create_from_byteseries(
-        byteseries: &mut OffsetFile,
+        byteseries: &OffsetFile,
         payload_size: PayloadSize,
         name: impl AsRef<Path> + fmt::Debug,
     ) -> Result<Self, Error> {
-        let temp_path = name.as_ref().with_extension("byteseries_index.part");
+        let temp_path = Self::part_path(&name);
         let index_file = FileWithHeader::new(&temp_path, &[])?;
         let entries = extract_entries(byteseries, payload_size)?;
 
         let mut index = Self {
             last_timestamp: entries.last().map(|Entry { timestamp, .. }| *timestamp),
-            file: index_file.split_off_header().0,
-            entries: Vec::new(),
+            file: Some(index_file.split_off_header().0),
+            entries: Arc::default(),
         };
 
         for entry in entries {
@@ -71,12 +71,10 @@ pub enum ExtractingTsError {
     ReadChunk(std::io::Error),
     #[error("Could not read last part of data")]
     ReadFinalChunk(std::io::Error),
-    #[error("Could not seek to start of byteseries data")]
-    Seek(std::io::Error),
 }
 
 pub(crate) fn extract_entries(
-    file: &mut OffsetFile,
+    file: &OffsetFile,
     payload_size: PayloadSize,
 ) -> Result<Vec<Entry>, ExtractingTsError> {
     let data_len = file
@@ -87,7 +85,7 @@ pub(crate) fn extract_entries(
 
 #[instrument]
 pub(crate) fn extract_entries_inner(
-    file: &mut OffsetFile,
+    file: &OffsetFile,
     payload_size: PayloadSize,
     start: u64,
     end: u64,
@@ -100,16 +98,17 @@ pub(crate) fn extract_entries_inner(
     // do not init with zero or the initially empty overlap
     // will be seen as a full timestamp
     let mut buffer = vec![1u8; chunk_size + overlap];
-    file.seek(std::io::SeekFrom::Start(start))
-        .map_err(ExtractingTsError::Seek)?;
 
     let mut to_read = end - start;
     let mut previously_read = 0;
 
     while to_read > 0 {
         let read_size = chunk_size.min(usize::try_from(to_read).unwrap_or(usize::MAX));
-        file.read_exact(&mut buffer[overlap..overlap + read_size])
-            .map_err(ExtractingTsError::ReadChunk)?;
+        file.read_exact_at(
+            &mut buffer[overlap..overlap + read_size],
+            start + previously_read,
+        )
+        .map_err(ExtractingTsError::ReadChunk)?;
         to_read -= read_size as u64;
 
         entries.extend(
@@ -132,13 +131,22 @@ pub(crate) fn extract_entries_inner(
 
 #[instrument(level = "debug", skip_all, ret)]
 pub(crate) fn last_meta_timestamp(
-    file: &mut OffsetFile,
+    file: &OffsetFile,
     payload_size: PayloadSize,
 ) -> Result<Option<Timestamp>, ExtractingTsError> {
     let data_bytes = file
         .data_len_bytes()
         .map_err(ExtractingTsError::GetDataLength)?;
+    last_meta_timestamp_before(file, payload_size, data_bytes)
+}
 
+/// Like [`last_meta_timestamp`] but only looks at the first `data_bytes`
+/// of the data.
+pub(crate) fn last_meta_timestamp_before(
+    file: &OffsetFile,
+    payload_size: PayloadSize,
+    data_bytes: u64,
+) -> Result<Option<Timestamp>, ExtractingTsError> {
     let window = 10_000u64.next_multiple_of(payload_size.line_size() as u64);
     let overlap = payload_size.metainfo_size();
     let mut start = data_bytes.saturating_sub(window);
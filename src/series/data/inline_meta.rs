@@ -1,13 +1,18 @@
 use crate::series::data::PayloadSize;
 use core::fmt;
 use itertools::Itertools;
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::io::{self, Write};
 use std::iter;
-use tracing::{instrument, warn};
+use std::ops::Range;
+use tracing::instrument;
 use with_processor::Error;
 
+use crate::file::MappedBytes;
+use crate::series::corruption::{self, Series, Skipped};
+use crate::series::repair::RepairReason;
 use crate::{CorruptionCallback, Pos, Resampler};
 
+use super::checksums::Checksums;
 use super::{Decoder, ReadError, Timestamp};
 pub(crate) mod meta;
 pub(crate) mod with_processor;
@@ -16,6 +21,10 @@ pub(crate) mod with_processor;
 pub(crate) struct FileWithInlineMeta<F: fmt::Debug> {
     pub(crate) file_handle: F,
     pub(crate) payload_size: PayloadSize,
+    /// If set reads check the meta sections against their checksum
+    pub(crate) checksums: Option<Checksums>,
+    /// Told to the corruption callback
+    pub(crate) series: Series,
 }
 
 pub(crate) trait SetLen {
@@ -23,41 +32,48 @@ pub(crate) trait SetLen {
     fn set_len(&mut self, len: u64) -> Result<(), std::io::Error>;
 }
 
-impl<F: fmt::Debug + Read + Seek + SetLen> FileWithInlineMeta<F> {
-    /// Will
-    ///  - remove partial line write at the end of the file
-    ///  - truncate the file if it contains only metadata
-    ///  - remove a (partial) trailing metadata sections if there is one
-    pub(crate) fn new(
-        mut file: F,
-        payload_size: PayloadSize,
-    ) -> Result<Self, std::io::Error> {
-        'check_and_repair: {
-            if file.len()? == 0 {
-                break 'check_and_repair;
-            }
-
-            repair_incomplete_last_write(&mut file, payload_size)?;
-            if repaired_is_only_meta(&mut file, payload_size)? {
-                warn!("repaired file only consisting of a meta section");
-                break 'check_and_repair;
-            }
-
-            if removed_partial_meta_at_end(&mut file, payload_size)? {
-                warn!("repaired incomplete written meta section at end");
-                break 'check_and_repair;
-            }
+/// Positional reads, these do not move a cursor and therefore only need
+/// a shared reference.
+pub(crate) trait ReadAt {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), std::io::Error>;
+
+    /// Direct access to the bytes in `range`, without copying them. Only
+    /// possible if the file is memory mapped, returns None otherwise.
+    fn map_range(
+        &self,
+        _range: Range<u64>,
+    ) -> Result<Option<MappedBytes<'_>>, std::io::Error> {
+        Ok(None)
+    }
 
-            if removed_start_of_meta_at_end(&mut file, payload_size)? {
-                warn!("repaired one line of incomplete meta section at end");
-                break 'check_and_repair;
-            }
+    /// Calls `f` with the bytes in `range`. These are read into a new
+    /// buffer unless the file is memory mapped.
+    fn with_bytes_at<T>(
+        &self,
+        range: Range<u64>,
+        f: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, std::io::Error> {
+        if let Some(bytes) = self.map_range(range.clone())? {
+            return Ok(f(&bytes));
         }
 
-        Ok(FileWithInlineMeta {
+        let len = usize::try_from(range.end - range.start)
+            .expect("callers never read more then fits in memory");
+        let mut buf = vec![0u8; len];
+        self.read_exact_at(&mut buf, range.start)?;
+        Ok(f(&buf))
+    }
+}
+
+impl<F: fmt::Debug + ReadAt + SetLen> FileWithInlineMeta<F> {
+    /// The file must not need any [repairs](needed_repairs)
+    pub(crate) fn new(file: F, payload_size: PayloadSize, series: Series) -> Self {
+        FileWithInlineMeta {
             file_handle: file,
             payload_size,
-        })
+            checksums: None,
+            series,
+        }
     }
 
     pub(crate) fn inner_mut(&mut self) -> &mut F {
@@ -69,15 +85,16 @@ impl<F: fmt::Debug + Read + Seek + SetLen> FileWithInlineMeta<F> {
         skip(self, decoder, timestamps, data, corruption_callback)
     )]
     pub(crate) fn read<D: Decoder>(
-        &mut self,
+        &self,
         decoder: &mut D,
         timestamps: &mut Vec<Timestamp>,
         data: &mut Vec<D::Item>,
         seek: Pos,
         corruption_callback: &mut Option<CorruptionCallback>,
-    ) -> Result<(), ReadError> {
+    ) -> Result<Skipped, ReadError> {
+        let mut corruption = corruption::Handler::new(corruption_callback, &self.series);
         let mut last = 0;
-        self.read_with_processor::<()>(seek, corruption_callback, |ts, payload| {
+        self.read_with_processor::<()>(seek, &mut corruption, |ts, payload| {
             let item = decoder.decode_payload(payload);
             data.push(item);
             timestamps.push(ts);
@@ -86,12 +103,16 @@ impl<F: fmt::Debug + Read + Seek + SetLen> FileWithInlineMeta<F> {
             last = ts;
             Ok(())
         })
+        .map(|()| corruption.skipped)
         .map_err(|e| match e {
             Error::Io(error) => ReadError::Io(error),
             Error::Processor(_) => {
                 panic!("impossible, this processor never returns an error")
             }
             Error::CorruptMetaSection => ReadError::CorruptMetaSection,
+            Error::ChecksumMismatch { section_start } => {
+                ReadError::ChecksumMismatch { section_start }
+            }
         })
     }
 
@@ -100,19 +121,20 @@ impl<F: fmt::Debug + Read + Seek + SetLen> FileWithInlineMeta<F> {
         skip(self, decoder, timestamps, data, corruption_callback)
     )]
     pub(crate) fn read_first_n<D: Decoder>(
-        &mut self,
+        &self,
         n: usize,
         decoder: &mut D,
         timestamps: &mut Vec<Timestamp>,
         data: &mut Vec<D::Item>,
         seek: Pos,
         corruption_callback: &mut Option<CorruptionCallback>,
-    ) -> Result<(), ReadError> {
+    ) -> Result<Skipped, ReadError> {
         #[derive(Debug)]
         struct ReachedN;
 
+        let mut corruption = corruption::Handler::new(corruption_callback, &self.series);
         let mut n_read = 0;
-        let res = self.read_with_processor(seek, corruption_callback, |ts, payload| {
+        let res = self.read_with_processor(seek, &mut corruption, |ts, payload| {
             let item = decoder.decode_payload(payload);
             data.push(item);
             timestamps.push(ts);
@@ -126,8 +148,11 @@ impl<F: fmt::Debug + Read + Seek + SetLen> FileWithInlineMeta<F> {
         });
 
         match res {
-            Ok(()) | Err(Error::Processor(ReachedN)) => Ok(()),
+            Ok(()) | Err(Error::Processor(ReachedN)) => Ok(corruption.skipped),
             Err(Error::CorruptMetaSection) => Err(ReadError::CorruptMetaSection),
+            Err(Error::ChecksumMismatch { section_start }) => {
+                Err(ReadError::ChecksumMismatch { section_start })
+            }
             Err(Error::Io(e)) => Err(ReadError::Io(e)),
         }
     }
@@ -137,38 +162,116 @@ impl<F: fmt::Debug + Read + Seek + SetLen> FileWithInlineMeta<F> {
         skip(self, resampler, timestamps, data, corruption_callback)
     )]
     pub(crate) fn read_resampling<R: crate::Resampler>(
-        &mut self,
+        &self,
         resampler: &mut R,
         bucket_size: usize,
         timestamps: &mut Vec<u64>,
         data: &mut Vec<<R as Decoder>::Item>,
         seek: Pos,
         corruption_callback: &mut Option<CorruptionCallback>,
-    ) -> Result<(), ReadError> {
+    ) -> Result<Skipped, ReadError> {
+        let mut corruption = corruption::Handler::new(corruption_callback, &self.series);
         let mut sampler = Sampler::new(resampler, bucket_size, timestamps, data);
-        self.read_with_processor::<()>(seek, corruption_callback, |ts, payload| {
+        self.read_with_processor::<()>(seek, &mut corruption, |ts, payload| {
             sampler.process(ts, payload);
             Ok(())
         })
+        .map(|()| corruption.skipped)
         .map_err(|e| match e {
             Error::Io(error) => ReadError::Io(error),
             Error::Processor(_) => {
                 panic!("impossible, this processor never returns an error")
             }
             Error::CorruptMetaSection => ReadError::CorruptMetaSection,
+            Error::ChecksumMismatch { section_start } => {
+                ReadError::ChecksumMismatch { section_start }
+            }
         })
     }
 }
 
-fn removed_start_of_meta_at_end<F: fmt::Debug + Read + Seek + SetLen>(
+/// The repairs needed to undo an interrupted write at the end of `file`,
+/// `len` long. Each removes the bytes in its range, later repairs remove
+/// bytes before those of earlier ones. Does not change the file.
+///
+/// Lines at the end are removed if they
+///  - are a partial line write
+///  - are all that is left and contain only metadata
+///  - are a (partial) trailing metadata section
+pub(crate) fn needed_repairs<F: fmt::Debug + ReadAt>(
+    file: &F,
+    len: u64,
+    payload_size: PayloadSize,
+) -> Result<Vec<(RepairReason, Range<u64>)>, io::Error> {
+    let mut file = Truncated { file, len };
+    let mut repairs = Vec::new();
+    if len == 0 {
+        return Ok(repairs);
+    }
+    let mut record = |file: &Truncated<'_, F>, reason| {
+        let end = repairs
+            .last()
+            .map_or(len, |(_, range): &(_, Range<u64>)| range.start);
+        if file.len < end {
+            repairs.push((reason, file.len..end));
+        }
+    };
+
+    repair_incomplete_last_write(&mut file, payload_size)?;
+    record(&file, RepairReason::IncompleteLine);
+    if repaired_is_only_meta(&mut file, payload_size)? {
+        record(&file, RepairReason::OnlyMeta);
+    } else if removed_partial_meta_at_end(&mut file, payload_size)? {
+        record(&file, RepairReason::PartialMeta);
+    } else if removed_start_of_meta_at_end(&mut file, payload_size)? {
+        record(&file, RepairReason::StartOfMeta);
+    }
+    Ok(repairs)
+}
+
+/// The length `file` would have after the [`needed_repairs`]
+pub(crate) fn repaired_len<F: fmt::Debug + ReadAt>(
+    file: &F,
+    len: u64,
+    payload_size: PayloadSize,
+) -> Result<u64, io::Error> {
+    let repairs = needed_repairs(file, len, payload_size)?;
+    Ok(repairs.last().map_or(len, |(_, removed)| removed.start))
+}
+
+/// A file that is only truncated in memory
+#[derive(Debug)]
+struct Truncated<'a, F> {
+    file: &'a F,
+    len: u64,
+}
+
+impl<F> SetLen for Truncated<'_, F> {
+    fn len(&self) -> Result<u64, io::Error> {
+        Ok(self.len)
+    }
+
+    fn set_len(&mut self, len: u64) -> Result<(), io::Error> {
+        self.len = len;
+        Ok(())
+    }
+}
+
+impl<F: ReadAt> ReadAt for Truncated<'_, F> {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), io::Error> {
+        self.file.read_exact_at(buf, offset)
+    }
+}
+
+fn removed_start_of_meta_at_end<F: fmt::Debug + ReadAt + SetLen>(
     file: &mut F,
     payload_size: PayloadSize,
 ) -> Result<bool, io::Error> {
-    file.seek(SeekFrom::Start(
-        file.len()? - payload_size.metainfo_size() as u64,
-    ))?;
     let mut to_check = vec![1u8; 2 * payload_size.line_size()];
-    file.read_exact(&mut to_check)?;
+    file.read_exact_at(
+        &mut to_check,
+        file.len()? - payload_size.metainfo_size() as u64,
+    )?;
     let mut lines = to_check.chunks_exact(payload_size.line_size());
     let last_line = lines.by_ref().last().expect("read multiple lines");
     let meta_start_before_last_line = lines
@@ -185,15 +288,13 @@ fn removed_start_of_meta_at_end<F: fmt::Debug + Read + Seek + SetLen>(
     }
 }
 
-fn removed_partial_meta_at_end<F: fmt::Debug + Read + Seek + SetLen>(
+fn removed_partial_meta_at_end<F: fmt::Debug + ReadAt + SetLen>(
     file: &mut F,
     payload_size: PayloadSize,
 ) -> Result<bool, io::Error> {
     let check_start = file.len()? - payload_size.metainfo_size() as u64;
-    file.seek(SeekFrom::Start(check_start))?;
-
     let mut to_check = vec![0u8; payload_size.metainfo_size()];
-    file.read_exact(&mut to_check)?;
+    file.read_exact_at(&mut to_check, check_start)?;
 
     // otherwise the check below does not match a partial meta section
     // that is only one line
@@ -219,15 +320,12 @@ fn repair_incomplete_last_write(
 ) -> Result<(), std::io::Error> {
     let rest = file.len()? % (payload_size.line_size() as u64);
     if rest > 0 {
-        tracing::warn!(
-            "Last write incomplete, truncating to largest multiple of the line size"
-        );
         file.set_len(file.len()? - rest)?;
     }
     Ok(())
 }
 
-fn repaired_is_only_meta<F: fmt::Debug + Read + Seek + SetLen>(
+fn repaired_is_only_meta<F: fmt::Debug + SetLen>(
     file: &mut F,
     payload_size: PayloadSize,
 ) -> Result<bool, io::Error> {
@@ -241,7 +339,7 @@ fn repaired_is_only_meta<F: fmt::Debug + Read + Seek + SetLen>(
 
 use crate::ResampleState;
 
-struct Sampler<'a, R: Resampler> {
+pub(crate) struct Sampler<'a, R: Resampler> {
     resampler: &'a mut R,
     resample_state: <R as Resampler>::State,
     timestamp_sum: u64,
@@ -253,7 +351,7 @@ struct Sampler<'a, R: Resampler> {
 }
 
 impl<'a, R: Resampler> Sampler<'a, R> {
-    fn new(
+    pub(crate) fn new(
         resampler: &'a mut R,
         bucket_size: usize,
         timestamps: &'a mut Vec<u64>,
@@ -271,7 +369,7 @@ impl<'a, R: Resampler> Sampler<'a, R> {
         }
     }
 
-    fn process(&mut self, ts: Timestamp, payload: &[u8]) {
+    pub(crate) fn process(&mut self, ts: Timestamp, payload: &[u8]) {
         let item = self.resampler.decode_payload(payload);
         self.timestamp_sum += ts;
         self.resample_state.add(item);
@@ -295,9 +393,3 @@ impl<F: Write + fmt::Debug> Write for FileWithInlineMeta<F> {
         self.file_handle.flush()
     }
 }
-
-impl<F: Seek + fmt::Debug> Seek for FileWithInlineMeta<F> {
-    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
-        self.file_handle.seek(pos)
-    }
-}
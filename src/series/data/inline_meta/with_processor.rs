@@ -1,10 +1,13 @@
 use core::fmt;
-use std::io::{Read, Seek, SeekFrom};
 use tracing::{instrument, warn};
 
-use crate::{CorruptionCallback, Pos};
+use crate::series::corruption;
+use crate::series::data::checksums::{Checksums, Section};
+use crate::series::data::index::LinePos;
+use crate::series::data::PayloadSize;
+use crate::Pos;
 
-use super::{meta, FileWithInlineMeta, SetLen, Timestamp};
+use super::{meta, FileWithInlineMeta, ReadAt, SetLen, Timestamp};
 
 // to make it easy for users writing Processors this does
 // not implement std::core::Error
@@ -13,6 +16,11 @@ pub(crate) enum Error<E> {
     Io(std::io::Error),
     Processor(E),
     CorruptMetaSection,
+    /// The meta section starting at this offset, or a line after it, got
+    /// corrupted
+    ChecksumMismatch {
+        section_start: u64,
+    },
 }
 
 impl<E> From<std::io::Error> for Error<E> {
@@ -28,81 +36,328 @@ fn ts_from(line: &[u8], full_ts: u64) -> u64 {
     full_ts + small_ts
 }
 
-impl<F: fmt::Debug + Read + Seek + SetLen> FileWithInlineMeta<F> {
-    #[instrument(level = "debug", skip(processor, corruption_callback))]
-    pub(crate) fn read_with_processor<E: std::fmt::Debug>(
+/// Hands lines to a processor together with their full timestamp. Keeps
+/// track of the meta sections to know the full timestamp.
+#[derive(Debug)]
+struct LineProcessor {
+    line_size: usize,
+    meta_ts: Timestamp,
+    skipping_over_corrupted_data: bool,
+}
+
+impl LineProcessor {
+    fn new(line_size: usize, first_full_ts: Timestamp) -> Self {
+        Self {
+            line_size,
+            meta_ts: first_full_ts,
+            skipping_over_corrupted_data: false,
+        }
+    }
+
+    /// Returns how many bytes at the end of `bytes` are part of a meta
+    /// section that continues after `bytes`. These should be passed in
+    /// again, followed by the rest of that section. `offset` is where
+    /// `bytes` start in the data.
+    fn process<E: fmt::Debug>(
         &mut self,
-        seek: Pos,
-        corruption_callback: &mut Option<CorruptionCallback>,
+        bytes: &[u8],
+        offset: u64,
+        corruption: &mut corruption::Handler<'_>,
         mut processor: impl FnMut(Timestamp, &[u8]) -> Result<(), E>,
-    ) -> Result<(), Error<E>> {
-        let mut to_read = seek.end - seek.start.raw_offset();
-        let chunk_size = 16384usize.next_multiple_of(self.payload_size.line_size());
-        // meta section decoding can need at most 5 lines of overlap.
-        let max_needed_overlap = (3 + 2) * self.payload_size.line_size();
-        let mut buf = vec![0; chunk_size + max_needed_overlap];
-
-        self.file_handle
-            .seek(SeekFrom::Start(seek.start.raw_offset()))?;
-
-        let mut skipping_over_corrupted_data = false;
-        let mut needed_overlap = 0;
-        let mut meta_ts = seek.first_full_ts;
-        let mut read_size = 0;
-
-        while to_read > 0 {
-            // move needed overlap to start of next read
-            let overlap = (read_size - needed_overlap)..read_size;
-            buf.copy_within(overlap, 0);
-
-            read_size = chunk_size.min(usize::try_from(to_read).unwrap_or(usize::MAX));
-            to_read -= read_size as u64;
-            self.file_handle
-                .read_exact(&mut buf[needed_overlap..needed_overlap + read_size])?;
-            let mut lines = buf[..needed_overlap + read_size]
-                .chunks_exact(self.payload_size.line_size());
-
-            needed_overlap = loop {
-                let Some(line) = lines.next() else {
-                    break 0;
-                };
-
-                if line[..2] != meta::PREAMBLE && !skipping_over_corrupted_data {
-                    let debug_res = processor(ts_from(line, meta_ts), &line[2..])
-                        .map_err(Error::Processor);
-                    debug_res?;
+    ) -> Result<usize, Error<E>> {
+        let mut lines = bytes.chunks_exact(self.line_size);
+        let n_lines = lines.len();
 
+        let needed_overlap = loop {
+            let Some(line) = lines.next() else {
+                break 0;
+            };
+
+            if line[..2] != meta::PREAMBLE && !self.skipping_over_corrupted_data {
+                let debug_res = processor(ts_from(line, self.meta_ts), &line[2..])
+                    .map_err(Error::Processor);
+                debug_res?;
+
+                continue;
+            }
+
+            let Some(next_line) = lines.next() else {
+                break self.line_size;
+            };
+
+            // the break with needed_overlap ensures a new read always starts
+            // before a meta section and never in between.
+            if next_line[..2] != meta::PREAMBLE {
+                let line = n_lines - lines.len() - 2;
+                let line_offset = offset + (line * self.line_size) as u64;
+                if corruption.skip(line_offset, self.meta_ts, 2) {
                     continue;
+                } else {
+                    return Err(Error::CorruptMetaSection);
                 }
+            }
 
-                let Some(next_line) = lines.next() else {
-                    break self.payload_size.line_size();
-                };
-
-                // the break with needed_overlap ensures a new read always starts
-                // before a meta section and never in between.
-                if next_line[..2] != meta::PREAMBLE {
-                    if let Some(corruption_accepted) = corruption_callback {
-                        if corruption_accepted() {
-                            continue;
-                        } else {
-                            return Err(Error::CorruptMetaSection);
-                        }
-                    } else {
-                        return Err(Error::CorruptMetaSection);
-                    }
+            self.skipping_over_corrupted_data = false;
+            match meta::read(lines.by_ref(), line, next_line) {
+                meta::Result::Meta { meta } => {
+                    self.meta_ts = u64::from_le_bytes(meta);
                 }
+                meta::Result::OutOfLines { consumed_lines } => {
+                    break (2 + consumed_lines) * self.line_size;
+                }
+            };
+        };
+        Ok(needed_overlap)
+    }
+}
 
-                skipping_over_corrupted_data = false;
-                match meta::read(lines.by_ref(), line, next_line) {
-                    meta::Result::Meta { meta } => {
-                        meta_ts = u64::from_le_bytes(meta);
-                    }
-                    meta::Result::OutOfLines { consumed_lines } => {
-                        break (2 + consumed_lines) * self.payload_size.line_size();
+/// Reads the lines between two positions one chunk at the time. Keeps
+/// track of the meta sections so the lines can be given their full
+/// timestamp. Never holds more then one chunk in memory, except for checking
+/// a section against its checksum. That reads the section as a whole.
+#[derive(Debug)]
+pub(crate) struct ChunkedReader {
+    buf: Vec<u8>,
+    chunk_size: usize,
+    /// offset from the start of the data where the next chunk starts
+    next_chunk_start: u64,
+    to_read: u64,
+    needed_overlap: usize,
+    read_size: usize,
+    lines: LineProcessor,
+    /// the sections before this passed their checksum
+    checked_until: u64,
+}
+
+impl ChunkedReader {
+    pub(crate) fn new(seek: Pos, payload_size: PayloadSize) -> Self {
+        let chunk_size = 16384usize.next_multiple_of(payload_size.line_size());
+        // meta section decoding can need at most 5 lines of overlap.
+        let max_needed_overlap = (3 + 2) * payload_size.line_size();
+        Self {
+            buf: vec![0; chunk_size + max_needed_overlap],
+            chunk_size,
+            next_chunk_start: seek.start.raw_offset(),
+            to_read: seek.end - seek.start.raw_offset(),
+            needed_overlap: 0,
+            read_size: 0,
+            lines: LineProcessor::new(payload_size.line_size(), seek.first_full_ts),
+            checked_until: 0,
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.to_read == 0
+    }
+
+    /// Reads the next chunk and hands every line in it to the processor.
+    /// Does nothing if everything has been read.
+    ///
+    /// If there are `checksums` the sections in the chunk are checked
+    /// before any of their lines are processed. A section with a wrong
+    /// checksum is skipped if the corruption callback allows it.
+    pub(crate) fn process_next_chunk<E: fmt::Debug>(
+        &mut self,
+        file: &impl ReadAt,
+        checksums: Option<&Checksums>,
+        corruption: &mut corruption::Handler<'_>,
+        processor: impl FnMut(Timestamp, &[u8]) -> Result<(), E>,
+    ) -> Result<(), Error<E>> {
+        if self.is_done() {
+            return Ok(());
+        }
+
+        let mut read_size = self
+            .chunk_size
+            .min(usize::try_from(self.to_read).unwrap_or(usize::MAX));
+        if let Some(checksums) = checksums {
+            match self.check_sections(file, checksums, read_size)? {
+                Checked::Ok => (),
+                Checked::EndBefore(corrupt_start) => {
+                    // process the lines before it first, the corrupt
+                    // section is then at the start of the next chunk
+                    read_size = usize::try_from(corrupt_start - self.next_chunk_start)
+                        .expect("less then read_size");
+                }
+                Checked::Corrupt(section) => {
+                    return self.skip_section(section, corruption);
+                }
+            }
+        }
+
+        // move needed overlap to start of next read
+        let overlap = (self.read_size - self.needed_overlap)..self.read_size;
+        self.buf.copy_within(overlap, 0);
+
+        self.read_size = read_size;
+        self.to_read -= self.read_size as u64;
+        file.read_exact_at(
+            &mut self.buf[self.needed_overlap..self.needed_overlap + self.read_size],
+            self.next_chunk_start,
+        )?;
+        let buf_start = self.next_chunk_start - self.needed_overlap as u64;
+        self.next_chunk_start += self.read_size as u64;
+
+        self.needed_overlap = self.lines.process(
+            &self.buf[..self.needed_overlap + self.read_size],
+            buf_start,
+            corruption,
+            processor,
+        )?;
+        Ok(())
+    }
+
+    /// Checks the sections that overlap the next `read_size` bytes and were
+    /// not yet checked
+    fn check_sections(
+        &mut self,
+        file: &impl ReadAt,
+        checksums: &Checksums,
+        read_size: usize,
+    ) -> Result<Checked, std::io::Error> {
+        let chunk = self.next_chunk_start..self.next_chunk_start + read_size as u64;
+        for section in checksums.overlapping(chunk) {
+            if section.end <= self.checked_until {
+                continue;
+            }
+            let correct = file
+                .with_bytes_at(section.range(), |b| crc32fast::hash(b) == section.crc)?;
+            if correct {
+                self.checked_until = section.end;
+            } else if section.start > self.next_chunk_start {
+                return Ok(Checked::EndBefore(section.start));
+            } else {
+                return Ok(Checked::Corrupt(*section));
+            }
+        }
+        Ok(Checked::Ok)
+    }
+
+    /// Moves past the lines in `section` that are still to be read. The
+    /// section starts at or before the next chunk.
+    fn skip_section<E>(
+        &mut self,
+        section: Section,
+        corruption: &mut corruption::Handler<'_>,
+    ) -> Result<(), Error<E>> {
+        let section_start = section.start;
+        warn!("checksum of meta section at {section_start} is wrong");
+        // the overlap can only be part of this section, a meta section
+        // never crosses into the next section
+        let skip_from = self.next_chunk_start - self.needed_overlap as u64;
+        let skip_to = section.end.min(self.next_chunk_start + self.to_read);
+        let to_skip = (skip_to - skip_from) / self.lines.line_size as u64;
+        if !corruption.skip(section_start, self.lines.meta_ts, to_skip) {
+            return Err(Error::ChecksumMismatch { section_start });
+        }
+
+        self.to_read -= skip_to - self.next_chunk_start;
+        self.next_chunk_start = skip_to;
+        self.needed_overlap = 0;
+        self.read_size = 0;
+        self.checked_until = skip_to;
+        Ok(())
+    }
+}
+
+/// The outcome of checking the sections in a chunk
+enum Checked {
+    Ok,
+    /// The chunk must end here, after it follows a corrupt section
+    EndBefore(u64),
+    /// This section, at the start of the chunk, is corrupt
+    Corrupt(Section),
+}
+
+impl<F: fmt::Debug + ReadAt + SetLen> FileWithInlineMeta<F> {
+    /// If the file is memory mapped the lines are processed straight from
+    /// the map, otherwise they are read one chunk at the time.
+    ///
+    /// If the meta sections have checksums every section that has one is
+    /// read as a whole and checked before its lines are processed.
+    ///
+    /// What the corruption callback allowed to skip is added to the
+    /// [`skipped`](corruption::Handler::skipped) of `corruption`.
+    #[instrument(level = "debug", skip(processor, corruption))]
+    pub(crate) fn read_with_processor<E: std::fmt::Debug>(
+        &self,
+        seek: Pos,
+        corruption: &mut corruption::Handler<'_>,
+        mut processor: impl FnMut(Timestamp, &[u8]) -> Result<(), E>,
+    ) -> Result<(), Error<E>> {
+        let Some(checksums) = &self.checksums else {
+            return self.read_unchecked(seek, corruption, processor);
+        };
+
+        let mut lines =
+            LineProcessor::new(self.payload_size.line_size(), seek.first_full_ts);
+        let mut start = seek.start.raw_offset();
+        for section in checksums.overlapping(start..seek.end) {
+            let to_process = (start.max(section.start) - section.start) as usize
+                ..(seek.end.min(section.end) - section.start) as usize;
+            let res = self.file_handle.with_bytes_at(section.range(), |bytes| {
+                if crc32fast::hash(bytes) != section.crc {
+                    return Err(Error::ChecksumMismatch {
+                        section_start: section.start,
+                    });
+                }
+                lines.process(
+                    &bytes[to_process.clone()],
+                    start.max(section.start),
+                    corruption,
+                    &mut processor,
+                )
+            })?;
+            match res {
+                Ok(_) => (),
+                Err(Error::ChecksumMismatch { section_start }) => {
+                    warn!("checksum of meta section at {section_start} is wrong");
+                    let to_skip = to_process.len() / self.payload_size.line_size();
+                    if !corruption.skip(section_start, lines.meta_ts, to_skip as u64) {
+                        return Err(Error::ChecksumMismatch { section_start });
                     }
-                };
-            };
+                }
+                Err(other) => return Err(other),
+            }
+            start = section.end;
+        }
+
+        if start >= seek.end {
+            return Ok(());
+        }
+        // the rest does not have a checksum yet, it starts at a meta
+        // section unless nothing was checked
+        let rest = Pos {
+            start: LinePos(start),
+            end: seek.end,
+            first_full_ts: seek.first_full_ts,
+        };
+        self.read_unchecked(rest, corruption, processor)
+    }
+
+    fn read_unchecked<E: std::fmt::Debug>(
+        &self,
+        seek: Pos,
+        corruption: &mut corruption::Handler<'_>,
+        mut processor: impl FnMut(Timestamp, &[u8]) -> Result<(), E>,
+    ) -> Result<(), Error<E>> {
+        if let Some(bytes) = self
+            .file_handle
+            .map_range(seek.start.raw_offset()..seek.end)?
+        {
+            LineProcessor::new(self.payload_size.line_size(), seek.first_full_ts)
+                .process(&bytes, seek.start.raw_offset(), corruption, processor)?;
+            return Ok(());
+        }
+
+        let mut reader = ChunkedReader::new(seek, self.payload_size);
+        while !reader.is_done() {
+            reader.process_next_chunk(
+                &self.file_handle,
+                None,
+                corruption,
+                &mut processor,
+            )?;
         }
         Ok(())
     }
@@ -0,0 +1,209 @@
+use core::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::file::FileWithHeader;
+use crate::series::compression::Encoding;
+use crate::series::corruption::Series;
+use crate::Timestamp;
+
+use super::checksums::{self, Checksums, Section};
+use super::index::{Entry, Index, PayloadSize};
+use super::inline_meta::FileWithInlineMeta;
+use super::{Data, OpenError};
+
+/// What a writer has made visible to its readers. Only contains
+/// fully written lines.
+pub(crate) struct Published {
+    /// Shared with the writer's index, see [`Index::shared_entries`]
+    entries: Arc<Vec<Entry>>,
+    /// The sections with a checksum, None if the series has no checksums
+    sections: Option<Arc<Vec<Section>>>,
+    data_len: u64,
+    last_time: Option<Timestamp>,
+    /// increased whenever the data changes in another way then lines being
+    /// appended.
+    generation: u64,
+}
+
+impl fmt::Debug for Published {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Published")
+            .field("# entries", &self.entries.len())
+            .field("# sections", &self.sections.as_ref().map(|s| s.len()))
+            .field("data_len", &self.data_len)
+            .field("last_time", &self.last_time)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+impl Published {
+    pub(crate) fn new(data: &Data) -> Self {
+        Self {
+            entries: data.index.shared_entries(),
+            sections: shared_sections(data),
+            data_len: data.data_len,
+            last_time: data.last_time,
+            generation: 0,
+        }
+    }
+
+    /// Makes lines appended to `data` since the last update visible
+    pub(crate) fn update(&mut self, data: &Data) {
+        self.entries = data.index.shared_entries();
+        self.sections = shared_sections(data);
+        self.data_len = data.data_len;
+        self.last_time = data.last_time;
+    }
+
+    pub(crate) fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    /// Use when the data changed in another way then lines being appended
+    pub(crate) fn replace(&mut self, data: &Data) {
+        self.entries = data.index.shared_entries();
+        self.sections = shared_sections(data);
+        self.data_len = data.data_len;
+        self.last_time = data.last_time;
+        self.generation += 1;
+    }
+}
+
+fn shared_sections(data: &Data) -> Option<Arc<Vec<Section>>> {
+    data.file_handle
+        .checksums
+        .as_ref()
+        .map(Checksums::shared_sections)
+}
+
+/// Reads the data of a writer through its own file handles. Only sees what
+/// the writer published.
+#[derive(Debug)]
+pub(crate) struct DataReader {
+    path: PathBuf,
+    payload_size: PayloadSize,
+    map_reads: bool,
+    encoding: Encoding,
+    series: Series,
+    published: Arc<RwLock<Published>>,
+    /// Opened on first use
+    data: Option<Data>,
+    generation: u64,
+}
+
+impl Clone for DataReader {
+    /// The clone opens its own file handles
+    fn clone(&self) -> Self {
+        Self {
+            path: self.path.clone(),
+            payload_size: self.payload_size,
+            map_reads: self.map_reads,
+            encoding: self.encoding.clone(),
+            series: self.series.clone(),
+            published: Arc::clone(&self.published),
+            data: None,
+            generation: 0,
+        }
+    }
+}
+
+impl DataReader {
+    pub(crate) fn new(
+        path: PathBuf,
+        payload_size: PayloadSize,
+        map_reads: bool,
+        encoding: Encoding,
+        series: Series,
+        published: Arc<RwLock<Published>>,
+    ) -> Self {
+        Self {
+            path,
+            payload_size,
+            map_reads,
+            encoding,
+            series,
+            published,
+            data: None,
+            generation: 0,
+        }
+    }
+
+    /// Brings the view of the data up to date with what the writer published.
+    /// The files are opened again if the writer replaced them.
+    pub(crate) fn sync(&mut self) -> Result<&mut Data, OpenError> {
+        // the writer holds this while it replaces the files
+        let published = self
+            .published
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.generation != published.generation {
+            self.data = None;
+        }
+        if self.data.is_none() {
+            self.data = Some(open(
+                self.path.clone(),
+                self.payload_size,
+                self.map_reads,
+                self.encoding.clone(),
+                self.series.clone(),
+            )?);
+        }
+        let data = self.data.as_mut().expect("just set it if it was None");
+
+        data.index.sync_entries(&published.entries);
+        data.file_handle.checksums = published.sections.as_ref().map(|sections| {
+            Checksums::published(checksums::path(&self.path), Arc::clone(sections))
+        });
+        data.data_len = published.data_len;
+        data.last_time = published.last_time;
+        self.generation = published.generation;
+        Ok(data)
+    }
+}
+
+fn open(
+    path: PathBuf,
+    payload_size: PayloadSize,
+    map_reads: bool,
+    encoding: Encoding,
+    series: Series,
+) -> Result<Data, OpenError> {
+    let data_path = path.with_extension("byteseries");
+    let file =
+        FileWithHeader::open_existing_read_only(data_path.clone()).map_err(|source| {
+            OpenError::File {
+                source,
+                path: data_path.clone(),
+            }
+        })?;
+    let mut file = file
+        .split_off_header()
+        .0
+        .open_compressed(encoding, false)
+        .map_err(OpenError::CheckOrRepair)?;
+    if map_reads {
+        file.map_reads().map_err(|e| OpenError::File {
+            source: e.into(),
+            path: data_path,
+        })?;
+    }
+    Ok(Data {
+        file_handle: FileWithInlineMeta {
+            file_handle: file,
+            payload_size,
+            // the checksums come from what the writer published
+            checksums: None,
+            series,
+        },
+        // the entries come from what the writer published
+        index: Index::in_memory(Vec::new()),
+        payload_size,
+        data_len: 0,
+        last_time: None,
+        path,
+        published: None,
+        buffering: None,
+    })
+}
@@ -3,18 +3,19 @@ pub(crate) mod resample;
 
 use std::ffi::OsStr;
 use std::io;
-use std::ops::Bound;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use tracing::instrument;
 
+use super::compression::Encoding;
+use super::corruption::{self, Series};
 use super::data::index::{MetaPos, PayloadSize};
 use super::data::{self, Data};
+use super::repair::RepairPolicy;
 use super::DownSampled;
-use crate::seek::RoughPos;
 use crate::{file, CorruptionCallback, Pos, ResampleState, Resampler, Timestamp};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Config {
     /// reject buckets that have a gap in time larger then this
     pub max_gap: Option<Timestamp>,
@@ -46,6 +47,79 @@ impl Default for Config {
     }
 }
 
+/// The files of every downsampled cache of the series at `source_path`,
+/// whatever config they were created with.
+pub(crate) fn cache_files(source_path: &Path) -> io::Result<Vec<PathBuf>> {
+    Ok(cache_files_with_config(source_path)?
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect())
+}
+
+/// The name, without extension, and config of every downsampled cache of
+/// the series at `source_path`.
+pub(crate) fn caches(source_path: &Path) -> io::Result<Vec<(PathBuf, Config)>> {
+    Ok(cache_files_with_config(source_path)?
+        .into_iter()
+        .filter(|(path, _)| path.extension().is_some_and(|ext| ext == "byteseries"))
+        .map(|(path, config)| (path.with_extension(""), config))
+        .collect())
+}
+
+fn cache_files_with_config(source_path: &Path) -> io::Result<Vec<(PathBuf, Config)>> {
+    let Some(source_name) = source_path.file_name() else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{}_", source_name.to_string_lossy());
+    let dir = match source_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(OsStr::to_str) else {
+            continue;
+        };
+        let Some((suffix, extension)) = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.split_once('.'))
+        else {
+            continue;
+        };
+        if !matches!(
+            extension,
+            "byteseries"
+                | "byteseries_tail"
+                | "byteseries_index"
+                | "byteseries_checksums"
+                | "quarantine"
+        ) {
+            continue;
+        }
+        if let Some(config) = parse_config_suffix(suffix) {
+            files.push((path, config));
+        }
+    }
+    Ok(files)
+}
+
+/// Undoes [`Config::file_name_suffix`]
+fn parse_config_suffix(suffix: &str) -> Option<Config> {
+    let (max_gap, bucket_size) = suffix.rsplit_once('_')?;
+    let max_gap = if max_gap == "None" {
+        None
+    } else {
+        let gap = max_gap.strip_prefix("Some(")?.strip_suffix(')')?;
+        Some(gap.parse::<Timestamp>().ok()?)
+    };
+    Some(Config {
+        max_gap,
+        bucket_size: bucket_size.parse().ok()?,
+    })
+}
+
 #[derive(Debug)]
 pub(crate) struct DownSampledData<R: Resampler> {
     data: Data,
@@ -73,6 +147,8 @@ pub enum CreateError {
         timestamp to do so enable `skipping_over_corrupted_data`"
     )]
     CorruptMetaSection,
+    #[error("The checksum of the meta section at byte {section_start} is wrong")]
+    ChecksumMismatch { section_start: u64 },
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -98,12 +174,32 @@ pub enum OpenOrCreateError {
     Create(#[source] CreateError),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum TruncateError {
+    #[error("Could not truncate the downsampled data")]
+    Data(#[source] data::TruncateError),
+    #[error("Could not downsample the data again")]
+    Repair(#[source] repair::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RebuildError {
+    #[error("Could not empty the downsampled data")]
+    Clear(#[source] data::ClearError),
+    #[error("Could not downsample the data again")]
+    Resample(#[source] repair::Error),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Could not create new data file")]
     Creating(#[source] CreateError),
     #[error("While creating or opening")]
     OpenOrCreate(#[source] OpenOrCreateError),
+    #[error("While truncating")]
+    Truncating(#[source] TruncateError),
+    #[error("While downsampling the compacted data again")]
+    Rebuilding(#[source] RebuildError),
 }
 
 impl<R> DownSampledData<R>
@@ -124,7 +220,14 @@ where
         let mut path = source_path.to_path_buf();
         path.set_file_name(resampled_name);
         Ok(Self {
-            data: Data::new(path, payload_size, config.header(source_name).as_bytes())?,
+            data: Data::new(
+                path,
+                payload_size,
+                config.header(source_name).as_bytes(),
+                Encoding::default(),
+                false,
+                Series::Downsampled(config.clone()),
+            )?,
             resample_state: resampler.state(),
             resampler,
             config,
@@ -141,6 +244,7 @@ where
         source_path: &Path,
         source: &mut Data,
         payload_size: PayloadSize,
+        repair: RepairPolicy,
         corruption_callback: &mut Option<CorruptionCallback>,
     ) -> Result<Self, OpenError> {
         let source_name = source_path.file_name().unwrap_or_default();
@@ -157,8 +261,16 @@ where
             })
             .map_err(OpenError::Data)?;
         let (file, _) = file.split_off_header();
-        let mut data = Data::open_existing(path, file, payload_size, corruption_callback)
-            .map_err(OpenError::Data)?;
+        let mut data = Data::open_existing(
+            path,
+            file,
+            payload_size,
+            false,
+            repair,
+            Series::Downsampled(config.clone()),
+            corruption_callback,
+        )
+        .map_err(OpenError::Data)?;
 
         repair::add_missing_data(
             source,
@@ -201,15 +313,16 @@ where
             first_full_ts,
         };
         let mut prev_ts = 0;
-        let res = source.file_handle.read_with_processor(
-            seek,
-            corruption_callback,
-            |ts, line| {
-                assert!(ts > prev_ts || prev_ts == 0, "ts: {ts}, prev_ts: {prev_ts}");
-                prev_ts = ts;
-                empty.process(ts, line)
-            },
-        );
+        let mut corruption =
+            corruption::Handler::new(corruption_callback, &source.file_handle.series);
+        let res =
+            source
+                .file_handle
+                .read_with_processor(seek, &mut corruption, |ts, line| {
+                    assert!(ts > prev_ts || prev_ts == 0, "ts: {ts}, prev_ts: {prev_ts}");
+                    prev_ts = ts;
+                    empty.process(ts, line)
+                });
 
         match res {
             Ok(()) => Ok(empty),
@@ -222,6 +335,9 @@ where
             Err(data::inline_meta::with_processor::Error::CorruptMetaSection) => {
                 Err(CreateError::CorruptMetaSection)
             }
+            Err(data::inline_meta::with_processor::Error::ChecksumMismatch {
+                section_start,
+            }) => Err(CreateError::ChecksumMismatch { section_start }),
         }
     }
 
@@ -232,6 +348,7 @@ where
         source_path: &Path,
         payload_size: PayloadSize,
         source: &mut Data,
+        repair: RepairPolicy,
         corruption_callback: &mut Option<CorruptionCallback>,
     ) -> Result<Self, OpenOrCreateError> {
         match Self::open(
@@ -240,6 +357,7 @@ where
             source_path,
             source,
             payload_size,
+            repair,
             corruption_callback,
         ) {
             Ok(downsampled) => return Ok(downsampled),
@@ -264,6 +382,32 @@ where
     }
 }
 
+impl<R> DownSampledData<R>
+where
+    R: Resampler + Clone + Send + 'static,
+    R::State: Send + 'static,
+{
+    /// Starts a new bucket and downsamples the lines in `source` that are
+    /// after the last bucket.
+    fn resample_missing(
+        &mut self,
+        source: &mut Data,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<(), repair::Error> {
+        self.resample_state = self.resampler.state();
+        self.ts_sum = 0;
+        self.samples_in_bin = 0;
+        self.debug_tss.clear();
+        repair::add_missing_data(
+            source,
+            &mut self.data,
+            &self.config,
+            &mut self.resampler,
+            corruption_callback,
+        )
+    }
+}
+
 impl<R> DownSampled for DownSampledData<R>
 where
     R: Resampler + Clone + Send + 'static,
@@ -297,14 +441,43 @@ where
         Ok(())
     }
 
-    /// returns an error if
-    fn estimate_lines(
-        &self,
-        start: Bound<Timestamp>,
-        end: Bound<Timestamp>,
-    ) -> Option<crate::seek::Estimate> {
-        let seek = RoughPos::new(&self.data, start, end).ok()?;
-        Some(seek.estimate_lines(self.data.payload_size(), self.data.data_len))
+    fn truncate_after(
+        &mut self,
+        source: &mut Data,
+        ts: Timestamp,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<(), TruncateError> {
+        self.data
+            .truncate_after(ts, corruption_callback)
+            .map_err(TruncateError::Data)?;
+        // the timestamp of a bucket is the average of its lines. The last
+        // bucket can therefore contain lines after ts, downsample it again.
+        if let Some(last) = self.data.last_time() {
+            match last.checked_sub(1) {
+                Some(before_last) => self
+                    .data
+                    .truncate_after(before_last, corruption_callback)
+                    .map_err(TruncateError::Data)?,
+                None => self
+                    .data
+                    .clear()
+                    .map_err(data::TruncateError::Clear)
+                    .map_err(TruncateError::Data)?,
+            }
+        }
+
+        self.resample_missing(source, corruption_callback)
+            .map_err(TruncateError::Repair)
+    }
+
+    fn rebuild(
+        &mut self,
+        source: &mut Data,
+        corruption_callback: &mut Option<CorruptionCallback>,
+    ) -> Result<(), RebuildError> {
+        self.data.clear().map_err(RebuildError::Clear)?;
+        self.resample_missing(source, corruption_callback)
+            .map_err(RebuildError::Resample)
     }
 
     fn data_mut(&mut self) -> &mut Data {
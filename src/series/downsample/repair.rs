@@ -17,7 +17,7 @@ pub enum Error {
         seek::Error,
     ),
     #[error("Could not empty (clear) downsampled data")]
-    ClearingDownsampled(std::io::Error),
+    ClearingDownsampled(#[source] data::ClearError),
     #[error("Could not read from source")]
     ReadingSource(#[source] data::ReadError),
     #[error("Could not add new items to downsampled data")]
@@ -0,0 +1,79 @@
+use core::fmt;
+
+use super::Error;
+use crate::Timestamp;
+
+/// What to do when a line is pushed with the same timestamp as an earlier
+/// line.
+///
+/// # Note
+/// Replacing a line that was already written does not update the
+/// downsampled caches, they keep using the first line.
+///
+/// A line a [`SeriesReader`](crate::SeriesReader) can already see is never
+/// replaced. While readers exist [`Overwrite`] and [`Merge`] act like
+/// [`KeepFirst`] for such a line and log a warning. Buffered lines are only
+/// seen once they are committed, see [`ByteSeries::commit`].
+///
+/// [`Overwrite`]: Self::Overwrite
+/// [`Merge`]: Self::Merge
+/// [`KeepFirst`]: Self::KeepFirst
+/// [`ByteSeries::commit`]: crate::ByteSeries::commit
+#[derive(Default)]
+pub enum DuplicatePolicy {
+    /// Return [`Error::TimeNotAfterLast`]
+    #[default]
+    Reject,
+    /// Drop the new line
+    KeepFirst,
+    /// Replace the earlier line with the new one
+    Overwrite,
+    /// Replace the earlier line with the result of this function. It is
+    /// passed the payload of the earlier and then the new line. It must return
+    /// a payload of the same length.
+    Merge(MergeFn),
+}
+
+/// Gets the payload of the earlier line then that of the new line, returns
+/// the merged payload.
+pub type MergeFn = Box<dyn FnMut(&[u8], &[u8]) -> Vec<u8> + Send>;
+
+impl fmt::Debug for DuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reject => write!(f, "Reject"),
+            Self::KeepFirst => write!(f, "KeepFirst"),
+            Self::Overwrite => write!(f, "Overwrite"),
+            Self::Merge(_) => write!(f, "Merge(..)"),
+        }
+    }
+}
+
+impl DuplicatePolicy {
+    /// Returns the payload that should replace the earlier line, None if it
+    /// should be kept. The earlier line is only fetched if needed.
+    pub(crate) fn resolve(
+        &mut self,
+        ts: Timestamp,
+        earlier: impl FnOnce() -> Result<Vec<u8>, Error>,
+        new: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            Self::Reject => Err(Error::TimeNotAfterLast { new: ts, prev: ts }),
+            Self::KeepFirst => Ok(None),
+            Self::Overwrite => Ok(Some(new.to_vec())),
+            Self::Merge(merge) => {
+                let earlier = earlier()?;
+                let merged = merge(&earlier, new);
+                if merged.len() == earlier.len() {
+                    Ok(Some(merged))
+                } else {
+                    Err(Error::WrongLineLength {
+                        required: earlier.len(),
+                        got: merged.len(),
+                    })
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+/// How often a [`ByteSeries`](crate::ByteSeries) waits for the OS to write
+/// pushed lines to disk. This covers the downsampled caches too.
+///
+/// Waiting is slow, the less often it happens the faster pushing is and the
+/// more lines can be lost on a crash of the OS or power failure.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Leave it to the OS, or call
+    /// [`flush_to_disk`](crate::ByteSeries::flush_to_disk) yourself.
+    #[default]
+    None,
+    /// After every `n` lines pushed
+    EveryN(usize),
+    /// On the first push at least this long after the last time
+    Interval(Duration),
+    /// After every call to push_line(s)
+    EveryPush,
+}
+
+/// Tracks when the [`Durability`] policy requires a flush
+#[derive(Debug)]
+pub(crate) struct Tracker {
+    policy: Durability,
+    unflushed_lines: usize,
+    last_flush: Instant,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new(Durability::default())
+    }
+}
+
+impl Tracker {
+    pub(crate) fn new(policy: Durability) -> Self {
+        Self {
+            policy,
+            unflushed_lines: 0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Returns true if the lines should be flushed to disk now
+    pub(crate) fn pushed(&mut self, lines: usize) -> bool {
+        self.unflushed_lines += lines;
+        match self.policy {
+            Durability::None => false,
+            Durability::EveryN(n) => self.unflushed_lines >= n,
+            Durability::Interval(interval) => self.last_flush.elapsed() >= interval,
+            Durability::EveryPush => true,
+        }
+    }
+
+    pub(crate) fn flushed(&mut self) {
+        self.unflushed_lines = 0;
+        self.last_flush = Instant::now();
+    }
+}
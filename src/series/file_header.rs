@@ -3,28 +3,137 @@ use std::str::Utf8Error;
 
 use crate::builder::PayloadSizeOption;
 
+use super::compression::{self, Compression, Encoding, Field, Layout};
 use super::data::index::PayloadSize;
 
-const VERSION: u16 = 1;
+/// Version 2 and later store the parameters in a binary block
+pub(crate) const VERSION: u16 = 2;
+const V1: u16 = 1;
 
-#[derive(Copy, Clone)]
+/// Starts the header of version 2 and later. A version 1 header starts with
+/// the length of its text as u32. That is less then 2^16 therefore its third
+/// and fourth byte are zero, which is never the case for the magic.
+const MAGIC: &[u8; 10] = b"byteseries";
+
+/// Tags of the records in the binary parameter block. Each record is a one
+/// byte tag followed by the length of its value as u16 and the value.
+mod tag {
+    pub(super) const PAYLOAD_SIZE: u8 = 1;
+    /// Not present if the file is not compressed
+    pub(super) const COMPRESSION: u8 = 2;
+    /// Not present if there is no payload layout
+    pub(super) const LAYOUT: u8 = 3;
+    /// Has no value, only present if the meta sections are checksummed
+    pub(super) const CHECKSUMS: u8 = 4;
+    /// The level as i32, only present if the compression has one
+    pub(super) const COMPRESSION_LEVEL: u8 = 5;
+}
+
+#[derive(Clone)]
 pub(crate) struct SeriesParams {
     pub(crate) payload_size: usize,
-    pub(crate) version: u16,
+    pub(crate) encoding: Encoding,
+    /// Every meta section and the lines up to the next section have a crc32
+    /// checksum
+    pub(crate) checksums: bool,
 }
 
 impl SeriesParams {
-    pub(crate) fn to_text(self) -> Vec<u8> {
+    /// The header has the following layout:
+    ///  - the magic: `byteseries`
+    ///  - the format version as u16
+    ///  - the length of the parameter block as u16
+    ///  - the parameter block, see [`tag`]
+    ///  - a crc32 checksum over the version, length and parameter block
+    ///  - the length of a text preamble as u32
+    ///  - the text preamble, it explains the format to a human and is
+    ///    ignored while reading
+    ///
+    /// All integers are little endian.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let params = self.param_block();
+        let params_len =
+            u16::try_from(params.len()).expect("there are only a few small parameters");
+
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&params_len.to_le_bytes());
+        header.extend_from_slice(&params);
+        let checksum = crc32fast::hash(&header[MAGIC.len()..]);
+        header.extend_from_slice(&checksum.to_le_bytes());
+
+        let text = self.to_text();
+        header.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        header.extend_from_slice(text.as_bytes());
+        header
+    }
+
+    fn param_block(&self) -> Vec<u8> {
+        let mut block = Vec::new();
+        let mut push = |tag: u8, value: &[u8]| {
+            block.push(tag);
+            let len = u16::try_from(value.len()).expect("parameters are small");
+            block.extend_from_slice(&len.to_le_bytes());
+            block.extend_from_slice(value);
+        };
+
+        push(tag::PAYLOAD_SIZE, &(self.payload_size as u64).to_le_bytes());
+        if !self.encoding.compression.is_none() {
+            push(
+                tag::COMPRESSION,
+                self.encoding.compression.name().as_bytes(),
+            );
+        }
+        if let Some(level) = self.encoding.compression.level() {
+            push(tag::COMPRESSION_LEVEL, &level.to_le_bytes());
+        }
+        if let Some(layout) = &self.encoding.layout {
+            let ids: Vec<_> = layout.fields().iter().map(|f| f.id()).collect();
+            push(tag::LAYOUT, &ids);
+        }
+        if self.checksums {
+            push(tag::CHECKSUMS, &[]);
+        }
+        block
+    }
+
+    fn to_text(&self) -> String {
         let Self {
             payload_size,
-            version,
+            encoding,
+            checksums,
         } = self;
+        let mut compression = if encoding.is_none() {
+            String::new()
+        } else {
+            format!(
+                " The lines are grouped into blocks that are\n    \
+                compressed using: {}.",
+                encoding.compression.name()
+            )
+        };
+        if let Some(layout) = &encoding.layout {
+            let fields: Vec<_> = layout.fields().iter().map(|f| f.name()).collect();
+            compression.push_str(&format!(
+                " Before compressing each payload is\n    \
+                encoded relative to the one before it. Its fields are: {}.",
+                fields.join(", ")
+            ));
+        }
+        if *checksums {
+            compression.push_str(
+                " Each meta section and the lines up to\n    \
+                the next one have a crc32 checksum. These are stored in the file\n    \
+                ending in: byteseries_checksums.",
+            );
+        }
         let text = format!(
-            "\nNote: NUMB_LINES line ASCII preamble followed by binary data.
+            "\nNote: NUMB_LINES line ASCII preamble followed by binary data. The
+    parameters below are read from the binary block before this preamble.
 
-    This is a byteseries {version} file, an embedded timeseries file. Time may here may
+    This is a byteseries {VERSION} file, an embedded timeseries file. Time may here may
     be whatever value as long as it is monotonically increasing. The entries
-    have a fixed length that never changes. For this file that is: {payload_size} bytes.
+    have a fixed length that never changes. For this file that is: {payload_size} bytes.{compression}
 
     The 'time' is stored as a 16 bit value for most entries. A line is a 16 bit
     little endian time followed by the entry. The 16 bit time is the number of
@@ -50,23 +159,106 @@ impl SeriesParams {
         );
 
         let n_lines = text.lines().count();
-        let text = text.replace("NUMB_LINES", &n_lines.to_string());
-
-        let length = text.len() as u32;
-        let mut header = length.to_le_bytes().to_vec();
-        header.extend_from_slice(text.as_bytes());
-        header
+        text.replace("NUMB_LINES", &n_lines.to_string())
     }
 
-    pub(crate) fn from_text(text: &str) -> Result<Self, ParseError> {
+    /// Parses the parameters of a version 1 header, they are stored in text.
+    /// Version 1 files are never compressed.
+    fn from_text(text: &str) -> Result<(u16, Self), Error> {
         let version = parse_version(text)?;
         let payload_size = parse_payload_size(text)?;
 
-        Ok(Self {
+        let params = Self {
             payload_size,
-            version,
-        })
+            encoding: Encoding::default(),
+            checksums: false,
+        };
+        Ok((version, params))
+    }
+
+    /// Parses the binary parameter block of a header of version 2 or later,
+    /// see [`to_bytes`](Self::to_bytes).
+    fn from_bytes(header: &[u8]) -> Result<(u16, Self, usize), Error> {
+        let mut rest = &header[MAGIC.len()..];
+        let checked = rest;
+        let version = u16::from_le_bytes(take_array(&mut rest)?);
+        let params_len = u16::from_le_bytes(take_array(&mut rest)?) as usize;
+        let mut block = take(&mut rest, params_len)?;
+        let checked = &checked[..checked.len() - rest.len()];
+        let stored = u32::from_le_bytes(take_array(&mut rest)?);
+        let computed = crc32fast::hash(checked);
+        if stored != computed {
+            return Err(ParseError::ChecksumMismatch { stored, computed })?;
+        }
+        let text_len = u32::from_le_bytes(take_array(&mut rest)?) as usize;
+        take(&mut rest, text_len)?;
+
+        let mut payload_size = None;
+        let mut compression = Compression::None;
+        let mut level = None;
+        let mut layout = None;
+        let mut checksums = false;
+        while !block.is_empty() {
+            let [tag] = take_array(&mut block)?;
+            let len = u16::from_le_bytes(take_array(&mut block)?) as usize;
+            let value = take(&mut block, len)?;
+            match tag {
+                tag::PAYLOAD_SIZE => {
+                    let value =
+                        value.try_into().map_err(|_| ParseError::Malformed(tag))?;
+                    payload_size = Some(u64::from_le_bytes(value) as usize);
+                }
+                tag::COMPRESSION => {
+                    let name = core::str::from_utf8(value)
+                        .map_err(|_| ParseError::Malformed(tag))?;
+                    compression = Compression::from_name(name)?;
+                }
+                tag::LAYOUT => {
+                    let fields = value
+                        .iter()
+                        .map(|id| {
+                            Field::from_id(*id).ok_or(ParseError::UnknownFieldId(*id))
+                        })
+                        .collect::<Result<_, _>>()?;
+                    layout = Some(Layout::new(fields));
+                }
+                tag::CHECKSUMS => checksums = true,
+                tag::COMPRESSION_LEVEL => {
+                    let value =
+                        value.try_into().map_err(|_| ParseError::Malformed(tag))?;
+                    level = Some(i32::from_le_bytes(value));
+                }
+                other => return Err(ParseError::UnknownParameter(other))?,
+            }
+        }
+
+        if let Some(level) = level {
+            compression = compression.with_level(level);
+        }
+
+        let params = Self {
+            payload_size: payload_size.ok_or(ParseError::MissingPayloadSize)?,
+            encoding: Encoding {
+                compression,
+                layout,
+            },
+            checksums,
+        };
+        Ok((version, params, header.len() - rest.len()))
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], ParseError> {
+    if bytes.len() < n {
+        return Err(ParseError::Truncated);
     }
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn take_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], ParseError> {
+    Ok(take(bytes, N)?.try_into().expect("take returns N bytes"))
 }
 
 fn parse_version(text: &str) -> Result<u16, ParseError> {
@@ -107,6 +299,21 @@ pub enum ParseError {
     MissingPayloadEnd,
     #[error("Could not parse payload size: {0}")]
     ParsePayload(ParseIntError),
+    #[error("The binary parameter block ends before all parameters are read")]
+    Truncated,
+    #[error(
+        "The checksum of the parameters is wrong, the header is corrupt. \
+        Stored: {stored:x}, computed: {computed:x}"
+    )]
+    ChecksumMismatch { stored: u32, computed: u32 },
+    #[error("Unknown parameter with tag {0}, was the file made by a newer version?")]
+    UnknownParameter(u8),
+    #[error("The value of the parameter with tag {0} is malformed")]
+    Malformed(u8),
+    #[error("Unknown field id in payload layout: {0}")]
+    UnknownFieldId(u8),
+    #[error("The binary parameter block has no payload size")]
+    MissingPayloadSize,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -131,13 +338,33 @@ pub enum Error {
     TooShort,
     #[error("Should be valid utf8 however: {0}")]
     NotText(Utf8Error),
+    #[error("Can not read the data")]
+    Compression(
+        #[from]
+        #[source]
+        compression::UnsupportedError,
+    ),
 }
 
-pub(crate) fn check_and_split_off_user_header(
-    mut header: Vec<u8>,
-    payload_size_option: PayloadSizeOption,
-) -> Result<(PayloadSize, Vec<u8>), Error> {
-    let text_len = header[0..4].try_into().map_err(|_| Error::TooShort)?;
+/// Reads the parameters at the start of `header`. Returns the version of the
+/// file format, the parameters and where the user header starts.
+fn parse(header: &[u8]) -> Result<(u16, SeriesParams, usize), Error> {
+    if header.starts_with(MAGIC) {
+        let (version, params, user_header_start) = SeriesParams::from_bytes(header)?;
+        if version > VERSION {
+            return Err(Error::VersionMismatch {
+                needed: VERSION,
+                file: version,
+            });
+        }
+        return Ok((version, params, user_header_start));
+    }
+
+    let text_len = header
+        .get(0..4)
+        .ok_or(Error::TooShort)?
+        .try_into()
+        .expect("slice is 4 long");
     let text_len = u32::from_le_bytes(text_len) as usize;
 
     assert!(
@@ -146,14 +373,21 @@ pub(crate) fn check_and_split_off_user_header(
     );
     let text = &header[4..text_len];
     let text = core::str::from_utf8(text).map_err(Error::NotText)?;
-    let params = SeriesParams::from_text(text)?;
-
-    if params.version != VERSION {
+    let (version, params) = SeriesParams::from_text(text)?;
+    if version != V1 {
         return Err(Error::VersionMismatch {
-            needed: VERSION,
-            file: params.version,
+            needed: V1,
+            file: version,
         });
     }
+    Ok((version, params, text_len + core::mem::size_of::<u32>()))
+}
+
+pub(crate) fn check_and_split_off_user_header(
+    mut header: Vec<u8>,
+    payload_size_option: PayloadSizeOption,
+) -> Result<(PayloadSize, Encoding, bool, Vec<u8>), Error> {
+    let (_, params, user_header_start) = parse(&header)?;
 
     match payload_size_option {
         PayloadSizeOption::MustMatch(configured) if params.payload_size != configured => {
@@ -165,7 +399,20 @@ pub(crate) fn check_and_split_off_user_header(
         PayloadSizeOption::MustMatch(_) | PayloadSizeOption::Ignore => (),
     }
 
-    header.drain(0..text_len + core::mem::size_of::<u32>());
+    header.drain(0..user_header_start);
     let payload_size = PayloadSize::from_raw(params.payload_size);
-    Ok((payload_size, header))
+    Ok((payload_size, params.encoding, params.checksums, header))
+}
+
+/// The complete header in the current format. Returns `None` if `header` is
+/// already in the current format.
+pub(crate) fn upgrade(header: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let (version, params, user_header_start) = parse(header)?;
+    if version == VERSION {
+        return Ok(None);
+    }
+
+    let mut upgraded = params.to_bytes();
+    upgraded.extend_from_slice(&header[user_header_start..]);
+    Ok(Some(upgraded))
 }
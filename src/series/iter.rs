@@ -0,0 +1,365 @@
+use std::collections::{btree_map, VecDeque};
+
+use tracing::warn;
+
+use crate::file::OffsetFile;
+use crate::series::corruption::{self, Skipped};
+use crate::series::data::index::{Index, PayloadSize};
+use crate::series::data::inline_meta::with_processor::{ChunkedReader, Error};
+use crate::series::data::inline_meta::{meta, FileWithInlineMeta, ReadAt};
+use crate::series::data::ReadError;
+use crate::{CorruptionCallback, Decoder, Pos, Timestamp};
+
+/// Lazily reads the lines in a range, oldest first. Created by
+/// [`ByteSeries::iter_range`](crate::ByteSeries::iter_range).
+///
+/// Reads one chunk (16 KiB) at the time and only decodes the lines in that
+/// chunk. Memory use therefore does not depend on the length of the range.
+/// If the series has checksums each section is checked before its lines are
+/// decoded.
+pub struct Iter<'a, D: Decoder> {
+    file: &'a FileWithInlineMeta<OffsetFile>,
+    corruption: corruption::Handler<'a>,
+    decoder: &'a mut D,
+    reader: Option<ChunkedReader>,
+    decoded: VecDeque<(Timestamp, D::Item)>,
+    /// returned after the lines decoded before it ran into it
+    error: Option<ReadError>,
+    /// lines not yet written, returned after those in the file
+    pending: Option<btree_map::Range<'a, Timestamp, Vec<u8>>>,
+}
+
+impl<D: Decoder> std::fmt::Debug for Iter<'_, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Iter")
+            .field("file", &self.file)
+            .field("reader", &self.reader)
+            .field("# decoded", &self.decoded.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, D: Decoder> Iter<'a, D> {
+    /// `reader` is None if there is nothing to read
+    pub(crate) fn new(
+        file: &'a FileWithInlineMeta<OffsetFile>,
+        corruption_callback: &'a mut Option<CorruptionCallback>,
+        decoder: &'a mut D,
+        reader: Option<ChunkedReader>,
+    ) -> Self {
+        Self {
+            file,
+            corruption: corruption::Handler::new(corruption_callback, &file.series),
+            decoder,
+            reader,
+            decoded: VecDeque::new(),
+            error: None,
+            pending: None,
+        }
+    }
+
+    /// Also return these lines, after those in the file
+    pub(crate) fn with_pending(
+        mut self,
+        pending: Option<btree_map::Range<'a, Timestamp, Vec<u8>>>,
+    ) -> Self {
+        self.pending = pending;
+        self
+    }
+
+    /// The corrupt data skipped so far
+    #[must_use]
+    pub fn skipped(&self) -> Skipped {
+        self.corruption.skipped
+    }
+}
+
+impl<D: Decoder> Iterator for Iter<'_, D> {
+    type Item = Result<(Timestamp, D::Item), ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(decoded) = self.decoded.pop_front() {
+                return Some(Ok(decoded));
+            }
+
+            if let Some(error) = self.error.take() {
+                return Some(Err(error));
+            }
+
+            let Some(reader) = self.reader.as_mut() else {
+                let (ts, line) = self.pending.as_mut()?.next()?;
+                return Some(Ok((*ts, self.decoder.decode_payload(line))));
+            };
+            if reader.is_done() {
+                self.reader = None;
+                continue;
+            }
+
+            let Self {
+                file,
+                corruption,
+                decoder,
+                decoded,
+                ..
+            } = self;
+            let res = reader.process_next_chunk::<()>(
+                &file.file_handle,
+                file.checksums.as_ref(),
+                corruption,
+                |ts, payload| {
+                    decoded.push_back((ts, decoder.decode_payload(payload)));
+                    Ok(())
+                },
+            );
+
+            if let Err(e) = res {
+                self.reader = None;
+                self.pending = None;
+                self.error = Some(match e {
+                    Error::Io(error) => ReadError::Io(error),
+                    Error::Processor(()) => {
+                        unreachable!("this processor never returns an error")
+                    }
+                    Error::CorruptMetaSection => ReadError::CorruptMetaSection,
+                    Error::ChecksumMismatch { section_start } => {
+                        ReadError::ChecksumMismatch { section_start }
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Lazily reads the lines in a range, newest first. Created by
+/// [`ByteSeries::iter_range_rev`](crate::ByteSeries::iter_range_rev).
+///
+/// Walks backwards through the data one chunk (16 KiB) at the time. Meta
+/// sections can not be decoded backwards, the full timestamp for each line
+/// is therefore taken from the index. If the series has checksums each
+/// section is checked before its first chunk is read.
+pub struct RevIter<'a, D: Decoder> {
+    file: &'a FileWithInlineMeta<OffsetFile>,
+    index: &'a Index,
+    corruption: corruption::Handler<'a>,
+    decoder: &'a mut D,
+    payload_size: PayloadSize,
+    chunk_size: u64,
+    /// start of the first (oldest) line that should be read
+    start: u64,
+    /// position in the index of the meta section whose lines we are reading,
+    /// None once everything has been read
+    section: Option<usize>,
+    /// the lines in the current section before this have not yet been read
+    section_read_end: u64,
+    /// position in the index of the last section checked against its checksum
+    checked: Option<usize>,
+    buf: Vec<u8>,
+    decoded: VecDeque<(Timestamp, D::Item)>,
+    /// returned after the lines decoded before it ran into it
+    error: Option<ReadError>,
+    /// lines not yet written, returned before those in the file
+    pending: Option<btree_map::Range<'a, Timestamp, Vec<u8>>>,
+}
+
+impl<D: Decoder> std::fmt::Debug for RevIter<'_, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RevIter")
+            .field("file", &self.file)
+            .field("start", &self.start)
+            .field("section", &self.section)
+            .field("section_read_end", &self.section_read_end)
+            .field("# decoded", &self.decoded.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, D: Decoder> RevIter<'a, D> {
+    /// `seek` is None if there is nothing to read
+    pub(crate) fn new(
+        file: &'a FileWithInlineMeta<OffsetFile>,
+        index: &'a Index,
+        corruption_callback: &'a mut Option<CorruptionCallback>,
+        decoder: &'a mut D,
+        seek: Option<Pos>,
+    ) -> Self {
+        let payload_size = file.payload_size;
+        let (start, section, section_read_end) = match seek {
+            Some(seek) => (
+                seek.start.raw_offset(),
+                index.last_section_before(seek.end),
+                seek.end,
+            ),
+            None => (0, None, 0),
+        };
+
+        Self {
+            file,
+            index,
+            corruption: corruption::Handler::new(corruption_callback, &file.series),
+            decoder,
+            payload_size,
+            chunk_size: 16384u64.next_multiple_of(payload_size.line_size() as u64),
+            start,
+            section,
+            section_read_end,
+            checked: None,
+            buf: Vec::new(),
+            decoded: VecDeque::new(),
+            error: None,
+            pending: None,
+        }
+    }
+
+    /// Also return these lines, before those in the file
+    pub(crate) fn with_pending(
+        mut self,
+        pending: Option<btree_map::Range<'a, Timestamp, Vec<u8>>>,
+    ) -> Self {
+        self.pending = pending;
+        self
+    }
+
+    /// The corrupt data skipped so far
+    #[must_use]
+    pub fn skipped(&self) -> Skipped {
+        self.corruption.skipped
+    }
+
+    /// Decodes the chunk of lines just before `section_read_end`. Moves on
+    /// to the previous meta section once the current one is read.
+    fn read_chunk(&mut self) -> Result<(), ReadError> {
+        let Some(section) = self.section else {
+            return Ok(());
+        };
+        let index = self.index;
+        let entry = &index.entries()[section];
+        let section_start = entry
+            .meta_start
+            .line_start(self.payload_size)
+            .raw_offset()
+            .max(self.start);
+        if self.checked != Some(section) {
+            self.checked = Some(section);
+            let meta_start = entry.meta_start.raw_offset();
+            if !self.check_section(meta_start, section_start, entry.timestamp)? {
+                // nothing left to read in it, moves on to the one before
+                self.section_read_end = section_start;
+            }
+        }
+        let chunk_start = self
+            .section_read_end
+            .saturating_sub(self.chunk_size)
+            .max(section_start);
+
+        if chunk_start < self.section_read_end {
+            let chunk_len = usize::try_from(self.section_read_end - chunk_start)
+                .expect("chunk_size fits in usize");
+            self.buf.resize(chunk_len, 0);
+            self.file
+                .file_handle
+                .read_exact_at(&mut self.buf, chunk_start)
+                .map_err(ReadError::Io)?;
+
+            let line_size = self.payload_size.line_size();
+            let lines = self.buf.chunks_exact(line_size).enumerate().rev();
+            for (i, line) in lines {
+                if line[..2] == meta::PREAMBLE {
+                    // the index tells us where the meta sections are, this
+                    // can only be a corrupt line.
+                    let offset = chunk_start + (i * line_size) as u64;
+                    if self.corruption.skip(offset, entry.timestamp, 1) {
+                        continue;
+                    } else {
+                        return Err(ReadError::CorruptMetaSection);
+                    }
+                }
+                let small_ts: [u8; 2] = line[..2].try_into().expect("slice len is 2");
+                let ts = entry.timestamp + u64::from(u16::from_le_bytes(small_ts));
+                let item = self.decoder.decode_payload(&line[2..]);
+                self.decoded.push_back((ts, item));
+            }
+        }
+
+        self.section_read_end = chunk_start;
+        if chunk_start <= section_start {
+            self.section = if section_start > self.start {
+                section.checked_sub(1)
+            } else {
+                None
+            };
+            self.section_read_end = entry.meta_start.raw_offset();
+        }
+        Ok(())
+    }
+
+    /// Checks the section starting at `meta_start` against its checksum.
+    /// Returns false if it is corrupt and the corruption callback allowed
+    /// skipping its lines from `read_from` on.
+    fn check_section(
+        &mut self,
+        meta_start: u64,
+        read_from: u64,
+        meta_ts: Timestamp,
+    ) -> Result<bool, ReadError> {
+        let Some(checksums) = &self.file.checksums else {
+            return Ok(true);
+        };
+        // the last section has no checksum yet
+        let Some(section) = checksums
+            .overlapping(meta_start..meta_start + 1)
+            .first()
+            .filter(|section| section.start == meta_start)
+        else {
+            return Ok(true);
+        };
+        let correct = self
+            .file
+            .file_handle
+            .with_bytes_at(section.range(), |b| crc32fast::hash(b) == section.crc)
+            .map_err(ReadError::Io)?;
+        if correct {
+            return Ok(true);
+        }
+
+        warn!("checksum of meta section at {meta_start} is wrong");
+        let line_size = self.payload_size.line_size() as u64;
+        let to_skip = self.section_read_end.saturating_sub(read_from) / line_size;
+        if self.corruption.skip(meta_start, meta_ts, to_skip) {
+            Ok(false)
+        } else {
+            Err(ReadError::ChecksumMismatch {
+                section_start: meta_start,
+            })
+        }
+    }
+}
+
+impl<D: Decoder> Iterator for RevIter<'_, D> {
+    type Item = Result<(Timestamp, D::Item), ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(pending) = &mut self.pending {
+            if let Some((ts, line)) = pending.next_back() {
+                return Some(Ok((*ts, self.decoder.decode_payload(line))));
+            }
+            self.pending = None;
+        }
+
+        loop {
+            if let Some(decoded) = self.decoded.pop_front() {
+                return Some(Ok(decoded));
+            }
+
+            if let Some(error) = self.error.take() {
+                return Some(Err(error));
+            }
+
+            self.section?;
+            if let Err(error) = self.read_chunk() {
+                self.section = None;
+                self.error = Some(error);
+            }
+        }
+    }
+}
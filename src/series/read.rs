@@ -0,0 +1,212 @@
+//! The read operations shared by [`ByteSeries`](super::ByteSeries) and
+//! [`SeriesReader`](super::reader::SeriesReader).
+
+use std::ops::{Bound, RangeBounds};
+
+use tracing::instrument;
+
+use super::data::Data;
+use super::iter::{Iter, RevIter};
+use super::Error;
+use crate::seek::{self, Estimate, Pos};
+use crate::series::corruption::Skipped;
+use crate::series::data::inline_meta::with_processor::ChunkedReader;
+use crate::{CorruptionCallback, Decoder, Resampler, Timestamp};
+
+fn find_pos(
+    data: &Data,
+    range: &impl RangeBounds<Timestamp>,
+) -> Result<Option<Pos>, Error> {
+    let pos = seek::RoughPos::new(
+        data,
+        range.start_bound().cloned(),
+        range.end_bound().cloned(),
+    )
+    .map_err(Error::InvalidRange)?
+    .refine(data)
+    .map_err(Error::Seeking)?;
+
+    if pos.is_none() {
+        tracing::debug!(
+            "No data to read within given range, probably due to \
+            a gap in the data."
+        );
+    }
+    Ok(pos)
+}
+
+pub(crate) fn all<D: Decoder>(
+    data: &Data,
+    corruption_callback: &mut Option<CorruptionCallback>,
+    range: impl RangeBounds<Timestamp>,
+    decoder: &mut D,
+    timestamps: &mut Vec<Timestamp>,
+    items: &mut Vec<D::Item>,
+) -> Result<Skipped, Error> {
+    let Some(seek) = find_pos(data, &range)? else {
+        return Ok(Skipped::default());
+    };
+
+    data.read_all(seek, corruption_callback, decoder, timestamps, items)
+        .map_err(Error::Reading)
+}
+
+pub(crate) fn iter<'a, D: Decoder>(
+    data: &'a Data,
+    corruption_callback: &'a mut Option<CorruptionCallback>,
+    range: impl RangeBounds<Timestamp>,
+    decoder: &'a mut D,
+) -> Result<Iter<'a, D>, Error> {
+    let reader =
+        find_pos(data, &range)?.map(|seek| ChunkedReader::new(seek, data.payload_size()));
+    Ok(Iter::new(
+        &data.file_handle,
+        corruption_callback,
+        decoder,
+        reader,
+    ))
+}
+
+pub(crate) fn iter_rev<'a, D: Decoder>(
+    data: &'a Data,
+    corruption_callback: &'a mut Option<CorruptionCallback>,
+    range: impl RangeBounds<Timestamp>,
+    decoder: &'a mut D,
+) -> Result<RevIter<'a, D>, Error> {
+    let seek = find_pos(data, &range)?;
+    Ok(RevIter::new(
+        &data.file_handle,
+        &data.index,
+        corruption_callback,
+        decoder,
+        seek,
+    ))
+}
+
+pub(crate) fn n_lines_between(
+    data: &Data,
+    range: impl RangeBounds<Timestamp>,
+) -> Result<u64, Error> {
+    let start = range.start_bound().cloned();
+    let end = range.end_bound().cloned();
+
+    let pos = match seek::RoughPos::new(data, start, end) {
+        Ok(pos) => pos,
+        Err(seek::Error::EmptyFile) => return Ok(0),
+        Err(other) => return Err(Error::InvalidRange(other)),
+    };
+
+    Ok(pos
+        .refine(data)
+        .map_err(Error::Seeking)?
+        .map(|pos| pos.lines(data))
+        .unwrap_or(0))
+}
+
+fn estimate_lines(
+    data: &Data,
+    start: Bound<Timestamp>,
+    end: Bound<Timestamp>,
+) -> Option<Estimate> {
+    let seek = seek::RoughPos::new(data, start, end).ok()?;
+    Some(seek.estimate_lines(data.payload_size(), data.data_len))
+}
+
+/// `downsampled` must be sorted in descending resolution
+#[instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn n<'a, R: Resampler>(
+    mut optimal_data: &'a Data,
+    downsampled: impl IntoIterator<Item = &'a Data>,
+    corruption_callback: &mut Option<CorruptionCallback>,
+    n: usize,
+    range: impl RangeBounds<Timestamp>,
+    resampler: &mut R,
+    timestamps: &mut Vec<Timestamp>,
+    items: &mut Vec<<R as Decoder>::Item>,
+) -> Result<Skipped, Error> {
+    let start = range.start_bound().cloned();
+    let end = range.end_bound().cloned();
+
+    for downsampled in downsampled {
+        let Some(estimate) = estimate_lines(downsampled, start, end) else {
+            break; // more downsampled files are empty
+        };
+        if estimate.max < n as u64 {
+            tracing::debug!(
+                "not enough datapoints, not using next\
+                downsamled cache, estimate was: {estimate:?}"
+            );
+            break;
+        }
+        if estimate.min < n as u64 {
+            tracing::debug!(
+                "possibly not enough datapoints, not using \
+                next level downsamled cache, estimate was: {estimate:?}"
+            );
+            break;
+        }
+        tracing::debug!("using downsampled data: {downsampled:?}");
+        optimal_data = downsampled;
+    }
+
+    let Some(seek) = find_pos(optimal_data, &range)? else {
+        return Ok(Skipped::default());
+    };
+
+    let lines = seek.lines(optimal_data);
+    let bucket_size = 1.max(lines / n as u64);
+    let bucket_size =
+        usize::try_from(bucket_size).map_err(|_| Error::TooMuchToResample)?;
+
+    optimal_data
+        .read_resampling(
+            seek,
+            corruption_callback,
+            resampler,
+            bucket_size,
+            timestamps,
+            items,
+        )
+        .map_err(Error::Reading)
+}
+
+pub(crate) fn first_n<D: Decoder>(
+    data: &Data,
+    corruption_callback: &mut Option<CorruptionCallback>,
+    n: usize,
+    decoder: &mut D,
+    range: impl RangeBounds<Timestamp>,
+    timestamps: &mut Vec<Timestamp>,
+    items: &mut Vec<D::Item>,
+) -> Result<Skipped, Error> {
+    let Some(seek) = find_pos(data, &range)? else {
+        return Ok(Skipped::default());
+    };
+
+    data.read_first_n(n, seek, corruption_callback, decoder, timestamps, items)
+        .map_err(Error::Reading)
+}
+
+pub(crate) fn last_n<D: Decoder>(
+    data: &Data,
+    corruption_callback: &mut Option<CorruptionCallback>,
+    n: usize,
+    range: impl RangeBounds<Timestamp>,
+    decoder: &mut D,
+    timestamps: &mut Vec<Timestamp>,
+    items: &mut Vec<D::Item>,
+) -> Result<Skipped, Error> {
+    let mut iter = iter_rev(data, corruption_callback, range, decoder)?;
+    let newest_first: Vec<_> = iter
+        .by_ref()
+        .take(n)
+        .collect::<Result<_, _>>()
+        .map_err(Error::Reading)?;
+
+    for (ts, item) in newest_first.into_iter().rev() {
+        timestamps.push(ts);
+        items.push(item);
+    }
+    Ok(iter.skipped())
+}
@@ -0,0 +1,265 @@
+use core::fmt;
+use std::ops::RangeBounds;
+
+use super::corruption::Skipped;
+use super::data;
+use super::data::reader::DataReader;
+use super::iter::{Iter, RevIter};
+use super::{read, Error};
+use crate::{CorruptionCallback, Decoder, Resampler, Timestamp};
+
+/// Reads a [`ByteSeries`](super::ByteSeries) while it is being appended to,
+/// for example from another thread. Get one using
+/// [`ByteSeries::reader`](super::ByteSeries::reader).
+///
+/// Every read sees all lines pushed before it started. Lines pushed during
+/// a read may or may not be returned.
+///
+/// A series can also be opened as just a reader using
+/// [`read_only`](crate::builder::ByteSeriesBuilder::read_only).
+///
+/// # Note
+/// A reader never repairs the data. It only calls the corruption callback
+/// if it was opened read only, clones do not. Corruption the writer did not
+/// repair when opening is returned as an error.
+pub struct SeriesReader {
+    data: DataReader,
+    downsampled: Vec<DataReader>,
+    /// None unless opened read only, readers do not handle corruption
+    corruption_callback: Option<CorruptionCallback>,
+}
+
+impl fmt::Debug for SeriesReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeriesReader")
+            .field("data", &self.data)
+            .field("downsampled", &self.downsampled)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Clone for SeriesReader {
+    fn clone(&self) -> Self {
+        Self::new(self.data.clone(), self.downsampled.clone())
+    }
+}
+
+impl SeriesReader {
+    pub(crate) fn new(data: DataReader, downsampled: Vec<DataReader>) -> Self {
+        Self {
+            data,
+            downsampled,
+            corruption_callback: None,
+        }
+    }
+
+    pub(crate) fn read_only(
+        data: DataReader,
+        corruption_callback: Option<CorruptionCallback>,
+    ) -> Self {
+        Self {
+            data,
+            downsampled: Vec::new(),
+            corruption_callback,
+        }
+    }
+
+    /// See [`ByteSeries::read_all`](super::ByteSeries::read_all)
+    ///
+    /// # Errors
+    ///
+    /// See the [`Error`] docs for an exhaustive list of everything that can go wrong.
+    /// Its mostly io-errors
+    pub fn read_all<D: Decoder>(
+        &mut self,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &mut D,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        read::all(
+            self.data.sync().map_err(Error::Open)?,
+            &mut self.corruption_callback,
+            range,
+            decoder,
+            timestamps,
+            data,
+        )
+    }
+
+    /// See [`ByteSeries::iter_range`](super::ByteSeries::iter_range)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the range lies outside the data or seeking failed.
+    /// Errors while reading are returned by the iterator, after an error the
+    /// iterator ends.
+    pub fn iter_range<'a, D: Decoder>(
+        &'a mut self,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &'a mut D,
+    ) -> Result<Iter<'a, D>, Error> {
+        read::iter(
+            self.data.sync().map_err(Error::Open)?,
+            &mut self.corruption_callback,
+            range,
+            decoder,
+        )
+    }
+
+    /// See [`ByteSeries::iter_range_rev`](super::ByteSeries::iter_range_rev)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the range lies outside the data or seeking failed.
+    /// Errors while reading are returned by the iterator, after an error the
+    /// iterator ends.
+    pub fn iter_range_rev<'a, D: Decoder>(
+        &'a mut self,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &'a mut D,
+    ) -> Result<RevIter<'a, D>, Error> {
+        read::iter_rev(
+            self.data.sync().map_err(Error::Open)?,
+            &mut self.corruption_callback,
+            range,
+            decoder,
+        )
+    }
+
+    /// See [`ByteSeries::n_lines_between`](super::ByteSeries::n_lines_between)
+    ///
+    /// # Errors
+    ///
+    /// See the [`Error`] docs for an exhaustive list of everything that can go wrong.
+    /// Its mostly io-errors
+    pub fn n_lines_between(
+        &mut self,
+        range: impl RangeBounds<Timestamp>,
+    ) -> Result<u64, Error> {
+        read::n_lines_between(self.data.sync().map_err(Error::Open)?, range)
+    }
+
+    /// See [`ByteSeries::read_n`](super::ByteSeries::read_n)
+    ///
+    /// # Errors
+    ///
+    /// See the [`Error`] docs for an exhaustive list of everything that can go wrong.
+    /// Its mostly IO-issues.
+    pub fn read_n<R: Resampler>(
+        &mut self,
+        n: usize,
+        range: impl RangeBounds<Timestamp>,
+        resampler: &mut R,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<<R as Decoder>::Item>,
+    ) -> Result<Skipped, Error> {
+        let downsampled = self
+            .downsampled
+            .iter_mut()
+            .map(|reader| reader.sync().map(|data| &*data))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::Open)?;
+        read::n(
+            self.data.sync().map_err(Error::Open)?,
+            downsampled,
+            &mut self.corruption_callback,
+            n,
+            range,
+            resampler,
+            timestamps,
+            data,
+        )
+    }
+
+    /// See [`ByteSeries::read_first_n`](super::ByteSeries::read_first_n)
+    ///
+    /// # Errors
+    ///
+    /// See the [`Error`] docs for an exhaustive list of everything that can go wrong.
+    /// Its mostly IO-issues.
+    pub fn read_first_n<D: Decoder>(
+        &mut self,
+        n: usize,
+        decoder: &mut D,
+        range: impl RangeBounds<Timestamp>,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        read::first_n(
+            self.data.sync().map_err(Error::Open)?,
+            &mut self.corruption_callback,
+            n,
+            decoder,
+            range,
+            timestamps,
+            data,
+        )
+    }
+
+    /// See [`ByteSeries::read_last_n`](super::ByteSeries::read_last_n)
+    ///
+    /// # Errors
+    ///
+    /// See the [`Error`] docs for an exhaustive list of everything that can go wrong.
+    /// Its mostly IO-issues.
+    pub fn read_last_n<D: Decoder>(
+        &mut self,
+        n: usize,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &mut D,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        read::last_n(
+            self.data.sync().map_err(Error::Open)?,
+            &mut self.corruption_callback,
+            n,
+            range,
+            decoder,
+            timestamps,
+            data,
+        )
+    }
+
+    /// # Errors
+    /// Returns [`Error::Open`] if the files could not be opened and
+    /// [`Error::Reading`] if anything goes wrong reading the last line. That
+    /// could be an io issue or the file could be empty.
+    pub fn last_line<D>(
+        &mut self,
+        decoder: &mut D,
+    ) -> Result<(u64, <D as Decoder>::Item), Error>
+    where
+        D: Decoder + Clone,
+        <D as Decoder>::Item: Clone,
+    {
+        self.data
+            .sync()
+            .map_err(Error::Open)?
+            .last_line(decoder, &mut self.corruption_callback)
+            .map_err(Error::Reading)
+    }
+
+    /// # Errors
+    /// Returns an error if the files could not be opened
+    pub fn range(
+        &mut self,
+    ) -> Result<Option<core::ops::RangeInclusive<Timestamp>>, data::OpenError> {
+        Ok(self.data.sync()?.range())
+    }
+
+    /// Returns the number of lines in the file.
+    ///
+    /// # Errors
+    /// Returns an error if the files could not be opened
+    pub fn len(&mut self) -> Result<u64, data::OpenError> {
+        Ok(self.data.sync()?.len())
+    }
+
+    /// # Errors
+    /// Returns an error if the files could not be opened
+    pub fn is_empty(&mut self) -> Result<bool, data::OpenError> {
+        Ok(self.data.sync()?.len() == 0)
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::{btree_map, BTreeMap};
+use std::ops::{Bound, RangeBounds};
+
+use super::duplicates::DuplicatePolicy;
+use super::Error;
+use crate::series::data::inline_meta::Sampler;
+use crate::{Decoder, Resampler, Timestamp};
+
+/// How to undo an [`Window::insert`]
+pub(crate) enum Undo {
+    Remove,
+    Restore(Vec<u8>),
+    Nothing,
+}
+
+/// Holds the newest lines in memory so lines that arrive late can still be
+/// sorted in. Every line in here is newer then the lines in the data.
+#[derive(Debug)]
+pub(crate) struct Window {
+    /// lines this much older then the newest are written out
+    width: Timestamp,
+    lines: BTreeMap<Timestamp, Vec<u8>>,
+}
+
+impl Window {
+    pub(crate) fn new(width: Timestamp) -> Self {
+        Self {
+            width,
+            lines: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn newest(&self) -> Option<Timestamp> {
+        self.lines.last_key_value().map(|(ts, _)| *ts)
+    }
+
+    fn window_start(&self) -> Option<Timestamp> {
+        self.newest()
+            .map(|newest| newest.saturating_sub(self.width))
+    }
+
+    /// If there already is a line with the same timestamp `duplicates`
+    /// decides what happens.
+    ///
+    /// # Errors
+    /// If the line is older then the window or it is a duplicate that is
+    /// rejected. Nothing is inserted in that case.
+    pub(crate) fn insert(
+        &mut self,
+        ts: Timestamp,
+        line: Vec<u8>,
+        duplicates: &mut DuplicatePolicy,
+    ) -> Result<Undo, Error> {
+        if let Some(window_start) = self.window_start() {
+            if ts < window_start {
+                return Err(Error::OutsideReorderWindow {
+                    new: ts,
+                    newest: self.newest().expect("window_start is Some"),
+                });
+            }
+        }
+
+        match self.lines.entry(ts) {
+            btree_map::Entry::Occupied(mut entry) => {
+                let earlier = entry.get().clone();
+                match duplicates.resolve(ts, || Ok(earlier), &line)? {
+                    Some(replacement) => Ok(Undo::Restore(std::mem::replace(
+                        entry.get_mut(),
+                        replacement,
+                    ))),
+                    None => Ok(Undo::Nothing),
+                }
+            }
+            btree_map::Entry::Vacant(entry) => {
+                entry.insert(line);
+                Ok(Undo::Remove)
+            }
+        }
+    }
+
+    /// Undoes an [`insert`](Self::insert) that succeeded, these must be
+    /// undone in the reverse order of the inserts.
+    pub(crate) fn undo(&mut self, ts: Timestamp, undo: Undo) {
+        match undo {
+            Undo::Remove => {
+                self.lines.remove(&ts);
+            }
+            Undo::Restore(line) => {
+                self.lines.insert(ts, line);
+            }
+            Undo::Nothing => (),
+        }
+    }
+
+    /// Removes and returns the lines that fell out of the window, oldest
+    /// first.
+    pub(crate) fn take_expired(&mut self) -> Vec<(Timestamp, Vec<u8>)> {
+        let Some(window_start) = self.window_start() else {
+            return Vec::new();
+        };
+        let in_window = self.lines.split_off(&window_start);
+        std::mem::replace(&mut self.lines, in_window)
+            .into_iter()
+            .collect()
+    }
+
+    pub(crate) fn truncate_after(&mut self, ts: Timestamp) {
+        if let Some(after) = ts.checked_add(1) {
+            self.lines.split_off(&after);
+        }
+    }
+
+    pub(crate) fn drop_before(&mut self, ts: Timestamp) {
+        self.lines = self.lines.split_off(&ts);
+    }
+
+    /// Removes and returns all lines, oldest first.
+    pub(crate) fn take_all(&mut self) -> Vec<(Timestamp, Vec<u8>)> {
+        std::mem::take(&mut self.lines).into_iter().collect()
+    }
+
+    pub(crate) fn range(
+        &self,
+        range: &impl RangeBounds<Timestamp>,
+    ) -> btree_map::Range<'_, Timestamp, Vec<u8>> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        let empty = match bounds {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start >= end,
+            _ => false,
+        };
+        if empty {
+            // BTreeMap::range panics on these
+            self.lines.range(0..0)
+        } else {
+            self.lines.range(bounds)
+        }
+    }
+
+    pub(crate) fn first(&self) -> Option<Timestamp> {
+        self.lines.first_key_value().map(|(ts, _)| *ts)
+    }
+
+    pub(crate) fn last_line<D: Decoder>(
+        &self,
+        decoder: &mut D,
+    ) -> Option<(Timestamp, D::Item)> {
+        self.lines
+            .last_key_value()
+            .map(|(ts, line)| (*ts, decoder.decode_payload(line)))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub(crate) fn read_first_n<D: Decoder>(
+        &self,
+        n: usize,
+        range: &impl RangeBounds<Timestamp>,
+        decoder: &mut D,
+        timestamps: &mut Vec<Timestamp>,
+        items: &mut Vec<D::Item>,
+    ) {
+        for (ts, line) in self.range(range).take(n) {
+            timestamps.push(*ts);
+            items.push(decoder.decode_payload(line));
+        }
+    }
+
+    pub(crate) fn read_resampling<R: Resampler>(
+        &self,
+        range: &impl RangeBounds<Timestamp>,
+        resampler: &mut R,
+        bucket_size: usize,
+        timestamps: &mut Vec<Timestamp>,
+        items: &mut Vec<<R as Decoder>::Item>,
+    ) {
+        let mut sampler = Sampler::new(resampler, bucket_size, timestamps, items);
+        for (ts, line) in self.range(range) {
+            sampler.process(*ts, line);
+        }
+    }
+}
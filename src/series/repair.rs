@@ -0,0 +1,80 @@
+//! Controls what happens to the end of a data file left behind by an
+//! interrupted write, see [`RepairPolicy`].
+//!
+//! Bytes removed by a repair are appended to a `.quarantine` file next to
+//! the data. Every repair adds one record: the offset of the removed bytes
+//! in the data (after the header) as little endian `u64`, their number as
+//! little endian `u64` and then the bytes themselves.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// What to do when opening a series finds the end of its data file needs
+/// repairing. Set it using
+/// [`repair_policy`](crate::builder::ByteSeriesBuilder::repair_policy).
+#[derive(Debug, Clone, Copy, Default)]
+pub enum RepairPolicy {
+    /// Repair and log a warning
+    #[default]
+    Auto,
+    /// Do not repair, return a
+    /// [`RepairNeeded`](crate::series::data::OpenError::RepairNeeded) error
+    /// describing the repairs instead.
+    Report,
+    /// Repair if this returns true for every action, otherwise return a
+    /// [`RepairNeeded`](crate::series::data::OpenError::RepairNeeded) error.
+    Callback(fn(&RepairAction) -> bool),
+}
+
+impl RepairPolicy {
+    pub(crate) fn allows(&self, actions: &[RepairAction]) -> bool {
+        match self {
+            Self::Auto => true,
+            Self::Report => actions.is_empty(),
+            Self::Callback(approve) => actions.iter().all(approve),
+        }
+    }
+}
+
+/// A repair that removes bytes from the end of a data file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairAction {
+    /// The data file
+    pub path: PathBuf,
+    pub reason: RepairReason,
+    /// The bytes that are removed, as offset in the data after the header
+    pub removed: Range<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepairReason {
+    /// The last line was only partially written
+    IncompleteLine,
+    /// The file contains nothing but a meta section
+    OnlyMeta,
+    /// A meta section at the end is missing its second line
+    PartialMeta,
+    /// A meta section at the end has no lines after it
+    StartOfMeta,
+}
+
+/// Appends the `bytes` that are about to be removed from the data at
+/// `removed` to the quarantine file of the series at `name`.
+pub(crate) fn quarantine(
+    name: &Path,
+    removed: Range<u64>,
+    bytes: &[u8],
+) -> Result<(), io::Error> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(name.with_extension("quarantine"))?;
+    let mut record = Vec::with_capacity(16 + bytes.len());
+    record.extend_from_slice(&removed.start.to_le_bytes());
+    record.extend_from_slice(&(removed.end - removed.start).to_le_bytes());
+    record.extend_from_slice(bytes);
+    file.write_all(&record)?;
+    file.sync_data()
+}
@@ -0,0 +1,81 @@
+use super::data::Data;
+use crate::Timestamp;
+
+/// Pushed lines between checks of the [`Retention`] policy
+const CHECK_EVERY: usize = 1000;
+
+/// Limits how much data a [`ByteSeries`](crate::ByteSeries) keeps. The
+/// oldest lines are dropped using
+/// [`drop_before`](crate::ByteSeries::drop_before) when the series is opened
+/// and then every 1000 pushed lines.
+///
+/// Dropping lines rewrites the data, that takes longer the more data is
+/// kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Drop lines this much older then the newest line
+    pub max_age: Option<Timestamp>,
+    /// Drop the oldest lines when the data file is larger then this. The
+    /// index and downsampled caches are not counted. At least the newest
+    /// meta section is kept.
+    pub max_bytes: Option<u64>,
+}
+
+impl Retention {
+    /// The oldest timestamp to keep, None if there is nothing to drop
+    pub(crate) fn cutoff(
+        &self,
+        data: &Data,
+        newest: Option<Timestamp>,
+    ) -> Option<Timestamp> {
+        let by_age = self
+            .max_age
+            .zip(newest)
+            .and_then(|(max_age, newest)| newest.checked_sub(max_age));
+        let by_size = self
+            .max_bytes
+            .filter(|max_bytes| data.data_len > *max_bytes)
+            .and_then(|max_bytes| {
+                let entries = data.index.entries();
+                let first_kept = entries.partition_point(|entry| {
+                    data.data_len - entry.meta_start.raw_offset() > max_bytes
+                });
+                entries
+                    .get(first_kept)
+                    .or(entries.last())
+                    .map(|entry| entry.timestamp)
+            });
+        by_age.max(by_size)
+    }
+}
+
+/// Tracks when the [`Retention`] policy should be checked
+#[derive(Debug, Default)]
+pub(crate) struct Tracker {
+    policy: Option<Retention>,
+    unchecked_lines: usize,
+}
+
+impl Tracker {
+    pub(crate) fn new(policy: Option<Retention>) -> Self {
+        Self {
+            policy,
+            unchecked_lines: 0,
+        }
+    }
+
+    pub(crate) fn policy(&self) -> Option<Retention> {
+        self.policy
+    }
+
+    /// Returns the policy if it should be checked now
+    pub(crate) fn pushed(&mut self, lines: usize) -> Option<Retention> {
+        self.policy?;
+        self.unchecked_lines += lines;
+        if self.unchecked_lines < CHECK_EVERY {
+            return None;
+        }
+        self.unchecked_lines = 0;
+        self.policy
+    }
+}
@@ -0,0 +1,464 @@
+//! Checks a series without changing it, see [`verify`].
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crc32fast::Hasher;
+
+use super::data::checksums;
+use super::data::index::{self, Entry, PayloadSize};
+use super::data::inline_meta::{meta, ReadAt};
+use super::downsample;
+use super::file_header;
+use crate::builder::PayloadSizeOption;
+use crate::file::{self, FileWithHeader, OffsetFile};
+use crate::Timestamp;
+
+/// Something wrong with a series. Offsets are in bytes from the start of the
+/// data, the header is not counted. For compressed data they are offsets in
+/// the uncompressed lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// The data ends in a partial line or a meta section without a line
+    /// after it. These are left behind by an interrupted write and removed
+    /// when the series is opened.
+    TornTail { start: u64, len: u64 },
+    /// The index ends in a partial entry, it is removed when the series is
+    /// opened.
+    TornIndexTail { len: u64 },
+    /// There is no index, it is created from the data when the series is
+    /// opened.
+    IndexMissing,
+    /// The line at `offset` starts with the meta section preamble but the
+    /// line after it does not. Reads fail here unless the corruption
+    /// callback skips the line.
+    MissingSecondPreamble { offset: u64 },
+    /// The line at `offset` is not after the line before it
+    NonMonotonic {
+        offset: u64,
+        previous: Timestamp,
+        timestamp: Timestamp,
+    },
+    /// The index and the data disagree about the meta section at
+    /// `meta_start`. A `None` means the section is missing there.
+    IndexMismatch {
+        meta_start: u64,
+        in_index: Option<Timestamp>,
+        in_data: Option<Timestamp>,
+    },
+    /// The meta section at `section_start` or a line after it does not match
+    /// its checksum
+    ChecksumMismatch { section_start: u64 },
+    /// The source has lines newer then the downsampled cache that should
+    /// have been in it. The cache is brought up to date when the series is
+    /// opened with it.
+    CacheLags {
+        last_in_cache: Option<Timestamp>,
+        newer_source_lines: u64,
+    },
+}
+
+/// What [`verify`] found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Issues with the data, its index and its checksums
+    pub issues: Vec<Issue>,
+    pub caches: Vec<CacheReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheReport {
+    /// Path of the cache's data file
+    pub path: PathBuf,
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    /// True if neither the series nor any of its caches has an issue
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty() && self.caches.iter().all(|c| c.issues.is_empty())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not open the data file: {path:?}")]
+    Open {
+        #[source]
+        source: file::OpenError,
+        path: PathBuf,
+    },
+    #[error("The header of the data file is invalid")]
+    Header(#[source] file_header::Error),
+    #[error("Could not read the data: {0}")]
+    Read(io::Error),
+    #[error("Could not read the index: {0}")]
+    ReadIndex(#[source] file::OpenError),
+    #[error("Could not read the checksums: {0}")]
+    ReadChecksums(io::Error),
+    #[error("Could not list the downsampled caches: {0}")]
+    ListCaches(io::Error),
+}
+
+/// Scans the data, index and every downsampled cache of the series at
+/// `path` and reports everything wrong with them. Nothing is changed or
+/// repaired. Opening the series repairs what a crash left behind, see the
+/// docs of each [`Issue`].
+///
+/// The series should not be open in another process while it is verified,
+/// lines written during the scan can show up as torn.
+///
+/// A cache only lags if it misses a complete bucket. For caches with a
+/// `max_gap` the buckets spanning a gap are left out, those can show up as
+/// lag.
+///
+/// # Errors
+/// Returns an error if a file could not be read or the header of the data
+/// file is invalid. See the [`Error`] docs for an exhaustive list.
+pub fn verify(path: impl AsRef<Path>) -> Result<Report, Error> {
+    let name = if path
+        .as_ref()
+        .extension()
+        .is_some_and(|ext| ext == "byteseries")
+    {
+        path.as_ref().with_extension("")
+    } else {
+        path.as_ref().to_owned()
+    };
+
+    let (file, header) = open_data(&name)?;
+    let (payload_size, encoding, has_checksums, _) =
+        file_header::check_and_split_off_user_header(header, PayloadSizeOption::Ignore)
+            .map_err(Error::Header)?;
+    let file = file.open_compressed(encoding, false).map_err(Error::Read)?;
+
+    // the caches are scanned first, then counting the source lines newer
+    // then each cache takes only one scan of the source
+    let mut caches = Vec::new();
+    for (cache_name, config) in downsample::caches(&name).map_err(Error::ListCaches)? {
+        let (cache, _) = open_data(&cache_name)?;
+        let scan = scan(&cache, payload_size, |_| ())?;
+        let mut issues = scan.issues.clone();
+        issues.extend(check_index(&cache_name, &scan)?);
+        caches.push((cache_name, config, scan.last_timestamp, issues, 0u64));
+    }
+
+    let scan = scan(&file, payload_size, |ts| {
+        for (_, _, last_in_cache, _, newer) in &mut caches {
+            if last_in_cache.is_none_or(|last| ts > last) {
+                *newer += 1;
+            }
+        }
+    })?;
+    let mut issues = scan.issues.clone();
+    issues.extend(check_index(&name, &scan)?);
+    if has_checksums {
+        let stored = checksums::read(&name).map_err(Error::ReadChecksums)?;
+        issues.extend(check_checksums(&stored, &scan));
+    }
+
+    let caches = caches
+        .into_iter()
+        .map(|(cache_name, config, last_in_cache, mut issues, newer)| {
+            // lines after the average of the last bucket can already be in
+            // it, the bucket being filled now is not in the cache yet.
+            if newer + 1 >= 2 * config.bucket_size as u64 {
+                issues.push(Issue::CacheLags {
+                    last_in_cache,
+                    newer_source_lines: newer,
+                });
+            }
+            CacheReport {
+                path: cache_name.with_extension("byteseries"),
+                issues,
+            }
+        })
+        .collect();
+    Ok(Report { issues, caches })
+}
+
+fn open_data(name: &Path) -> Result<(OffsetFile, Vec<u8>), Error> {
+    let path = name.with_extension("byteseries");
+    let file = FileWithHeader::open_existing_read_only(path.clone())
+        .map_err(|source| Error::Open { source, path })?;
+    Ok(file.split_off_header())
+}
+
+/// A meta section found while scanning
+#[derive(Debug, Clone)]
+struct Section {
+    start: u64,
+    timestamp: Timestamp,
+    lines: u64,
+    /// checksum of the section, only complete once the next one starts
+    crc: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Scan {
+    /// every complete meta section with a line after it
+    sections: Vec<Section>,
+    last_timestamp: Option<Timestamp>,
+    issues: Vec<Issue>,
+}
+
+/// What the line before the current one started
+enum State {
+    Lines,
+    /// a line starting with the meta section preamble
+    Preamble {
+        start: u64,
+        line: Vec<u8>,
+    },
+    /// the lines of a meta section read so far
+    Meta {
+        start: u64,
+        lines: Vec<Vec<u8>>,
+    },
+}
+
+struct Scanner<F> {
+    line_size: usize,
+    meta_lines: usize,
+    state: State,
+    hasher: Hasher,
+    scan: Scan,
+    on_line: F,
+}
+
+/// Reads every line in `file`, `on_line` is called with the timestamp of
+/// each line that is not part of a meta section.
+fn scan(
+    file: &OffsetFile,
+    payload_size: PayloadSize,
+    on_line: impl FnMut(Timestamp),
+) -> Result<Scan, Error> {
+    let data_len = file.data_len_bytes().map_err(Error::Read)?;
+    let line_size = payload_size.line_size() as u64;
+    let whole_lines = data_len - data_len % line_size;
+
+    let mut scanner = Scanner {
+        line_size: payload_size.line_size(),
+        meta_lines: meta::lines_per_metainfo(payload_size.raw()),
+        state: State::Lines,
+        hasher: Hasher::new(),
+        scan: Scan {
+            sections: Vec::new(),
+            last_timestamp: None,
+            issues: Vec::new(),
+        },
+        on_line,
+    };
+
+    let chunk_size = 16384u64.next_multiple_of(line_size);
+    let mut buf = Vec::new();
+    let mut chunk_start = 0;
+    while chunk_start < whole_lines {
+        let chunk_end = whole_lines.min(chunk_start + chunk_size);
+        buf.resize((chunk_end - chunk_start) as usize, 0);
+        file.read_exact_at(&mut buf, chunk_start)
+            .map_err(Error::Read)?;
+        for (i, line) in buf.chunks_exact(scanner.line_size).enumerate() {
+            scanner.process(chunk_start + i as u64 * line_size, line);
+        }
+        chunk_start = chunk_end;
+    }
+
+    Ok(scanner.finish(whole_lines, data_len))
+}
+
+impl<F: FnMut(Timestamp)> Scanner<F> {
+    fn process(&mut self, offset: u64, line: &[u8]) {
+        let is_preamble = line[..2] == meta::PREAMBLE;
+        match &mut self.state {
+            State::Lines if is_preamble => {
+                self.state = State::Preamble {
+                    start: offset,
+                    line: line.to_vec(),
+                };
+            }
+            State::Lines => self.data_line(offset, line),
+            State::Preamble { start, line: first } if is_preamble => {
+                self.state = State::Meta {
+                    start: *start,
+                    lines: vec![std::mem::take(first), line.to_vec()],
+                };
+                self.meta_line_done();
+            }
+            State::Preamble { start, line: first } => {
+                self.scan
+                    .issues
+                    .push(Issue::MissingSecondPreamble { offset: *start });
+                self.hasher.update(first);
+                self.state = State::Lines;
+                self.process(offset, line);
+            }
+            State::Meta { lines, .. } => {
+                lines.push(line.to_vec());
+                self.meta_line_done();
+            }
+        }
+    }
+
+    fn data_line(&mut self, offset: u64, line: &[u8]) {
+        self.hasher.update(line);
+        let Some(section) = self.scan.sections.last_mut() else {
+            // can not know the time of a line before any meta section
+            return;
+        };
+        section.lines += 1;
+
+        let small_ts: [u8; 2] = line[..2].try_into().expect("slice len is 2");
+        let timestamp = section.timestamp + u64::from(u16::from_le_bytes(small_ts));
+        if let Some(previous) = self.scan.last_timestamp {
+            if timestamp <= previous {
+                self.scan.issues.push(Issue::NonMonotonic {
+                    offset,
+                    previous,
+                    timestamp,
+                });
+            }
+        }
+        self.scan.last_timestamp = Some(timestamp);
+        (self.on_line)(timestamp);
+    }
+
+    /// Starts the new section once all its lines are read
+    fn meta_line_done(&mut self) {
+        let State::Meta { start, lines } = &self.state else {
+            unreachable!("only called while reading a meta section");
+        };
+        if lines.len() < self.meta_lines {
+            return;
+        }
+
+        let mut rest = lines[2..].iter().map(Vec::as_slice);
+        let meta::Result::Meta { meta } = meta::read(&mut rest, &lines[0], &lines[1])
+        else {
+            unreachable!("all lines of the meta section are read");
+        };
+        let start = *start;
+        let mut hasher = Hasher::new();
+        for line in lines {
+            hasher.update(line);
+        }
+
+        let previous = std::mem::replace(&mut self.hasher, hasher);
+        if let Some(section) = self.scan.sections.last_mut() {
+            section.crc = previous.finalize();
+        }
+        self.scan.sections.push(Section {
+            start,
+            timestamp: u64::from_le_bytes(meta),
+            lines: 0,
+            crc: 0,
+        });
+        self.state = State::Lines;
+    }
+
+    fn finish(mut self, whole_lines: u64, data_len: u64) -> Scan {
+        let torn_start = match self.state {
+            State::Preamble { start, .. } | State::Meta { start, .. } => start,
+            State::Lines => match self.scan.sections.last() {
+                Some(last) if last.lines == 0 => {
+                    let last = self.scan.sections.pop().expect("just checked");
+                    last.start
+                }
+                _ => whole_lines,
+            },
+        };
+        if torn_start < data_len {
+            self.scan.issues.push(Issue::TornTail {
+                start: torn_start,
+                len: data_len - torn_start,
+            });
+        }
+        self.scan
+    }
+}
+
+/// Compares the index of the series `name` to the meta sections found by
+/// `scan`
+fn check_index(name: &Path, scan: &Scan) -> Result<Vec<Issue>, Error> {
+    let path = name.with_extension("byteseries_index");
+    let mut file = match FileWithHeader::open_existing_read_only(path) {
+        Ok(file) => file.split_off_header().0,
+        Err(file::OpenError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(vec![Issue::IndexMissing]);
+        }
+        Err(e) => return Err(Error::ReadIndex(e)),
+    };
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)
+        .map_err(|e| Error::ReadIndex(e.into()))?;
+
+    let mut issues = Vec::new();
+    let partial = bytes.len() % 16;
+    if partial > 0 {
+        issues.push(Issue::TornIndexTail {
+            len: partial as u64,
+        });
+    }
+
+    let entries = index::parse_entries(&bytes);
+    let mut entries = entries.iter().peekable();
+    let mut sections = scan.sections.iter().peekable();
+    loop {
+        let (meta_start, in_index, in_data) = match (entries.peek(), sections.peek()) {
+            (None, None) => break,
+            (Some(entry), Some(section))
+                if entry.meta_start.raw_offset() == section.start =>
+            {
+                let entry = entries.next().expect("just peeked");
+                let section = sections.next().expect("just peeked");
+                if entry.timestamp == section.timestamp {
+                    continue;
+                }
+                (
+                    section.start,
+                    Some(entry.timestamp),
+                    Some(section.timestamp),
+                )
+            }
+            (Some(entry), Some(section))
+                if entry.meta_start.raw_offset() > section.start =>
+            {
+                let section = sections.next().expect("just peeked");
+                (section.start, None, Some(section.timestamp))
+            }
+            (Some(_), _) => {
+                let Entry {
+                    timestamp,
+                    meta_start,
+                } = entries.next().expect("just peeked");
+                (meta_start.raw_offset(), Some(*timestamp), None)
+            }
+            (None, Some(_)) => {
+                let section = sections.next().expect("just peeked");
+                (section.start, None, Some(section.timestamp))
+            }
+        };
+        issues.push(Issue::IndexMismatch {
+            meta_start,
+            in_index,
+            in_data,
+        });
+    }
+    Ok(issues)
+}
+
+/// Only closed sections have a checksum, stored checksums for sections that
+/// do not exist are removed on open and not an issue.
+fn check_checksums(stored: &[(u64, u32)], scan: &Scan) -> Vec<Issue> {
+    let closed = &scan.sections[..scan.sections.len().saturating_sub(1)];
+    stored
+        .iter()
+        .filter_map(|(start, crc)| {
+            let i = closed.binary_search_by_key(start, |s| s.start).ok()?;
+            (closed[i].crc != *crc).then_some(Issue::ChecksumMismatch {
+                section_start: *start,
+            })
+        })
+        .collect()
+}
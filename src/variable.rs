@@ -0,0 +1,345 @@
+//! A series whose lines each have their own payload length. The payloads
+//! are appended to a separate `.byteseries_payloads` file. The lines of a
+//! normal [`ByteSeries`] hold where each payload starts and how long it is,
+//! that offset column provides the timestamps, index and seeking.
+//!
+//! A payload is written before the line pointing to it. If we crash in
+//! between the payload is removed when the series is opened again. Lines
+//! whose payload did not make it to disk are removed too.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::ops::{RangeBounds, RangeInclusive};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::series::corruption::Skipped;
+use crate::{series, ByteSeries, Decoder, Timestamp};
+
+/// Offset (u64) and length (u32) of the payload
+const LOCATION_SIZE: usize = 12;
+const HEADER: &[u8] = b"Lines point to a payload in the .byteseries_payloads file";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not open or create the offset column")]
+    Open(#[source] series::Error),
+    #[error("Could not open the payload file: {0}")]
+    OpenPayloads(io::Error),
+    #[error("Could not check or repair the payload file: {0}")]
+    Repair(io::Error),
+    #[error("Payload is {0} bytes, at most u32::MAX bytes are supported")]
+    TooLong(usize),
+    #[error("Could not write the payload: {0}")]
+    WritePayload(io::Error),
+    #[error(
+        "Could not remove the payload after pushing failed, it is removed \
+        when the series is opened again: {rollback}"
+    )]
+    RollBack {
+        #[source]
+        cause: Box<Error>,
+        rollback: io::Error,
+    },
+    #[error("Could not read the payloads: {0}")]
+    ReadPayload(io::Error),
+    #[error("Error in the offset column")]
+    Series(#[source] series::Error),
+    #[error("Could not read the last line")]
+    LastLine(#[source] series::data::ReadError),
+}
+
+/// Decodes the location of a payload from a line
+#[derive(Debug, Clone)]
+struct LocationDecoder;
+
+impl Decoder for LocationDecoder {
+    type Item = Location;
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        Location {
+            offset: u64::from_le_bytes(line[0..8].try_into().expect("is 12 long")),
+            len: u32::from_le_bytes(line[8..12].try_into().expect("is 12 long")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    offset: u64,
+    len: u32,
+}
+
+impl Location {
+    fn end(self) -> u64 {
+        self.offset + u64::from(self.len)
+    }
+
+    fn to_line(self) -> [u8; LOCATION_SIZE] {
+        let mut line = [0; LOCATION_SIZE];
+        line[0..8].copy_from_slice(&self.offset.to_le_bytes());
+        line[8..12].copy_from_slice(&self.len.to_le_bytes());
+        line
+    }
+}
+
+/// A time series with variable length payloads, see the [module
+/// docs](self).
+#[derive(Debug)]
+pub struct VariableSeries {
+    offsets: ByteSeries,
+    payloads: File,
+    /// length of the payload file
+    payloads_len: u64,
+}
+
+impl VariableSeries {
+    /// Opens the series at `path`, if there is none a new one is created.
+    /// Repairs the payload file if we crashed while pushing.
+    ///
+    /// The offset column is opened with the default settings of
+    /// [`ByteSeries::builder`]. Compression, checksums, durability, a user
+    /// header and the other builder options are not supported. Use
+    /// [`flush_to_disk`](Self::flush_to_disk) to make lines durable.
+    ///
+    /// # Errors
+    /// If the files could not be opened, created or repaired. See
+    /// [`Error`] for all that can go wrong.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().with_extension("");
+        let open = |create_new| {
+            ByteSeries::builder()
+                .payload_size(LOCATION_SIZE)
+                .create_new(create_new)
+                .with_header(HEADER.to_vec())
+                .open(&path)
+                .map(|(series, _)| series)
+        };
+        let offsets = match open(false) {
+            Err(series::Error::Open(series::data::OpenError::File {
+                source: crate::file::OpenError::Io(e),
+                ..
+            })) if e.kind() == io::ErrorKind::NotFound => open(true),
+            res => res,
+        }
+        .map_err(Error::Open)?;
+
+        let payloads = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path.with_extension("byteseries_payloads"))
+            .map_err(Error::OpenPayloads)?;
+        let payloads_len = payloads.metadata().map_err(Error::OpenPayloads)?.len();
+
+        let mut series = Self {
+            offsets,
+            payloads,
+            payloads_len,
+        };
+        series.repair()?;
+        Ok(series)
+    }
+
+    /// Removes lines whose payload is missing and payloads without a line
+    fn repair(&mut self) -> Result<(), Error> {
+        while let Some((ts, location)) = self.last_location()? {
+            if location.end() <= self.payloads_len {
+                break;
+            }
+            tracing::warn!("payload of last line is missing, removing the line");
+            match ts.checked_sub(1) {
+                Some(before) => self.offsets.truncate_after(before),
+                // timestamps increase so only the first line can be at zero,
+                // dropping everything before one removes it
+                None => self.offsets.drop_before(1),
+            }
+            .map_err(Error::Series)?;
+        }
+        self.trim_payloads()
+    }
+
+    /// Removes payloads after that of the last line
+    fn trim_payloads(&mut self) -> Result<(), Error> {
+        let end = self.last_location()?.map_or(0, |(_, l)| l.end());
+        if end < self.payloads_len {
+            self.payloads.set_len(end).map_err(Error::Repair)?;
+            self.payloads_len = end;
+        }
+        Ok(())
+    }
+
+    fn last_location(&mut self) -> Result<Option<(Timestamp, Location)>, Error> {
+        if self.offsets.is_empty() {
+            return Ok(None);
+        }
+        self.offsets
+            .last_line(&mut LocationDecoder)
+            .map(Some)
+            .map_err(Error::LastLine)
+    }
+
+    /// Appends a line with a payload of any length up to `u32::MAX`.
+    ///
+    /// # Errors
+    /// If the timestamp is not after the last, the payload is too long or
+    /// writing failed. See [`Error`] for all that can go wrong.
+    pub fn push_line(
+        &mut self,
+        ts: Timestamp,
+        payload: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        let payload = payload.as_ref();
+        let len =
+            u32::try_from(payload.len()).map_err(|_| Error::TooLong(payload.len()))?;
+        let location = Location {
+            offset: self.payloads_len,
+            len,
+        };
+        if let Err(e) = self.payloads.write_all(payload) {
+            // do not leave a partial payload behind
+            return Err(self.roll_back(Error::WritePayload(e)));
+        }
+        if let Err(e) = self.offsets.push_line(ts, location.to_line()) {
+            return Err(self.roll_back(Error::Series(e)));
+        }
+        self.payloads_len = location.end();
+        Ok(())
+    }
+
+    /// Removes the payload written by a push that failed with `cause`
+    fn roll_back(&mut self, cause: Error) -> Error {
+        match self.payloads.set_len(self.payloads_len) {
+            Ok(()) => cause,
+            Err(rollback) => Error::RollBack {
+                cause: Box::new(cause),
+                rollback,
+            },
+        }
+    }
+
+    /// Reads all lines in `range`. Each payload is passed to `decoder`
+    /// as is. Returns how many corrupt lines of the offset column were
+    /// skipped.
+    ///
+    /// # Errors
+    /// If the range could not be read from the offset column or the payload
+    /// file. See [`Error`] for all that can go wrong.
+    pub fn read_all<D: Decoder>(
+        &mut self,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &mut D,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        let mut locations = Vec::new();
+        let skipped = self
+            .offsets
+            .read_all(range, &mut LocationDecoder, timestamps, &mut locations)
+            .map_err(Error::Series)?;
+        self.decode(&locations, decoder, data)?;
+        Ok(skipped)
+    }
+
+    /// Reads up to `n` lines in `range`, oldest first.
+    ///
+    /// # Errors
+    /// See [`read_all`](Self::read_all).
+    pub fn read_first_n<D: Decoder>(
+        &mut self,
+        n: usize,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &mut D,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        let mut locations = Vec::new();
+        let skipped = self
+            .offsets
+            .read_first_n(n, &mut LocationDecoder, range, timestamps, &mut locations)
+            .map_err(Error::Series)?;
+        self.decode(&locations, decoder, data)?;
+        Ok(skipped)
+    }
+
+    /// Reads the newest `n` lines in `range`, they are appended oldest
+    /// first.
+    ///
+    /// # Errors
+    /// See [`read_all`](Self::read_all).
+    pub fn read_last_n<D: Decoder>(
+        &mut self,
+        n: usize,
+        range: impl RangeBounds<Timestamp>,
+        decoder: &mut D,
+        timestamps: &mut Vec<Timestamp>,
+        data: &mut Vec<D::Item>,
+    ) -> Result<Skipped, Error> {
+        let mut locations = Vec::new();
+        let skipped = self
+            .offsets
+            .read_last_n(n, range, &mut LocationDecoder, timestamps, &mut locations)
+            .map_err(Error::Series)?;
+        self.decode(&locations, decoder, data)?;
+        Ok(skipped)
+    }
+
+    /// The payloads of consecutive lines follow each other, they are read
+    /// in one go.
+    fn decode<D: Decoder>(
+        &self,
+        locations: &[Location],
+        decoder: &mut D,
+        data: &mut Vec<D::Item>,
+    ) -> Result<(), Error> {
+        let (Some(first), Some(last)) = (locations.first(), locations.last()) else {
+            return Ok(());
+        };
+        let mut buf = vec![0; (last.end() - first.offset) as usize];
+        self.payloads
+            .read_exact_at(&mut buf, first.offset)
+            .map_err(Error::ReadPayload)?;
+        for location in locations {
+            let start = (location.offset - first.offset) as usize;
+            let payload = &buf[start..start + location.len as usize];
+            data.push(decoder.decode_payload(payload));
+        }
+        Ok(())
+    }
+
+    /// Removes all lines with a timestamp after `ts` and their payloads.
+    ///
+    /// # Errors
+    /// If the offset column or payload file could not be truncated.
+    pub fn truncate_after(&mut self, ts: Timestamp) -> Result<(), Error> {
+        self.offsets.truncate_after(ts).map_err(Error::Series)?;
+        self.trim_payloads()
+    }
+
+    /// The range of timestamps of all lines in the series
+    #[must_use]
+    pub fn range(&self) -> Option<RangeInclusive<Timestamp>> {
+        self.offsets.range()
+    }
+
+    /// The number of lines
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.offsets.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// The payloads are flushed before the lines pointing to them
+    ///
+    /// # Errors
+    /// When the OS fails to flush files to disk the underlying
+    /// io error is returned
+    pub fn flush_to_disk(&mut self) -> io::Result<()> {
+        self.payloads.sync_data()?;
+        self.offsets.flush_to_disk()
+    }
+}
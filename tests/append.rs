@@ -196,3 +196,107 @@ mod refuses_old_time {
         }
     }
 }
+
+mod push_lines {
+    use byteseries::downsample;
+    use rstest::rstest;
+    use rstest_reuse::apply;
+
+    use super::{shared, ByteSeries, TempDir};
+    use shared::{payload_sizes, FakeFloatResampler};
+
+    fn open(test_dir: &TempDir, name: &str, payload_size: usize) -> ByteSeries {
+        ByteSeries::builder()
+            .payload_size(payload_size)
+            .create_new(true)
+            .with_any_header()
+            .with_downsampled_cache(
+                FakeFloatResampler { payload_size },
+                vec![downsample::Config {
+                    max_gap: None,
+                    bucket_size: 10,
+                }],
+            )
+            .open(test_dir.child(name))
+            .unwrap()
+            .0
+    }
+
+    fn files_in(test_dir: &TempDir) -> Vec<Vec<u8>> {
+        let mut paths: Vec<_> = std::fs::read_dir(test_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| std::fs::read(path).unwrap())
+            .collect()
+    }
+
+    #[apply(payload_sizes)]
+    #[trace]
+    fn same_files_as_push_line(#[case] payload_size: usize) {
+        shared::setup_tracing();
+
+        // the name ends up in the headers, use the same name in both dirs
+        let one_by_one_dir = TempDir::new().unwrap();
+        let batched_dir = TempDir::new().unwrap();
+        let mut one_by_one = open(&one_by_one_dir, "series", payload_size);
+        let mut batched = open(&batched_dir, "series", payload_size);
+
+        let mut ts = 0;
+        let lines: Vec<_> = (0..10_000u64)
+            .map(|i| {
+                // every once in a while a jump to force a new meta section
+                ts += if i % 1000 == 0 { 100_000 } else { 7 };
+                (ts, vec![i as u8; payload_size])
+            })
+            .collect();
+
+        for (ts, line) in &lines {
+            one_by_one.push_line(*ts, line).unwrap();
+        }
+        for batch in lines.chunks(3000) {
+            batched.push_lines(batch.iter().cloned()).unwrap();
+        }
+
+        assert_eq!(one_by_one.range(), batched.range());
+        assert_eq!(one_by_one.len(), batched.len());
+        drop((one_by_one, batched));
+        assert_eq!(files_in(&one_by_one_dir), files_in(&batched_dir));
+    }
+
+    #[test]
+    fn invalid_batch_is_not_written() {
+        shared::setup_tracing();
+
+        let test_dir = TempDir::new().unwrap();
+        let test_path = test_dir.child("push_lines_invalid_batch");
+        let (mut bs, _) = ByteSeries::builder()
+            .payload_size(1)
+            .create_new(true)
+            .with_any_header()
+            .open(&test_path)
+            .unwrap();
+        bs.push_lines([(1, [1]), (2, [2])]).unwrap();
+
+        let error = bs.push_lines([(3, [3]), (5, [5]), (4, [4])]).unwrap_err();
+        assert!(matches!(
+            error,
+            byteseries::series::Error::TimeNotAfterLast { new: 4, prev: 5 }
+        ));
+        let error = bs.push_lines([(3, vec![3]), (4, vec![4, 4])]).unwrap_err();
+        assert!(matches!(
+            error,
+            byteseries::series::Error::WrongLineLength {
+                required: 1,
+                got: 2
+            }
+        ));
+
+        assert_eq!(bs.range(), Some(1..=2));
+        assert_eq!(bs.len(), 2);
+        bs.push_line(3, [3]).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use byteseries::ByteSeries;
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{insert_timestamps, setup_tracing, Timestamp};
+
+#[derive(Debug)]
+struct TsDecoder;
+
+impl byteseries::Decoder for TsDecoder {
+    type Item = Timestamp;
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        u64::from_ne_bytes(line.try_into().expect("is 8 long")) as Timestamp
+    }
+}
+
+fn read_all(series: &mut ByteSeries) -> Vec<Timestamp> {
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_all(.., &mut TsDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    assert_eq!(timestamps, data);
+    timestamps
+}
+
+#[test]
+fn reads_see_buffered_lines() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("buffered_reads_see_buffered_lines");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .buffer_writes(usize::MAX, Duration::MAX)
+        .open(test_path)
+        .unwrap();
+    let mut reader = series.reader();
+
+    // step is large so new meta sections are buffered too
+    insert_timestamps(&mut series, 1000, 70_000, 1000);
+    let expected: Vec<_> = (0..1000).map(|i| 1000 + i * 70_000).collect();
+    assert_eq!(read_all(&mut series), expected);
+    assert_eq!(reader.len().unwrap(), 0);
+
+    series.commit().unwrap();
+    assert_eq!(reader.len().unwrap(), 1000);
+    assert_eq!(read_all(&mut series), expected);
+}
+
+#[test]
+fn nothing_written_before_commit() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("buffered_nothing_written_before_commit");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .buffer_writes(usize::MAX, Duration::MAX)
+        .open(&test_path)
+        .unwrap();
+
+    let data_path = test_path.with_extension("byteseries");
+    let empty_len = std::fs::metadata(&data_path).unwrap().len();
+    insert_timestamps(&mut series, 100, 1000, 0);
+    assert_eq!(std::fs::metadata(&data_path).unwrap().len(), empty_len);
+
+    series.commit().unwrap();
+    assert!(std::fs::metadata(&data_path).unwrap().len() > empty_len);
+}
+
+#[test]
+fn commits_when_full() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("buffered_commits_when_full");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .buffer_writes(10, Duration::MAX)
+        .open(test_path)
+        .unwrap();
+    let mut reader = series.reader();
+
+    insert_timestamps(&mut series, 25, 1000, 0);
+    assert_eq!(reader.len().unwrap(), 20);
+}
+
+#[test]
+fn drop_commits() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("buffered_drop_commits");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .buffer_writes(usize::MAX, Duration::MAX)
+        .open(&test_path)
+        .unwrap();
+    insert_timestamps(&mut series, 1000, 70_000, 1000);
+    drop(series);
+
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    let expected: Vec<_> = (0..1000).map(|i| 1000 + i * 70_000).collect();
+    assert_eq!(read_all(&mut series), expected);
+}
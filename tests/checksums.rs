@@ -0,0 +1,315 @@
+use std::path::Path;
+
+use byteseries::series::{data::ReadError, Error};
+use byteseries::ByteSeries;
+use pretty_assertions::assert_eq;
+use rstest::rstest;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, EmptyDecoder, Timestamp};
+
+const LINE_SIZE: usize = 2 + 4;
+/// with this many lines a new meta section starts after every 66 lines
+const STEP: Timestamp = 1000;
+
+fn open(test_path: &Path, create_new: bool) -> ByteSeries {
+    ByteSeries::builder()
+        .payload_size(4)
+        .create_new(create_new)
+        .with_any_header()
+        .checksums(true)
+        .open(test_path)
+        .unwrap()
+        .0
+}
+
+fn fill(test_path: &Path, lines: std::ops::Range<u64>) {
+    let mut series = open(test_path, true);
+    for i in lines {
+        series
+            .push_line(i * STEP, (i as u32).to_le_bytes())
+            .unwrap();
+    }
+}
+
+fn read_all(series: &mut ByteSeries) -> Result<Vec<Timestamp>, Error> {
+    let mut timestamps = Vec::new();
+    series.read_all(.., &mut EmptyDecoder, &mut timestamps, &mut Vec::new())?;
+    Ok(timestamps)
+}
+
+fn expected(lines: impl Iterator<Item = u64>) -> Vec<Timestamp> {
+    lines.map(|i| i * STEP).collect()
+}
+
+/// Changes the payload of the line that starts `line` lines into the data,
+/// meta section lines included
+fn flip_payload_byte(test_path: &Path, line: usize) {
+    let path = test_path.with_extension("byteseries");
+    let mut file = std::fs::read(&path).unwrap();
+    let header_len = u16::from_le_bytes([file[0], file[1]]) as usize;
+    let data_start = 4 + header_len;
+    file[data_start + line * LINE_SIZE + 2] ^= 0b1000;
+    std::fs::write(&path, file).unwrap();
+}
+
+fn checksums_len(test_path: &Path) -> u64 {
+    std::fs::metadata(test_path.with_extension("byteseries_checksums"))
+        .unwrap()
+        .len()
+}
+
+#[test]
+fn corrupt_line_is_detected() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("checksums_corrupt_line_is_detected");
+    fill(&test_path, 0..1000);
+    // the first meta section takes up two lines
+    flip_payload_byte(&test_path, 10);
+
+    let mut series = open(&test_path, false);
+    let err = read_all(&mut series).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Reading(ReadError::ChecksumMismatch { section_start: 0 })
+        ),
+        "{err:?}"
+    );
+    // the corrupt section is not touched
+    let mut timestamps = Vec::new();
+    series
+        .read_all(
+            500 * STEP..,
+            &mut EmptyDecoder,
+            &mut timestamps,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    assert_eq!(timestamps, expected(500..1000));
+    drop(series);
+
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .with_any_header()
+        .with_callback_on_recoverable_corruption(Box::new(|_| true))
+        .open(&test_path)
+        .unwrap();
+    assert_eq!(read_all(&mut series).unwrap(), expected(66..1000));
+}
+
+#[test]
+fn survives_rewrites() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("checksums_survives_rewrites");
+    fill(&test_path, 0..1000);
+    let mut series = open(&test_path, false);
+
+    series.truncate_after(899 * STEP).unwrap();
+    assert_eq!(read_all(&mut series).unwrap(), expected(0..900));
+    series.drop_before(100 * STEP).unwrap();
+    series.compact().unwrap();
+    for i in 900..1000 {
+        series
+            .push_line(i * STEP, (i as u32).to_le_bytes())
+            .unwrap();
+    }
+    // drop keeps the whole meta section holding the first line to keep
+    let expected = expected(66..1000);
+    assert_eq!(read_all(&mut series).unwrap(), expected);
+    drop(series);
+
+    let mut series = open(&test_path, false);
+    assert_eq!(read_all(&mut series).unwrap(), expected);
+    assert!(checksums_len(&test_path) > 0);
+    drop(series);
+
+    flip_payload_byte(&test_path, 10);
+    let mut series = open(&test_path, false);
+    assert!(read_all(&mut series).is_err());
+}
+
+#[test]
+fn missing_checksums_are_recalculated() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("checksums_missing_checksums_are_recalculated");
+    fill(&test_path, 0..1000);
+    let len = checksums_len(&test_path);
+    std::fs::remove_file(test_path.with_extension("byteseries_checksums")).unwrap();
+
+    let mut series = open(&test_path, false);
+    assert_eq!(checksums_len(&test_path), len);
+    assert_eq!(read_all(&mut series).unwrap(), expected(0..1000));
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn compressed() {
+    use byteseries::Compression;
+
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("checksums_compressed");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_any_header()
+        .checksums(true)
+        .compression(Compression::Lz4)
+        .open(&test_path)
+        .unwrap();
+    for i in 0..30_000 {
+        series
+            .push_line(i * STEP, (i as u32).to_le_bytes())
+            .unwrap();
+    }
+    drop(series);
+
+    let mut series = open(&test_path, false);
+    assert_eq!(read_all(&mut series).unwrap(), expected(0..30_000));
+    series.drop_before(20_000 * STEP).unwrap();
+    let read = read_all(&mut series).unwrap();
+    let first_kept = read[0] / STEP;
+    assert!(first_kept <= 20_000);
+    assert_eq!(read, expected(first_kept..30_000));
+}
+
+/// Offset of the meta section before line `66 * section`
+fn section_start(section: u64) -> u64 {
+    section * 68 * LINE_SIZE as u64
+}
+
+/// Corrupts a line in `section` and returns the lines in it
+fn corrupt_section(test_path: &Path, section: u64) -> std::ops::Range<u64> {
+    flip_payload_byte(test_path, section as usize * 68 + 10);
+    66 * section..66 * (section + 1)
+}
+
+fn collect(
+    iter: impl Iterator<Item = Result<(Timestamp, ()), ReadError>>,
+) -> (Vec<Timestamp>, Option<ReadError>) {
+    let mut timestamps = Vec::new();
+    for res in iter {
+        match res {
+            Ok((ts, ())) => timestamps.push(ts),
+            Err(e) => return (timestamps, Some(e)),
+        }
+    }
+    (timestamps, None)
+}
+
+// section 40 starts in the first 16 KiB chunk the iterator reads and ends
+// in the second
+#[rstest]
+#[case(5)]
+#[case(40)]
+fn reader_and_iterators_detect_corruption(#[case] section: u64) {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("checksums_reader_and_iterators_detect_corruption");
+    fill(&test_path, 0..3000);
+    let corrupt = corrupt_section(&test_path, section);
+    let mismatch = |err: &Option<ReadError>| {
+        matches!(err, Some(ReadError::ChecksumMismatch { section_start: start })
+            if *start == section_start(section))
+    };
+
+    let mut series = open(&test_path, false);
+    let (read, err) = collect(series.iter_range(.., &mut EmptyDecoder).unwrap());
+    assert_eq!(read, expected(0..corrupt.start));
+    assert!(mismatch(&err), "{err:?}");
+
+    let mut reader = series.reader();
+    let err = reader
+        .read_all(.., &mut EmptyDecoder, &mut Vec::new(), &mut Vec::new())
+        .unwrap_err();
+    let Error::Reading(err) = err else {
+        panic!("expected a read error, got: {err:?}");
+    };
+    assert!(mismatch(&Some(err)));
+    let (read, err) = collect(reader.iter_range(.., &mut EmptyDecoder).unwrap());
+    assert_eq!(read, expected(0..corrupt.start));
+    assert!(mismatch(&err), "{err:?}");
+    let (read, err) = collect(reader.iter_range_rev(.., &mut EmptyDecoder).unwrap());
+    assert_eq!(read, expected((corrupt.end..3000).rev()));
+    assert!(mismatch(&err), "{err:?}");
+}
+
+#[rstest]
+#[case(5)]
+#[case(40)]
+fn iterators_skip_corrupt_section(#[case] section: u64) {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("checksums_iterators_skip_corrupt_section");
+    fill(&test_path, 0..3000);
+    let corrupt = corrupt_section(&test_path, section);
+
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .with_any_header()
+        .with_callback_on_recoverable_corruption(Box::new(|_| true))
+        .open(&test_path)
+        .unwrap();
+    let expected = expected((0..corrupt.start).chain(corrupt.end..3000));
+    let (read, err) = collect(series.iter_range(.., &mut EmptyDecoder).unwrap());
+    assert!(err.is_none(), "{err:?}");
+    assert_eq!(read, expected);
+
+    let (mut read, err) = collect(series.iter_range_rev(.., &mut EmptyDecoder).unwrap());
+    assert!(err.is_none(), "{err:?}");
+    read.reverse();
+    assert_eq!(read, expected);
+}
+
+#[test]
+fn read_only_checks_checksums() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("checksums_read_only_checks_checksums");
+    fill(&test_path, 0..3000);
+    let corrupt = corrupt_section(&test_path, 5);
+
+    let (mut reader, _) = ByteSeries::builder()
+        .with_any_header()
+        .read_only()
+        .open(&test_path)
+        .unwrap();
+    let err = reader
+        .read_all(.., &mut EmptyDecoder, &mut Vec::new(), &mut Vec::new())
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Reading(ReadError::ChecksumMismatch { section_start: start })
+                if start == section_start(5)
+        ),
+        "{err:?}"
+    );
+
+    let (mut reader, _) = ByteSeries::builder()
+        .with_any_header()
+        .with_callback_on_recoverable_corruption(Box::new(|_| true))
+        .read_only()
+        .open(&test_path)
+        .unwrap();
+    let mut timestamps = Vec::new();
+    reader
+        .read_all(.., &mut EmptyDecoder, &mut timestamps, &mut Vec::new())
+        .unwrap();
+    assert_eq!(
+        timestamps,
+        expected((0..corrupt.start).chain(corrupt.end..3000))
+    );
+}
@@ -0,0 +1,182 @@
+use std::io::Write;
+use std::path::Path;
+
+use byteseries::{downsample, ByteSeries};
+use pretty_assertions::assert_eq;
+use rstest::rstest;
+use rstest_reuse::apply;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{payload_sizes, setup_tracing, EmptyDecoder, FakeFloatResampler, Timestamp};
+
+fn open(test_path: &Path, payload_size: usize, create_new: bool) -> ByteSeries {
+    ByteSeries::builder()
+        .payload_size(payload_size)
+        .create_new(create_new)
+        .with_any_header()
+        .with_downsampled_cache(
+            FakeFloatResampler { payload_size },
+            vec![downsample::Config {
+                max_gap: None,
+                bucket_size: 10,
+            }],
+        )
+        .open(test_path)
+        .unwrap()
+        .0
+}
+
+fn read_all(series: &mut ByteSeries) -> Vec<Timestamp> {
+    let mut read = Vec::new();
+    series
+        .read_all(.., &mut EmptyDecoder, &mut read, &mut Vec::new())
+        .unwrap();
+    read
+}
+
+/// Appends lines with a payload of 8 bytes that each get their own meta
+/// section, the last line is written twice.
+fn append_fragmented(test_path: &Path, timestamps: &[Timestamp]) {
+    let mut bytes = Vec::new();
+    for ts in timestamps {
+        let t = ts.to_le_bytes();
+        for half in [&t[0..4], &t[4..8]] {
+            bytes.extend_from_slice(&[255, 255]);
+            bytes.extend_from_slice(half);
+            bytes.extend_from_slice(&[0; 4]);
+        }
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+    }
+    bytes.extend_from_slice(&0u16.to_le_bytes());
+    bytes.extend_from_slice(&[0; 8]);
+
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(test_path.with_extension("byteseries"))
+        .unwrap()
+        .write_all(&bytes)
+        .unwrap();
+}
+
+#[test]
+fn removes_needless_meta_sections() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compact_removes_needless_meta_sections");
+    let mut series = open(&test_path, 8, true);
+    for ts in 1000..1100 {
+        series.push_line(ts, [0; 8]).unwrap();
+    }
+    drop(series);
+    let fragmented: Vec<_> = (0..200).map(|i| 5000 + i * 3).collect();
+    append_fragmented(&test_path, &fragmented);
+
+    let mut series = open(&test_path, 8, false);
+    let mut reader = series.reader();
+    let mut expected: Vec<_> = (1000..1100).collect();
+    expected.extend_from_slice(&fragmented);
+    let file_len = || {
+        std::fs::metadata(test_path.with_extension("byteseries"))
+            .unwrap()
+            .len()
+    };
+    let len_before = file_len();
+
+    let report = series.compact().unwrap();
+    assert_eq!(report.out_of_order_lines, 1);
+    // every fragmented line but the first had a meta section of two lines
+    // of ten bytes and an index entry of 16 bytes
+    let needless = 199 * (2 * 10 + 16);
+    assert!(
+        report.bytes_saved() >= needless,
+        "report: {report:?}, needless: {needless}"
+    );
+    assert!(len_before - file_len() >= 199 * 2 * 10 + 10);
+    assert_eq!(read_all(&mut series), expected);
+    assert_eq!(series.len(), expected.len() as u64);
+
+    let mut read = Vec::new();
+    reader
+        .read_all(.., &mut EmptyDecoder, &mut read, &mut Vec::new())
+        .unwrap();
+    assert_eq!(read, expected);
+
+    let mut resampled = Vec::new();
+    series
+        .read_n(
+            30,
+            ..,
+            &mut FakeFloatResampler { payload_size: 8 },
+            &mut resampled,
+            &mut Vec::new(),
+            false,
+        )
+        .unwrap();
+    assert_eq!(resampled.len(), 30);
+
+    series.push_line(6000, [0; 8]).unwrap();
+    expected.push(6000);
+    drop(series);
+
+    let mut series = open(&test_path, 8, false);
+    assert_eq!(read_all(&mut series), expected);
+}
+
+#[apply(payload_sizes)]
+#[trace]
+fn keeps_every_line(#[case] payload_size: usize) {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compact_keeps_every_line");
+    let mut series = open(&test_path, payload_size, true);
+    let mut ts = 0;
+    let expected: Vec<_> = (0..2_000)
+        .map(|i| {
+            ts += if i % 100 == 0 { 100_000 } else { 7 };
+            ts
+        })
+        .collect();
+    for ts in &expected {
+        series.push_line(*ts, vec![0; payload_size]).unwrap();
+    }
+
+    let report = series.compact().unwrap();
+    assert_eq!(report.out_of_order_lines, 0);
+    assert_eq!(report.bytes_saved(), 0);
+    assert_eq!(read_all(&mut series), expected);
+    drop(series);
+
+    let mut series = open(&test_path, payload_size, false);
+    assert_eq!(read_all(&mut series), expected);
+}
+
+#[test]
+fn compact_to_writes_new_series() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compact_to_writes_new_series");
+    let mut series = open(&test_path, 8, true);
+    for ts in 1000..1100 {
+        series.push_line(ts, [0; 8]).unwrap();
+    }
+    drop(series);
+    let fragmented: Vec<_> = (0..20).map(|i| 5000 + i * 3).collect();
+    append_fragmented(&test_path, &fragmented);
+
+    let mut series = open(&test_path, 8, false);
+    let dest = test_dir.child("compact_to_writes_new_series_dest");
+    let report = series.compact_to(&dest).unwrap();
+    assert_eq!(report.out_of_order_lines, 1);
+    assert!(report.bytes_saved() > 0);
+    assert!(series.compact_to(&dest).is_err());
+
+    let mut expected: Vec<_> = (1000..1100).collect();
+    expected.extend_from_slice(&fragmented);
+    let mut compacted = open(&dest, 8, false);
+    assert_eq!(read_all(&mut compacted), expected);
+}
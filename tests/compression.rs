@@ -0,0 +1,444 @@
+#![cfg(feature = "lz4")]
+
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use byteseries::series::compression::{Field, LayoutError};
+use byteseries::series::data::ReadError;
+use byteseries::{series, ByteSeries, Compression, Decoder};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, Timestamp};
+
+#[derive(Debug, Clone)]
+struct TsDecoder;
+
+impl byteseries::Decoder for TsDecoder {
+    type Item = Timestamp;
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        u64::from_le_bytes(line.try_into().expect("is 8 long")) as Timestamp
+    }
+}
+
+fn open(test_path: &Path, compression: Compression, create_new: bool) -> ByteSeries {
+    ByteSeries::builder()
+        .payload_size(8)
+        .create_new(create_new)
+        .with_any_header()
+        .compression(compression)
+        .open(test_path)
+        .unwrap()
+        .0
+}
+
+fn read_all(series: &mut ByteSeries) -> Vec<Timestamp> {
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_all(.., &mut TsDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    assert_eq!(timestamps, data);
+    timestamps
+}
+
+/// Spans multiple blocks, the payload is the timestamp
+fn push(series: &mut ByteSeries, timestamps: impl Iterator<Item = Timestamp>) {
+    for ts in timestamps {
+        series.push_line(ts, ts.to_le_bytes()).unwrap();
+    }
+}
+
+const N: u64 = 30_000;
+
+#[test]
+fn reads_back_after_reopen() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_reads_back_after_reopen");
+    let mut series = open(&test_path, Compression::Lz4, true);
+    push(&mut series, 0..N);
+    let lines = (N..2 * N).map(|ts| (ts, ts.to_le_bytes()));
+    series.push_lines(lines).unwrap();
+
+    let expected: Vec<_> = (0..2 * N).collect();
+    assert_eq!(read_all(&mut series), expected);
+    drop(series);
+
+    let mut series = open(&test_path, Compression::Lz4, false);
+    assert_eq!(read_all(&mut series), expected);
+    push(&mut series, 2 * N..2 * N + 10);
+    assert_eq!(series.last_line(&mut TsDecoder).unwrap().0, 2 * N + 9);
+}
+
+#[test]
+fn smaller_than_uncompressed() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let compressed_path = test_dir.child("compression_smaller_compressed");
+    let plain_path = test_dir.child("compression_smaller_plain");
+    let mut compressed = open(&compressed_path, Compression::Lz4, true);
+    let mut plain = open(&plain_path, Compression::None, true);
+    // the payload changes slowly, like that of most sensors
+    for ts in 0..N {
+        let payload = (ts / 1000).to_le_bytes();
+        compressed.push_line(ts, payload).unwrap();
+        plain.push_line(ts, payload).unwrap();
+    }
+    drop(compressed);
+    drop(plain);
+
+    let size = |path: &Path| {
+        std::fs::metadata(path.with_extension("byteseries"))
+            .unwrap()
+            .len()
+    };
+    assert!(size(&compressed_path) * 2 < size(&plain_path));
+}
+
+#[test]
+fn truncate_into_sealed_block() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_truncate_into_sealed_block");
+    let mut series = open(&test_path, Compression::Lz4, true);
+    push(&mut series, 0..N);
+
+    series.truncate_after(N / 10).unwrap();
+    push(&mut series, N..N + 10);
+    let mut expected: Vec<_> = (0..=N / 10).collect();
+    expected.extend(N..N + 10);
+    assert_eq!(read_all(&mut series), expected);
+    drop(series);
+
+    let mut series = open(&test_path, Compression::Lz4, false);
+    assert_eq!(read_all(&mut series), expected);
+}
+
+#[test]
+fn drop_before_and_compact() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_drop_before_and_compact");
+    let mut series = open(&test_path, Compression::Lz4, true);
+    push(&mut series, 0..N);
+
+    series.drop_before(N / 2).unwrap();
+    let first = read_all(&mut series)[0];
+    assert!(first <= N / 2);
+    let expected: Vec<_> = (first..N).collect();
+    assert_eq!(read_all(&mut series), expected);
+
+    series.compact().unwrap();
+    push(&mut series, N..N + 10);
+    let expected: Vec<_> = (first..N + 10).collect();
+    assert_eq!(read_all(&mut series), expected);
+    drop(series);
+
+    let mut series = open(&test_path, Compression::Lz4, false);
+    assert_eq!(read_all(&mut series), expected);
+}
+
+#[test]
+fn open_reads_block_table() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_open_reads_block_table");
+    let mut series = open(&test_path, Compression::Lz4, true);
+    push(&mut series, 0..N);
+    drop(series);
+
+    // break the start of the first frame header, only a scan of the data
+    // file reads it
+    let path = test_path.with_extension("byteseries");
+    let mut file = std::fs::read(&path).unwrap();
+    let header_len = u16::from_le_bytes([file[0], file[1]]) as usize;
+    file[4 + header_len] ^= 0xFF;
+    std::fs::write(&path, file).unwrap();
+
+    let mut series = open(&test_path, Compression::Lz4, false);
+    assert_eq!(read_all(&mut series), (0..N).collect::<Vec<_>>());
+}
+
+#[test]
+fn rebuilds_missing_block_table() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_rebuilds_missing_block_table");
+    let mut series = open(&test_path, Compression::Lz4, true);
+    push(&mut series, 0..N);
+    drop(series);
+
+    let table_path = test_path.with_extension("byteseries_blocks");
+    let table = std::fs::read(&table_path).unwrap();
+    assert!(!table.is_empty());
+    std::fs::remove_file(&table_path).unwrap();
+
+    let mut series = open(&test_path, Compression::Lz4, false);
+    assert_eq!(read_all(&mut series), (0..N).collect::<Vec<_>>());
+    assert_eq!(std::fs::read(&table_path).unwrap(), table);
+    push(&mut series, N..2 * N);
+    drop(series);
+
+    let mut series = open(&test_path, Compression::Lz4, false);
+    assert_eq!(read_all(&mut series), (0..2 * N).collect::<Vec<_>>());
+}
+
+#[test]
+fn reader_follows_writer() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_reader_follows_writer");
+    let mut series = open(&test_path, Compression::Lz4, true);
+    let mut reader = series.reader();
+
+    push(&mut series, 0..N);
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    reader
+        .read_all(.., &mut TsDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    assert_eq!(timestamps, (0..N).collect::<Vec<_>>());
+    assert_eq!(timestamps, data);
+}
+
+#[test]
+fn reader_follows_writer_from_other_thread() {
+    setup_tracing();
+
+    // many blocks get sealed while the reader reads the last line
+    const N: u64 = 300_000;
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_reader_follows_writer_from_other_thread");
+    let mut series = open(&test_path, Compression::Lz4, true);
+    let reader = series.reader();
+
+    thread::scope(|s| {
+        let read_thread = s.spawn(move || {
+            let mut reader = reader;
+            let mut prev = 0;
+            while prev < N - 1 {
+                let (ts, item) = match reader.last_line(&mut TsDecoder) {
+                    // nothing has been pushed yet
+                    Err(series::Error::Reading(ReadError::NoData)) => continue,
+                    res => res.unwrap(),
+                };
+                assert!(ts >= prev);
+                assert_eq!(ts, item);
+                prev = ts;
+            }
+        });
+
+        push(&mut series, 0..N);
+        read_thread.join().unwrap();
+    });
+}
+
+#[test]
+fn buffered_writes() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_buffered_writes");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .compression(Compression::Lz4)
+        .buffer_writes(usize::MAX, Duration::MAX)
+        .open(&test_path)
+        .unwrap();
+
+    push(&mut series, 0..N);
+    series.commit().unwrap();
+    drop(series);
+
+    let mut series = open(&test_path, Compression::Lz4, false);
+    assert_eq!(read_all(&mut series), (0..N).collect::<Vec<_>>());
+}
+
+#[derive(Debug, Clone)]
+struct SensorDecoder;
+
+impl byteseries::Decoder for SensorDecoder {
+    type Item = (f32, f32, u16);
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        (
+            f32::from_le_bytes(line[0..4].try_into().unwrap()),
+            f32::from_le_bytes(line[4..8].try_into().unwrap()),
+            u16::from_le_bytes(line[8..10].try_into().unwrap()),
+        )
+    }
+}
+
+fn sensor_line(ts: Timestamp) -> [u8; 10] {
+    let mut line = [0; 10];
+    line[0..4].copy_from_slice(&(20.0 + (ts / 100) as f32 * 0.5).to_le_bytes());
+    line[4..8].copy_from_slice(&(ts as f32).sin().to_le_bytes());
+    line[8..10].copy_from_slice(&((ts * 3) as u16).to_le_bytes());
+    line
+}
+
+fn open_with_layout(test_path: &Path, create_new: bool) -> ByteSeries {
+    ByteSeries::builder()
+        .payload_size(10)
+        .create_new(create_new)
+        .with_any_header()
+        .compression(Compression::Lz4)
+        .payload_layout([Field::F32, Field::F32, Field::U16])
+        .open(test_path)
+        .unwrap()
+        .0
+}
+
+#[test]
+fn payload_layout_reads_back() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_payload_layout_reads_back");
+    let mut series = open_with_layout(&test_path, true);
+    for ts in 0..N {
+        series.push_line(ts, sensor_line(ts)).unwrap();
+    }
+    series.truncate_after(N - 100).unwrap();
+    drop(series);
+
+    // the compression and layout are read from the file
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(10)
+        .with_any_header()
+        .open(&test_path)
+        .unwrap();
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_all(.., &mut SensorDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    assert_eq!(timestamps, (0..=N - 100).collect::<Vec<_>>());
+    let expected: Vec<_> = timestamps
+        .iter()
+        .map(|ts| SensorDecoder.decode_payload(&sensor_line(*ts)))
+        .collect();
+    assert_eq!(data, expected);
+}
+
+#[test]
+fn payload_layout_compresses_better() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let layout_path = test_dir.child("compression_payload_layout_better");
+    let plain_path = test_dir.child("compression_payload_layout_plain");
+    let mut with_layout = open_with_layout(&layout_path, true);
+    let (mut without, _) = ByteSeries::builder()
+        .payload_size(10)
+        .create_new(true)
+        .with_any_header()
+        .compression(Compression::Lz4)
+        .open(&plain_path)
+        .unwrap();
+    for ts in 0..N {
+        // a counter that grows with a constant step
+        let mut line = [0; 10];
+        line[0..8].copy_from_slice(&(ts * 1000).to_le_bytes());
+        with_layout.push_line(ts, line).unwrap();
+        without.push_line(ts, line).unwrap();
+    }
+    drop(with_layout);
+    drop(without);
+
+    let size = |path: &Path| {
+        std::fs::metadata(path.with_extension("byteseries"))
+            .unwrap()
+            .len()
+    };
+    assert!(size(&layout_path) < size(&plain_path));
+}
+
+#[test]
+fn payload_layout_must_fit() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_payload_layout_must_fit");
+    let err = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .compression(Compression::Lz4)
+        .payload_layout([Field::F32, Field::U16])
+        .open(&test_path)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        series::Error::PayloadLayout(LayoutError::SizeMismatch {
+            layout: 6,
+            payload: 8
+        })
+    ));
+
+    let err = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .payload_layout([Field::F64])
+        .open(&test_path)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        series::Error::PayloadLayout(LayoutError::NeedsCompression)
+    ));
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_reads_back() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("compression_zstd_reads_back");
+    let compression = Compression::Zstd { level: 3 };
+    let mut series = open(&test_path, compression, true);
+    push(&mut series, 0..N);
+    drop(series);
+
+    let mut series = open(&test_path, compression, false);
+    assert_eq!(read_all(&mut series), (0..N).collect::<Vec<_>>());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_level_kept_after_reopen() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let compression = Compression::Zstd { level: 19 };
+    let in_one_go = test_dir.child("compression_zstd_level_in_one_go");
+    let mut series = open(&in_one_go, compression, true);
+    push(&mut series, 0..N);
+    drop(series);
+
+    let reopened = test_dir.child("compression_zstd_level_reopened");
+    let mut series = open(&reopened, compression, true);
+    push(&mut series, 0..N / 2);
+    drop(series);
+    // the compression passed in is ignored for an existing series
+    let mut series = open(&reopened, Compression::None, false);
+    push(&mut series, N / 2..N);
+    drop(series);
+
+    let data = |path: &Path| std::fs::read(path.with_extension("byteseries")).unwrap();
+    assert_eq!(data(&in_one_go), data(&reopened));
+}
@@ -0,0 +1,197 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use byteseries::series::corruption::{Series, Skipped};
+use byteseries::{downsample, ByteSeries, CorruptionEvent};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, EmptyDecoder, FloatResampler, Timestamp};
+
+const LINE_SIZE: usize = 2 + 4;
+/// with this many lines a new meta section starts after every 66 lines
+const STEP: Timestamp = 1000;
+const CONFIG: downsample::Config = downsample::Config {
+    max_gap: None,
+    bucket_size: 10,
+};
+
+type Events = Arc<Mutex<Vec<CorruptionEvent>>>;
+
+fn fill(test_path: &Path, checksums: bool) {
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_any_header()
+        .with_downsampled_cache(FloatResampler, vec![CONFIG])
+        .checksums(checksums)
+        .open(test_path)
+        .unwrap();
+    for i in 0..1000u64 {
+        series
+            .push_line(i * STEP, (i as f32).to_le_bytes())
+            .unwrap();
+    }
+}
+
+/// Opens the series with a callback that records the events and skips
+fn open_recording(test_path: &Path) -> (ByteSeries, Events) {
+    let events = Events::default();
+    let recorded = Arc::clone(&events);
+    let (series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .with_any_header()
+        .with_downsampled_cache(FloatResampler, vec![CONFIG])
+        .with_callback_on_recoverable_corruption(Box::new(move |event| {
+            recorded.lock().unwrap().push(event.clone());
+            true
+        }))
+        .open(test_path)
+        .unwrap();
+    (series, events)
+}
+
+/// Returns the data of the file and where the data starts in it
+fn read_file(path: &Path) -> (Vec<u8>, usize) {
+    let file = std::fs::read(path).unwrap();
+    let header_len = u16::from_le_bytes([file[0], file[1]]) as usize;
+    (file, 4 + header_len)
+}
+
+/// Offsets of the meta sections in the data file
+fn meta_starts(path: &Path) -> Vec<u64> {
+    let (file, data_start) = read_file(path);
+    let mut starts = Vec::new();
+    let mut lines = file[data_start..].chunks_exact(LINE_SIZE).enumerate();
+    while let Some((i, line)) = lines.next() {
+        if line[..2] == [255, 255] {
+            starts.push((i * LINE_SIZE) as u64);
+            lines.next();
+        }
+    }
+    starts
+}
+
+/// Breaks the second line of the meta section at `meta_start`
+fn break_second_preamble(path: &Path, meta_start: u64) {
+    let (mut file, data_start) = read_file(path);
+    let second_line = data_start + meta_start as usize + LINE_SIZE;
+    file[second_line..second_line + 2].copy_from_slice(&[0, 0]);
+    std::fs::write(path, file).unwrap();
+}
+
+#[test]
+fn checksum_mismatch() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("corruption_event_checksum_mismatch");
+    fill(&test_path, true);
+    let path = test_path.with_extension("byteseries");
+    let section_start = meta_starts(&path)[2];
+    let (mut file, data_start) = read_file(&path);
+    file[data_start + section_start as usize + 3 * LINE_SIZE + 2] ^= 0b1000;
+    std::fs::write(&path, file).unwrap();
+
+    let (mut series, events) = open_recording(&test_path);
+    let mut timestamps = Vec::new();
+    let skipped = series
+        .read_all(.., &mut EmptyDecoder, &mut timestamps, &mut Vec::new())
+        .unwrap();
+
+    // the meta section and the 66 lines after it
+    let lines_to_skip = 2 + 66;
+    assert_eq!(
+        skipped,
+        Skipped {
+            corruptions: 1,
+            lines: lines_to_skip,
+        }
+    );
+    assert_eq!(timestamps.len(), 1000 - 66);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![CorruptionEvent {
+            series: Series::Main,
+            offset: section_start,
+            last_meta_ts: 66 * STEP,
+            lines_to_skip,
+        }]
+    );
+}
+
+#[test]
+fn corrupt_meta_section() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("corruption_event_corrupt_meta_section");
+    fill(&test_path, false);
+    let path = test_path.with_extension("byteseries");
+    let meta_start = meta_starts(&path)[3];
+    break_second_preamble(&path, meta_start);
+
+    let (mut series, events) = open_recording(&test_path);
+    let mut decoder = EmptyDecoder;
+    let mut iter = series.iter_range(.., &mut decoder).unwrap();
+    let n_read = iter.by_ref().map(Result::unwrap).count();
+    // only the meta section is skipped
+    assert_eq!(n_read, 1000);
+    assert_eq!(
+        iter.skipped(),
+        Skipped {
+            corruptions: 1,
+            lines: 2,
+        }
+    );
+    drop(iter);
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![CorruptionEvent {
+            series: Series::Main,
+            offset: meta_start,
+            last_meta_ts: 2 * 66 * STEP,
+            lines_to_skip: 2,
+        }]
+    );
+}
+
+#[test]
+fn in_downsampled_cache() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("corruption_event_in_downsampled_cache");
+    fill(&test_path, false);
+    let mut cache_name = test_path.file_name().unwrap().to_owned();
+    cache_name.push("_");
+    cache_name.push(CONFIG.file_name_suffix());
+    let cache_path = test_path
+        .with_file_name(cache_name)
+        .with_extension("byteseries");
+    let meta_start = meta_starts(&cache_path)[1];
+    break_second_preamble(&cache_path, meta_start);
+
+    let (mut series, events) = open_recording(&test_path);
+    let mut timestamps = Vec::new();
+    let skipped = series
+        .read_n(
+            10,
+            ..,
+            &mut FloatResampler,
+            &mut timestamps,
+            &mut Vec::new(),
+            false,
+        )
+        .unwrap();
+    assert_eq!(skipped.corruptions, 1);
+
+    let events = events.lock().unwrap();
+    let [event] = events.as_slice() else {
+        panic!("there should be one event, got: {events:?}");
+    };
+    assert_eq!(event.series, Series::Downsampled(CONFIG));
+    assert_eq!(event.offset, meta_start);
+}
@@ -0,0 +1,175 @@
+use byteseries::{series, ByteSeries, DuplicatePolicy};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::setup_tracing;
+
+#[derive(Debug, Clone)]
+struct U64Decoder;
+
+impl byteseries::Decoder for U64Decoder {
+    type Item = u64;
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        u64::from_le_bytes(line.try_into().expect("is 8 long"))
+    }
+}
+
+fn open(test_dir: &TempDir, policy: DuplicatePolicy, reorder: bool) -> ByteSeries {
+    let builder = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .on_duplicate(policy);
+    let builder = if reorder {
+        builder.reorder_window(100)
+    } else {
+        builder
+    };
+    builder.open(test_dir.child("series")).unwrap().0
+}
+
+fn push(series: &mut ByteSeries, ts: u64, value: u64) -> Result<(), series::Error> {
+    series.push_line(ts, value.to_le_bytes())
+}
+
+fn read_all(series: &mut ByteSeries) -> (Vec<u64>, Vec<u64>) {
+    let mut timestamps = Vec::new();
+    let mut values = Vec::new();
+    series
+        .read_all(.., &mut U64Decoder, &mut timestamps, &mut values)
+        .unwrap();
+    (timestamps, values)
+}
+
+#[test]
+fn reject() {
+    setup_tracing();
+    let test_dir = TempDir::new().unwrap();
+    let mut series = open(&test_dir, DuplicatePolicy::Reject, false);
+
+    push(&mut series, 10, 1).unwrap();
+    assert!(matches!(
+        push(&mut series, 10, 2),
+        Err(series::Error::TimeNotAfterLast { new: 10, prev: 10 })
+    ));
+    assert_eq!(read_all(&mut series), (vec![10], vec![1]));
+}
+
+#[test]
+fn keep_first() {
+    setup_tracing();
+    let test_dir = TempDir::new().unwrap();
+    let mut series = open(&test_dir, DuplicatePolicy::KeepFirst, false);
+
+    push(&mut series, 10, 1).unwrap();
+    push(&mut series, 10, 2).unwrap();
+    series
+        .push_lines([10u64, 20, 20, 30].map(|ts| (ts, ts.to_le_bytes())))
+        .unwrap();
+    assert_eq!(read_all(&mut series), (vec![10, 20, 30], vec![1, 20, 30]));
+}
+
+#[test]
+fn overwrite() {
+    setup_tracing();
+    let test_dir = TempDir::new().unwrap();
+    let mut series = open(&test_dir, DuplicatePolicy::Overwrite, false);
+
+    for ts in 1..=100 {
+        push(&mut series, ts, ts).unwrap();
+    }
+    push(&mut series, 100, 42).unwrap();
+    assert_eq!(series.last_line(&mut U64Decoder).unwrap(), (100, 42));
+
+    series
+        .push_lines(
+            [(100, 43u64), (101, 1), (101, 2)].map(|(ts, v)| (ts, v.to_le_bytes())),
+        )
+        .unwrap();
+    let (timestamps, values) = read_all(&mut series);
+    assert_eq!(timestamps, (1..=101).collect::<Vec<_>>());
+    assert_eq!(values[98..], [99, 43, 2]);
+
+    drop(series);
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .with_any_header()
+        .open(test_dir.child("series"))
+        .unwrap();
+    assert_eq!(read_all(&mut series).1[98..], [99, 43, 2]);
+}
+
+#[test]
+fn merge() {
+    setup_tracing();
+    let test_dir = TempDir::new().unwrap();
+    let sum = DuplicatePolicy::Merge(Box::new(|a, b| {
+        let a = u64::from_le_bytes(a.try_into().unwrap());
+        let b = u64::from_le_bytes(b.try_into().unwrap());
+        (a + b).to_le_bytes().to_vec()
+    }));
+    let mut series = open(&test_dir, sum, false);
+
+    push(&mut series, 10, 1).unwrap();
+    push(&mut series, 10, 2).unwrap();
+    series
+        .push_lines([(10, 3u64), (20, 1), (20, 1)].map(|(ts, v)| (ts, v.to_le_bytes())))
+        .unwrap();
+    assert_eq!(read_all(&mut series), (vec![10, 20], vec![6, 2]));
+}
+
+#[test]
+fn merge_must_keep_length() {
+    setup_tracing();
+    let test_dir = TempDir::new().unwrap();
+    let policy = DuplicatePolicy::Merge(Box::new(|_, _| Vec::new()));
+    let mut series = open(&test_dir, policy, false);
+
+    push(&mut series, 10, 1).unwrap();
+    assert!(matches!(
+        push(&mut series, 10, 2),
+        Err(series::Error::WrongLineLength {
+            required: 8,
+            got: 0
+        })
+    ));
+}
+
+#[test]
+fn in_reorder_window() {
+    setup_tracing();
+    let test_dir = TempDir::new().unwrap();
+    let mut series = open(&test_dir, DuplicatePolicy::Overwrite, true);
+
+    push(&mut series, 50, 1).unwrap();
+    push(&mut series, 60, 1).unwrap();
+    push(&mut series, 50, 2).unwrap();
+    series
+        .push_lines([(60, 2u64), (55, 1)].map(|(ts, v)| (ts, v.to_le_bytes())))
+        .unwrap();
+    assert_eq!(read_all(&mut series), (vec![50, 55, 60], vec![2, 1, 2]));
+}
+
+#[test]
+fn overwrite_keeps_line_readers_see() {
+    setup_tracing();
+    let test_dir = TempDir::new().unwrap();
+    let mut series = open(&test_dir, DuplicatePolicy::Overwrite, false);
+
+    push(&mut series, 10, 1).unwrap();
+    let mut reader = series.reader();
+    push(&mut series, 10, 2).unwrap();
+    let mut timestamps = Vec::new();
+    let mut values = Vec::new();
+    reader
+        .read_all(.., &mut U64Decoder, &mut timestamps, &mut values)
+        .unwrap();
+    assert_eq!((timestamps, values), (vec![10], vec![1]));
+    assert_eq!(read_all(&mut series), (vec![10], vec![1]));
+
+    drop(reader);
+    push(&mut series, 10, 3).unwrap();
+    assert_eq!(read_all(&mut series), (vec![10], vec![3]));
+}
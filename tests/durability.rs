@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use byteseries::{downsample, ByteSeries, Durability};
+use pretty_assertions::assert_eq;
+use rstest::rstest;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, EmptyDecoder, FakeFloatResampler};
+
+#[rstest]
+#[case(Durability::None)]
+#[case(Durability::EveryN(7))]
+#[case(Durability::Interval(Duration::ZERO))]
+#[case(Durability::EveryPush)]
+fn lines_survive_reopen(#[case] durability: Durability) {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("durability_lines_survive_reopen");
+    let open = |create_new| {
+        ByteSeries::builder()
+            .payload_size(4)
+            .create_new(create_new)
+            .with_any_header()
+            .with_downsampled_cache(
+                FakeFloatResampler { payload_size: 4 },
+                vec![downsample::Config {
+                    max_gap: None,
+                    bucket_size: 10,
+                }],
+            )
+            .durability(durability)
+            .open(&test_path)
+            .unwrap()
+            .0
+    };
+
+    let mut series = open(true);
+    for ts in 0..100 {
+        series.push_line(ts * 10, 1.0f32.to_le_bytes()).unwrap();
+    }
+    series
+        .push_lines((100..200).map(|ts| (ts * 10, 1.0f32.to_le_bytes())))
+        .unwrap();
+    drop(series);
+
+    let mut series = open(false);
+    let mut timestamps = Vec::new();
+    series
+        .read_all(.., &mut EmptyDecoder, &mut timestamps, &mut Vec::new())
+        .unwrap();
+    assert_eq!(timestamps, (0..200).map(|ts| ts * 10).collect::<Vec<_>>());
+
+    let mut timestamps = Vec::new();
+    series
+        .read_n(
+            20,
+            ..,
+            &mut FakeFloatResampler { payload_size: 4 },
+            &mut timestamps,
+            &mut Vec::new(),
+            false,
+        )
+        .unwrap();
+    assert_eq!(timestamps.len(), 20);
+}
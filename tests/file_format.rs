@@ -0,0 +1,118 @@
+use std::error::Error as _;
+use std::path::Path;
+
+use byteseries::series::Error;
+use byteseries::ByteSeries;
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, EmptyDecoder, Timestamp};
+
+/// Header parameters as written by version 1 of the file format
+fn v1_params(payload_size: usize) -> Vec<u8> {
+    let text = format!(
+        "\nNote: 8 line ASCII preamble followed by binary data.
+
+    This is a byteseries 1 file, an embedded timeseries file. Time may here may
+    be whatever value as long as it is monotonically increasing. The entries
+    have a fixed length that never changes. For this file that is: {payload_size} bytes.
+
+    In the case the creator of this file wanted to store metadata in it that
+    follows now:\n
+     "
+    );
+    let mut params = (text.len() as u32).to_le_bytes().to_vec();
+    params.extend_from_slice(text.as_bytes());
+    params
+}
+
+/// Creates a series and then swaps its header for one in version 1 of the
+/// file format
+fn create_v1(test_path: &Path, user_header: &[u8], n: u64) {
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(0)
+        .create_new(true)
+        .with_header(user_header.to_vec())
+        .open(test_path)
+        .unwrap();
+    for ts in 0..n {
+        series.push_line(ts, []).unwrap();
+    }
+    drop(series);
+
+    let path = test_path.with_extension("byteseries");
+    let file = std::fs::read(&path).unwrap();
+    let header_len = u16::from_le_bytes([file[0], file[1]]) as usize;
+    let data = &file[4 + header_len..];
+
+    let mut header = v1_params(0);
+    header.extend_from_slice(user_header);
+    let mut v1 = (header.len() as u16).to_le_bytes().to_vec();
+    v1.extend_from_slice(b"\n\n");
+    v1.extend_from_slice(&header);
+    v1.extend_from_slice(data);
+    std::fs::write(&path, v1).unwrap();
+}
+
+fn read_all(test_path: &Path, user_header: &[u8]) -> Vec<Timestamp> {
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(0)
+        .with_header(user_header.to_vec())
+        .open(test_path)
+        .unwrap();
+    let mut timestamps = Vec::new();
+    series
+        .read_all(.., &mut EmptyDecoder, &mut timestamps, &mut Vec::new())
+        .unwrap();
+    timestamps
+}
+
+#[test]
+fn v1_opens_and_upgrades() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("file_format_v1_opens_and_upgrades");
+    create_v1(&test_path, b"user header", 100);
+    let expected: Vec<_> = (0..100).collect();
+    assert_eq!(read_all(&test_path, b"user header"), expected);
+
+    assert!(ByteSeries::upgrade(&test_path).unwrap());
+    let file = std::fs::read(test_path.with_extension("byteseries")).unwrap();
+    assert_eq!(&file[4..14], b"byteseries");
+    assert_eq!(read_all(&test_path, b"user header"), expected);
+
+    assert!(!ByteSeries::upgrade(&test_path).unwrap());
+}
+
+#[test]
+fn corrupt_parameters_are_detected() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("file_format_corrupt_parameters_are_detected");
+    ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_any_header()
+        .open(&test_path)
+        .unwrap();
+
+    let path = test_path.with_extension("byteseries");
+    let mut file = std::fs::read(&path).unwrap();
+    // header length, line ends, magic, version, block length, tag, length
+    let payload_size = 2 + 2 + 10 + 2 + 2 + 1 + 2;
+    assert_eq!(file[payload_size], 4);
+    file[payload_size] = 8;
+    std::fs::write(&path, file).unwrap();
+
+    let err = ByteSeries::builder()
+        .payload_size(8)
+        .with_any_header()
+        .open(&test_path)
+        .unwrap_err();
+    assert!(matches!(err, Error::Parameters(_)));
+    let source = err.source().unwrap().to_string();
+    assert!(source.contains("checksum"), "{source}");
+}
@@ -1,5 +1,5 @@
 use byteseries::series::Error;
-use byteseries::ByteSeries;
+use byteseries::{ByteSeries, HeaderError};
 use serde::{Deserialize, Serialize};
 use temp_dir::TempDir;
 
@@ -59,3 +59,65 @@ fn opening_with_correct_header_is_ok() {
 
     assert_eq!(header, test_header1)
 }
+
+#[test]
+fn typed_header_round_trips() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("typed_header_round_trips");
+
+    {
+        let (_, header) = ByteSeries::builder()
+            .create_new(true)
+            .with_typed_header(TestHeader(1))
+            .payload_size(0)
+            .open(&test_path)
+            .unwrap();
+        assert_eq!(header, TestHeader(1));
+    }
+
+    let (_, header) = ByteSeries::builder()
+        .payload_size(0)
+        .with_typed_header(TestHeader(1))
+        .open(&test_path)
+        .unwrap();
+    assert_eq!(header, TestHeader(1));
+
+    let res = ByteSeries::builder()
+        .payload_size(0)
+        .with_typed_header(TestHeader(2))
+        .open(&test_path)
+        .unwrap_err();
+    assert!(matches!(
+        res,
+        Error::Header(HeaderError::TypedMismatch { .. })
+    ));
+}
+
+#[test]
+fn typed_header_of_other_type_is_err() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("typed_header_of_other_type");
+
+    {
+        let _ = ByteSeries::builder()
+            .create_new(true)
+            .with_header("not ron".as_bytes().to_owned())
+            .payload_size(0)
+            .open(&test_path)
+            .unwrap();
+    }
+
+    let res = ByteSeries::builder()
+        .payload_size(0)
+        .with_typed_header(TestHeader(1))
+        .open(&test_path)
+        .unwrap_err();
+    let Error::Header(HeaderError::Deserializing(err)) = res else {
+        panic!("expected a deserialize error, got: {res:?}");
+    };
+    assert_eq!(err.header(), b"not ron");
+}
@@ -0,0 +1,138 @@
+use std::ops::Bound;
+
+use byteseries::ByteSeries;
+use pretty_assertions::assert_eq;
+use rstest::rstest;
+use rstest_reuse::apply;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{insert_timestamps, payload_sizes, setup_tracing, EmptyDecoder, Timestamp};
+
+#[derive(Debug)]
+struct TsDecoder;
+
+impl byteseries::Decoder for TsDecoder {
+    type Item = Timestamp;
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        u64::from_ne_bytes(line.try_into().expect("is 8 long")) as Timestamp
+    }
+}
+
+#[test]
+fn same_as_read_all() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("iter_same_as_read_all");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    // large steps so the data contains many meta sections and spans
+    // multiple chunks
+    insert_timestamps(&mut series, 10_000, 20, 1719330938);
+    insert_timestamps(&mut series, 100, 100_000, 1719330938 + 10_000 * 20);
+
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_all(.., &mut TsDecoder, &mut timestamps, &mut data)
+        .unwrap();
+
+    let (iter_timestamps, iter_data): (Vec<_>, Vec<_>) = series
+        .iter_range(.., &mut TsDecoder)
+        .unwrap()
+        .map(Result::unwrap)
+        .unzip();
+
+    assert_eq!(iter_timestamps, timestamps);
+    assert_eq!(iter_data, data);
+    assert_eq!(iter_timestamps, iter_data);
+}
+
+#[test]
+fn partial_range() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("iter_partial_range");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    insert_timestamps(&mut series, 100, 10, 1000);
+
+    let timestamps: Vec<_> = series
+        .iter_range(1100..1200, &mut TsDecoder)
+        .unwrap()
+        .map(|res| res.unwrap().0)
+        .collect();
+    assert_eq!(timestamps, (1100..1200).step_by(10).collect::<Vec<_>>());
+}
+
+#[test]
+fn empty_when_range_in_gap() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("iter_empty_when_range_in_gap");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(0)
+        .create_new(true)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    series.push_line(0, []).unwrap();
+    series.push_line(200_000, []).unwrap();
+
+    let mut decoder = EmptyDecoder;
+    let mut iter = series.iter_range(100_000..=120_000, &mut decoder).unwrap();
+    assert!(iter.next().is_none());
+}
+
+#[apply(payload_sizes)]
+#[trace]
+fn rev_is_reversed_forward(#[case] payload_size: usize) {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("rev_is_reversed_forward");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(payload_size)
+        .create_new(true)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    let mut ts = 1000;
+    for i in 0..10_000u64 {
+        // every once in a while a jump to force a new meta section
+        ts += if i % 1000 == 0 { 100_000 } else { 7 };
+        series.push_line(ts, vec![i as u8; payload_size]).unwrap();
+    }
+
+    for (start, end) in [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(50_000), Bound::Excluded(300_000)),
+        (Bound::Included(101_007), Bound::Included(101_007)),
+    ] {
+        let range = (start, end);
+        let forward: Vec<_> = series
+            .iter_range(range, &mut EmptyDecoder)
+            .unwrap()
+            .map(|res| res.unwrap().0)
+            .collect();
+        let mut reversed: Vec<_> = series
+            .iter_range_rev(range, &mut EmptyDecoder)
+            .unwrap()
+            .map(|res| res.unwrap().0)
+            .collect();
+        reversed.reverse();
+        assert_eq!(forward, reversed);
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::Path;
+
+use byteseries::series::{self, data};
+use byteseries::{downsample, ByteSeries};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, FloatResampler, Timestamp};
+
+/// A line with a temperature and a humidity, both f32
+#[derive(Debug, Clone)]
+struct PairDecoder;
+
+impl byteseries::Decoder for PairDecoder {
+    type Item = (f32, f32);
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        (
+            f32::from_le_bytes(line[0..4].try_into().unwrap()),
+            f32::from_le_bytes(line[4..8].try_into().unwrap()),
+        )
+    }
+}
+
+fn cache_config() -> downsample::Config {
+    downsample::Config {
+        max_gap: None,
+        bucket_size: 10,
+    }
+}
+
+fn create(test_path: &Path, n_lines: u64) {
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_header(b"temperature".to_vec())
+        .with_downsampled_cache(FloatResampler, vec![cache_config()])
+        .open(test_path)
+        .unwrap();
+    for ts in 0..n_lines {
+        series.push_line(ts, (ts as f32).to_le_bytes()).unwrap();
+    }
+}
+
+fn add_humidity(old: &[u8]) -> Vec<u8> {
+    let mut new = old.to_vec();
+    new.extend_from_slice(&50f32.to_le_bytes());
+    new
+}
+
+fn read_all(series: &mut ByteSeries) -> (Vec<Timestamp>, Vec<(f32, f32)>) {
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_all(.., &mut PairDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    (timestamps, data)
+}
+
+#[test]
+fn adds_field() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("migrate_adds_field");
+    create(&test_path, 1000);
+
+    let report = ByteSeries::migrate(&test_path, 8, add_humidity).unwrap();
+    assert_eq!(report.out_of_order_lines, 0);
+
+    let (mut series, header) = ByteSeries::builder()
+        .payload_size(8)
+        .with_any_header()
+        .open(&test_path)
+        .unwrap();
+    assert_eq!(header, b"temperature");
+    let (timestamps, data) = read_all(&mut series);
+    assert_eq!(timestamps, (0..1000).collect::<Vec<_>>());
+    let expected: Vec<_> = (0..1000).map(|ts| (ts as f32, 50.0)).collect();
+    assert_eq!(data, expected);
+
+    series
+        .push_line(1000, add_humidity(&1000f32.to_le_bytes()))
+        .unwrap();
+    assert_eq!(series.range(), Some(0..=1000));
+}
+
+#[test]
+fn updates_header_and_removes_caches() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("migrate_updates_header_and_removes_caches");
+    create(&test_path, 1000);
+    let cache_path = test_dir.child(format!(
+        "migrate_updates_header_and_removes_caches_{}",
+        cache_config().file_name_suffix()
+    ));
+    assert!(cache_path.with_extension("byteseries").exists());
+
+    ByteSeries::migrate_with_header(
+        &test_path,
+        8,
+        |mut header| {
+            header.extend_from_slice(b" and humidity");
+            header
+        },
+        add_humidity,
+    )
+    .unwrap();
+    assert!(!cache_path.with_extension("byteseries").exists());
+    assert!(!cache_path.with_extension("byteseries_index").exists());
+
+    ByteSeries::builder()
+        .payload_size(8)
+        .with_header(b"temperature and humidity".to_vec())
+        .open(&test_path)
+        .unwrap();
+}
+
+#[test]
+fn wrong_length_changes_nothing() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("migrate_wrong_length_changes_nothing");
+    create(&test_path, 100);
+
+    let err = ByteSeries::migrate(&test_path, 8, |old| old.to_vec()).unwrap_err();
+    assert!(matches!(
+        err,
+        series::Error::Migrating(data::CompactError::MigratedLength {
+            required: 8,
+            got: 4
+        })
+    ));
+
+    let (series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .with_any_header()
+        .with_downsampled_cache(FloatResampler, vec![cache_config()])
+        .open(&test_path)
+        .unwrap();
+    assert_eq!(series.len(), 100);
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn keeps_compression() {
+    use byteseries::Compression;
+
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("migrate_keeps_compression");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_any_header()
+        .compression(Compression::Lz4)
+        .open(&test_path)
+        .unwrap();
+    for ts in 0..30_000 {
+        series.push_line(ts, (ts as f32).to_le_bytes()).unwrap();
+    }
+    drop(series);
+
+    ByteSeries::migrate(&test_path, 8, add_humidity).unwrap();
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .with_any_header()
+        .open(&test_path)
+        .unwrap();
+    let (timestamps, data) = read_all(&mut series);
+    assert_eq!(timestamps, (0..30_000).collect::<Vec<_>>());
+    assert_eq!(data[29_999], (29_999.0, 50.0));
+}
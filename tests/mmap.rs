@@ -0,0 +1,100 @@
+use byteseries::ByteSeries;
+use pretty_assertions::assert_eq;
+use rstest::rstest;
+use rstest_reuse::apply;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{insert_timestamps, payload_sizes, setup_tracing, EmptyDecoder, Timestamp};
+
+#[derive(Debug)]
+struct TsDecoder;
+
+impl byteseries::Decoder for TsDecoder {
+    type Item = Timestamp;
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        u64::from_ne_bytes(line.try_into().expect("is 8 long")) as Timestamp
+    }
+}
+
+#[test]
+fn reads_lines_pushed_after_mapping() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("mmap_reads_lines_pushed_after_mapping");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .mmap_reads(true)
+        .open(test_path)
+        .unwrap();
+
+    let mut start = 1000;
+    for _ in 0..5 {
+        insert_timestamps(&mut series, 1000, 100, start);
+        start += 1000 * 100;
+
+        let mut timestamps = Vec::new();
+        let mut data = Vec::new();
+        series
+            .read_all(.., &mut TsDecoder, &mut timestamps, &mut data)
+            .unwrap();
+        let expected: Vec<_> = (1000..start).step_by(100).collect();
+        assert_eq!(timestamps, expected);
+        assert_eq!(data, expected);
+    }
+}
+
+#[apply(payload_sizes)]
+#[trace]
+fn same_as_unmapped(#[case] payload_size: usize) {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("mmap_same_as_unmapped");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(payload_size)
+        .create_new(true)
+        .with_any_header()
+        .open(&test_path)
+        .unwrap();
+    let mut ts = 0;
+    for i in 0..10_000u64 {
+        // every once in a while a jump to force a new meta section
+        ts += if i % 1000 == 0 { 100_000 } else { 7 };
+        series.push_line(ts, vec![i as u8; payload_size]).unwrap();
+    }
+    drop(series);
+
+    let (mut unmapped, _) = ByteSeries::builder()
+        .payload_size(payload_size)
+        .with_any_header()
+        .open(&test_path)
+        .unwrap();
+    let (mut mapped, _) = ByteSeries::builder()
+        .payload_size(payload_size)
+        .with_any_header()
+        .mmap_reads(true)
+        .open(&test_path)
+        .unwrap();
+
+    for range in [0..u64::MAX, 50_000..300_000, 101_007..101_008] {
+        let mut expected = Vec::new();
+        unmapped
+            .read_all(
+                range.clone(),
+                &mut EmptyDecoder,
+                &mut expected,
+                &mut Vec::new(),
+            )
+            .unwrap();
+        let mut timestamps = Vec::new();
+        mapped
+            .read_all(range, &mut EmptyDecoder, &mut timestamps, &mut Vec::new())
+            .unwrap();
+        assert_eq!(timestamps, expected);
+    }
+}
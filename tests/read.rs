@@ -111,3 +111,34 @@ fn read_a_single_item() {
         .unwrap();
     assert_eq!(timestamps.pop(), Some(timestamp));
 }
+
+#[test]
+fn read_last_n_is_oldest_first() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("read_last_n_is_oldest_first");
+    let (mut series, _) = ByteSeries::builder()
+        .create_new(true)
+        .payload_size(8)
+        .with_any_header()
+        .open(&test_path)
+        .unwrap();
+    insert_timestamps(&mut series, 1_000, 100_000, 0);
+
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_last_n(5, .., &mut TsDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    let expected: Vec<_> = (995..1_000).map(|i| i * 100_000).collect();
+    assert_eq!(timestamps, expected);
+    assert_eq!(data, expected);
+
+    timestamps.clear();
+    data.clear();
+    series
+        .read_last_n(5, ..=300_000, &mut TsDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    assert_eq!(timestamps, [0, 100_000, 200_000, 300_000]);
+}
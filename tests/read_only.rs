@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use byteseries::{downsample, ByteSeries, SeriesReader};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, EmptyDecoder, FloatResampler, Timestamp};
+
+/// with this many lines a new meta section starts after every 66 lines
+const STEP: Timestamp = 1000;
+
+fn fill(test_path: &Path, lines: std::ops::Range<u64>) {
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_any_header()
+        .with_downsampled_cache(
+            FloatResampler,
+            vec![downsample::Config {
+                max_gap: None,
+                bucket_size: 10,
+            }],
+        )
+        .open(test_path)
+        .unwrap();
+    for i in lines {
+        series
+            .push_line(i * STEP, (i as f32).to_le_bytes())
+            .unwrap();
+    }
+}
+
+fn open_read_only(test_path: &Path) -> SeriesReader {
+    ByteSeries::builder()
+        .with_any_header()
+        .read_only()
+        .open(test_path)
+        .unwrap()
+        .0
+}
+
+fn read_all(reader: &mut SeriesReader) -> Vec<Timestamp> {
+    let mut timestamps = Vec::new();
+    reader
+        .read_all(.., &mut EmptyDecoder, &mut timestamps, &mut Vec::new())
+        .unwrap();
+    timestamps
+}
+
+fn expected(lines: std::ops::Range<u64>) -> Vec<Timestamp> {
+    lines.map(|i| i * STEP).collect()
+}
+
+fn all_files(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| (path.clone(), std::fs::read(path).unwrap()))
+        .collect()
+}
+
+#[test]
+fn torn_tail_is_ignored() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("read_only_torn_tail_is_ignored");
+    fill(&test_path, 0..1000);
+
+    let path = test_path.with_extension("byteseries");
+    let mut file = std::fs::read(&path).unwrap();
+    // the start of a meta section and part of a line
+    file.extend_from_slice(&[255, 255, 0, 0, 0, 0, 1, 2, 3]);
+    std::fs::write(&path, file).unwrap();
+
+    let before = all_files(test_dir.path());
+    let mut reader = open_read_only(&test_path);
+    assert_eq!(read_all(&mut reader), expected(0..1000));
+    assert_eq!(reader.len().unwrap(), 1000);
+    drop(reader);
+    assert_eq!(before, all_files(test_dir.path()));
+}
+
+#[test]
+fn missing_index_is_built_in_memory() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("read_only_missing_index_is_built_in_memory");
+    fill(&test_path, 0..1000);
+    std::fs::remove_file(test_path.with_extension("byteseries_index")).unwrap();
+
+    let before = all_files(test_dir.path());
+    let mut reader = open_read_only(&test_path);
+    assert_eq!(read_all(&mut reader), expected(0..1000));
+    let mut timestamps = Vec::new();
+    reader
+        .read_all(
+            500 * STEP..600 * STEP,
+            &mut EmptyDecoder,
+            &mut timestamps,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    assert_eq!(timestamps, expected(500..600));
+    drop(reader);
+    assert_eq!(before, all_files(test_dir.path()));
+}
+
+#[test]
+fn read_n_without_caches() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("read_only_read_n_without_caches");
+    fill(&test_path, 0..1000);
+
+    let mut reader = open_read_only(&test_path);
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    reader
+        .read_n(10, .., &mut FloatResampler, &mut timestamps, &mut data)
+        .unwrap();
+
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .with_any_header()
+        .open(&test_path)
+        .unwrap();
+    let mut expected_timestamps = Vec::new();
+    let mut expected_data = Vec::new();
+    series
+        .read_n(
+            10,
+            ..,
+            &mut FloatResampler,
+            &mut expected_timestamps,
+            &mut expected_data,
+            false,
+        )
+        .unwrap();
+    assert!(!timestamps.is_empty());
+    assert_eq!(timestamps, expected_timestamps);
+    assert_eq!(data, expected_data);
+}
+
+#[test]
+fn header_is_checked() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("read_only_header_is_checked");
+    ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_header(b"header".to_vec())
+        .open(&test_path)
+        .unwrap();
+
+    let res = ByteSeries::builder()
+        .with_header(b"other".to_vec())
+        .read_only()
+        .open(&test_path);
+    assert!(matches!(res, Err(byteseries::series::Error::Header(_))));
+    let (_, header) = ByteSeries::builder()
+        .payload_size(4)
+        .with_header(b"header".to_vec())
+        .read_only()
+        .open(&test_path)
+        .unwrap();
+    assert_eq!(header, b"header");
+}
@@ -0,0 +1,179 @@
+use std::sync::mpsc;
+use std::thread;
+
+use byteseries::series::Error;
+use byteseries::{seek, ByteSeries};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{insert_timestamps, setup_tracing, EmptyDecoder, Timestamp};
+
+#[derive(Debug, Clone)]
+struct TsDecoder;
+
+impl byteseries::Decoder for TsDecoder {
+    type Item = Timestamp;
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        u64::from_ne_bytes(line.try_into().expect("is 8 long")) as Timestamp
+    }
+}
+
+#[test]
+fn sees_lines_pushed_before_read() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("reader_sees_lines_pushed_before_read");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    let mut reader = series.reader();
+    assert_eq!(reader.len().unwrap(), 0);
+
+    insert_timestamps(&mut series, 100, 1000, 42);
+    assert_eq!(reader.len().unwrap(), 100);
+    assert_eq!(reader.range().unwrap(), series.range());
+    assert_eq!(
+        reader.last_line(&mut TsDecoder).unwrap(),
+        series.last_line(&mut TsDecoder).unwrap()
+    );
+
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    reader
+        .read_all(.., &mut TsDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    assert_eq!(
+        timestamps,
+        (42..42 + 100 * 1000).step_by(1000).collect::<Vec<_>>()
+    );
+    assert_eq!(timestamps, data);
+}
+
+#[test]
+fn read_while_appending_from_other_thread() {
+    setup_tracing();
+
+    const N: u64 = 20_000;
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("reader_read_while_appending");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    let reader = series.reader();
+
+    thread::scope(|s| {
+        let read_thread = s.spawn(move || {
+            let mut reader = reader;
+            let mut prev_len = 0;
+            while prev_len < N as usize {
+                let mut timestamps = Vec::new();
+                let mut data = Vec::new();
+                match reader.read_all(.., &mut TsDecoder, &mut timestamps, &mut data) {
+                    // nothing has been pushed yet
+                    Err(Error::InvalidRange(seek::Error::EmptyFile)) => continue,
+                    res => res.unwrap(),
+                };
+                assert!(timestamps.len() >= prev_len);
+                // step is large so new meta sections are written regularly
+                let expected: Vec<_> = (0..timestamps.len() as u64)
+                    .map(|i| 1000 + i * 7_000)
+                    .collect();
+                assert_eq!(timestamps, expected);
+                assert_eq!(timestamps, data);
+                prev_len = timestamps.len();
+            }
+        });
+
+        insert_timestamps(&mut series, N as u32, 7_000, 1000);
+        read_thread.join().unwrap();
+    });
+}
+
+#[test]
+fn clones_read_independently() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("reader_clones_read_independently");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    insert_timestamps(&mut series, 1000, 100_000, 0);
+
+    let reader = series.reader();
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let mut reader = reader.clone();
+            thread::spawn(move || {
+                reader
+                    .iter_range(.., &mut EmptyDecoder)
+                    .unwrap()
+                    .map(|res| res.unwrap().0)
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let expected: Vec<_> = (0..1000).map(|i| i * 100_000).collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), expected);
+    }
+}
+
+#[test]
+fn iterate_while_truncated_from_other_thread() {
+    setup_tracing();
+
+    const N: u64 = 100_000;
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("reader_iterate_while_truncated");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(8)
+        .create_new(true)
+        .mmap_reads(true)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    insert_timestamps(&mut series, N as u32, 7_000, 0);
+    let reader = series.reader();
+
+    let (started_tx, started_rx) = mpsc::channel();
+    let (truncated_tx, truncated_rx) = mpsc::channel();
+    thread::scope(|s| {
+        let read_thread = s.spawn(move || {
+            let mut reader = reader;
+            let mut decoder = TsDecoder;
+            let mut iter = reader.iter_range(.., &mut decoder).unwrap();
+            let first = iter.next().unwrap().unwrap().0;
+            started_tx.send(()).unwrap();
+            truncated_rx.recv().unwrap();
+            let rest: Vec<_> = iter.map(|res| res.unwrap().0).collect();
+            (first, rest)
+        });
+
+        started_rx.recv().unwrap();
+        series.truncate_after(10).unwrap();
+        truncated_tx.send(()).unwrap();
+
+        // the iterator keeps reading the lines there were when it started
+        let (first, rest) = read_thread.join().unwrap();
+        assert_eq!(first, 0);
+        let expected: Vec<_> = (1..N).map(|i| i * 7_000).collect();
+        assert_eq!(rest, expected);
+    });
+
+    let mut reader = series.reader();
+    assert_eq!(reader.len().unwrap(), 1);
+}
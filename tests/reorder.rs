@@ -0,0 +1,159 @@
+use byteseries::{series, ByteSeries};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, Timestamp};
+
+#[derive(Debug, Clone)]
+struct TsDecoder;
+
+impl byteseries::Decoder for TsDecoder {
+    type Item = Timestamp;
+
+    fn decode_payload(&mut self, line: &[u8]) -> Self::Item {
+        u64::from_ne_bytes(line.try_into().expect("is 8 long")) as Timestamp
+    }
+}
+
+fn open(test_path: &std::path::Path, create_new: bool) -> ByteSeries {
+    ByteSeries::builder()
+        .payload_size(8)
+        .create_new(create_new)
+        .with_any_header()
+        .reorder_window(100)
+        .open(test_path)
+        .unwrap()
+        .0
+}
+
+fn push(series: &mut ByteSeries, ts: Timestamp) -> Result<(), series::Error> {
+    series.push_line(ts, ts.to_ne_bytes())
+}
+
+fn read_all(series: &mut ByteSeries) -> Vec<Timestamp> {
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_all(.., &mut TsDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    assert_eq!(timestamps, data);
+    timestamps
+}
+
+/// every block of 10 timestamps is pushed in reverse
+fn shuffled(n: u64) -> impl Iterator<Item = Timestamp> {
+    (0..n).map(|i| (i / 10 * 10 + 9 - i % 10) * 10)
+}
+
+#[test]
+fn late_lines_are_sorted_in() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("reorder_late_lines_are_sorted_in");
+    let mut series = open(&test_path, true);
+    let mut reader = series.reader();
+
+    for ts in shuffled(1000) {
+        push(&mut series, ts).unwrap();
+    }
+    let expected: Vec<_> = (0..1000).map(|i| i * 10).collect();
+    assert_eq!(read_all(&mut series), expected);
+    assert_eq!(series.len(), 1000);
+    assert_eq!(series.range(), Some(0..=9990));
+    assert!(reader.len().unwrap() < 1000);
+
+    drop(series);
+    let mut series = open(&test_path, false);
+    assert_eq!(read_all(&mut series), expected);
+}
+
+#[test]
+fn too_late_is_rejected() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("reorder_too_late_is_rejected");
+    let mut series = open(&test_path, true);
+
+    push(&mut series, 1000).unwrap();
+    push(&mut series, 950).unwrap();
+    assert!(matches!(
+        push(&mut series, 850),
+        Err(series::Error::OutsideReorderWindow {
+            new: 850,
+            newest: 1000
+        })
+    ));
+    assert!(matches!(
+        push(&mut series, 950),
+        Err(series::Error::TimeNotAfterLast { .. })
+    ));
+    assert!(series
+        .push_lines([(990, 990u64.to_ne_bytes()), (800, 800u64.to_ne_bytes())])
+        .is_err());
+    assert_eq!(read_all(&mut series), vec![950, 1000]);
+
+    series.flush_reorder_window().unwrap();
+    assert!(matches!(
+        push(&mut series, 990),
+        Err(series::Error::TimeNotAfterLast { .. })
+    ));
+}
+
+#[test]
+fn reads_merge_window_and_data() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("reorder_reads_merge_window_and_data");
+    let mut series = open(&test_path, true);
+    series
+        .push_lines(shuffled(1000).map(|ts| (ts, ts.to_ne_bytes())))
+        .unwrap();
+
+    let expected: Vec<_> = (0..1000).map(|i| i * 10).collect();
+    let forward: Vec<_> = series
+        .iter_range(.., &mut TsDecoder)
+        .unwrap()
+        .map(|res| res.unwrap().0)
+        .collect();
+    assert_eq!(forward, expected);
+
+    let mut backward: Vec<_> = series
+        .iter_range_rev(.., &mut TsDecoder)
+        .unwrap()
+        .map(|res| res.unwrap().0)
+        .collect();
+    backward.reverse();
+    assert_eq!(backward, expected);
+
+    let only_window: Vec<_> = series
+        .iter_range(9_950.., &mut TsDecoder)
+        .unwrap()
+        .map(|res| res.unwrap().0)
+        .collect();
+    assert_eq!(only_window, expected[995..]);
+
+    let mut timestamps = Vec::new();
+    series
+        .read_last_n(20, .., &mut TsDecoder, &mut timestamps, &mut Vec::new())
+        .unwrap();
+    assert_eq!(timestamps, expected[980..]);
+
+    let mut timestamps = Vec::new();
+    series
+        .read_first_n(
+            20,
+            &mut TsDecoder,
+            9_800..,
+            &mut timestamps,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    assert_eq!(timestamps, expected[980..]);
+
+    assert_eq!(series.n_lines_between(9_800..).unwrap(), 20);
+    assert_eq!(series.last_line(&mut TsDecoder).unwrap(), (9_990, 9_990));
+}
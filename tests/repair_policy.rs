@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use byteseries::downsample::{self, OpenOrCreateError};
+use byteseries::series::data::OpenError;
+use byteseries::series::repair::{RepairAction, RepairReason};
+use byteseries::series::Error;
+use byteseries::{ByteSeries, RepairPolicy};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, EmptyDecoder, FloatResampler, Timestamp};
+
+/// the start of a meta section and part of a line
+const TORN_TAIL: [u8; 9] = [255, 255, 0, 0, 0, 0, 1, 2, 3];
+
+fn open(test_path: &Path, policy: RepairPolicy) -> Result<ByteSeries, Error> {
+    ByteSeries::builder()
+        .payload_size(4)
+        .with_any_header()
+        .repair_policy(policy)
+        .open(test_path)
+        .map(|(series, _)| series)
+}
+
+/// Returns the length of the data without the torn tail
+fn fill_and_tear(test_path: &Path) -> u64 {
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_any_header()
+        .open(test_path)
+        .unwrap();
+    for i in 0..100u64 {
+        series.push_line(i, (i as f32).to_le_bytes()).unwrap();
+    }
+    drop(series);
+
+    let path = test_path.with_extension("byteseries");
+    let mut file = std::fs::read(&path).unwrap();
+    let header_len = u16::from_le_bytes([file[0], file[1]]) as usize;
+    let data_len = (file.len() - 4 - header_len) as u64;
+    file.extend_from_slice(&TORN_TAIL);
+    std::fs::write(&path, file).unwrap();
+    data_len
+}
+
+fn all_files(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| (path.clone(), std::fs::read(path).unwrap()))
+        .collect()
+}
+
+fn read_all(series: &mut ByteSeries) -> Vec<Timestamp> {
+    let mut timestamps = Vec::new();
+    series
+        .read_all(.., &mut EmptyDecoder, &mut timestamps, &mut Vec::new())
+        .unwrap();
+    timestamps
+}
+
+#[test]
+fn report_lists_repairs_without_changing_files() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("repair_policy_report");
+    let data_len = fill_and_tear(&test_path);
+
+    let before = all_files(test_dir.path());
+    let Err(Error::Open(OpenError::RepairNeeded { actions })) =
+        open(&test_path, RepairPolicy::Report)
+    else {
+        panic!("opening should fail as repairs are needed");
+    };
+    let path = test_path.with_extension("byteseries");
+    assert_eq!(
+        actions,
+        vec![
+            RepairAction {
+                path: path.clone(),
+                reason: RepairReason::IncompleteLine,
+                removed: data_len + 6..data_len + 9,
+            },
+            RepairAction {
+                path,
+                reason: RepairReason::PartialMeta,
+                removed: data_len..data_len + 6,
+            },
+        ]
+    );
+    assert_eq!(before, all_files(test_dir.path()));
+}
+
+#[test]
+fn callback_decides() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("repair_policy_callback_decides");
+    fill_and_tear(&test_path);
+
+    let before = all_files(test_dir.path());
+    let res = open(&test_path, RepairPolicy::Callback(|_| false));
+    assert!(matches!(
+        res,
+        Err(Error::Open(OpenError::RepairNeeded { .. }))
+    ));
+    assert_eq!(before, all_files(test_dir.path()));
+
+    let res = open(
+        &test_path,
+        RepairPolicy::Callback(|action| action.removed.end - action.removed.start < 10),
+    );
+    let mut series = res.unwrap();
+    assert_eq!(read_all(&mut series), (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn removed_bytes_are_quarantined() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("repair_policy_quarantine");
+    let data_len = fill_and_tear(&test_path);
+
+    let mut series = open(&test_path, RepairPolicy::Auto).unwrap();
+    assert_eq!(read_all(&mut series), (0..100).collect::<Vec<_>>());
+    drop(series);
+
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&data_len.to_le_bytes());
+    expected.extend_from_slice(&(TORN_TAIL.len() as u64).to_le_bytes());
+    expected.extend_from_slice(&TORN_TAIL);
+    let quarantine = std::fs::read(test_path.with_extension("quarantine")).unwrap();
+    assert_eq!(quarantine, expected);
+
+    // nothing left to repair
+    drop(open(&test_path, RepairPolicy::Report).unwrap());
+}
+
+#[test]
+fn report_covers_downsampled_cache() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("repair_policy_cache");
+    let config = downsample::Config {
+        max_gap: None,
+        bucket_size: 10,
+    };
+    let open = |policy| {
+        ByteSeries::builder()
+            .payload_size(4)
+            .with_downsampled_cache(FloatResampler, vec![config.clone()])
+            .with_any_header()
+            .repair_policy(policy)
+            .open(&test_path)
+            .map(|(series, _)| series)
+    };
+
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_downsampled_cache(FloatResampler, vec![config.clone()])
+        .with_any_header()
+        .open(&test_path)
+        .unwrap();
+    for i in 0..100u64 {
+        series.push_line(i, (i as f32).to_le_bytes()).unwrap();
+    }
+    drop(series);
+
+    let mut cache_name = test_path.file_name().unwrap().to_owned();
+    cache_name.push("_");
+    cache_name.push(config.file_name_suffix());
+    let cache_path = test_path
+        .with_file_name(cache_name)
+        .with_extension("byteseries");
+    let mut cache = std::fs::read(&cache_path).unwrap();
+    cache.extend_from_slice(&TORN_TAIL);
+    std::fs::write(&cache_path, cache).unwrap();
+
+    let before = all_files(test_dir.path());
+    let res = open(RepairPolicy::Report);
+    assert!(
+        matches!(
+            res,
+            Err(Error::Downsampled(downsample::Error::OpenOrCreate(
+                OpenOrCreateError::Open(downsample::OpenError::Data(
+                    OpenError::RepairNeeded { .. }
+                ))
+            )))
+        ),
+        "{res:?}"
+    );
+    assert_eq!(before, all_files(test_dir.path()));
+
+    let mut series = open(RepairPolicy::Auto).unwrap();
+    assert_eq!(read_all(&mut series), (0..100).collect::<Vec<_>>());
+}
@@ -0,0 +1,197 @@
+use byteseries::{downsample, series, ByteSeries, Retention};
+use pretty_assertions::assert_eq;
+use rstest::rstest;
+use rstest_reuse::apply;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{payload_sizes, setup_tracing, EmptyDecoder, FakeFloatResampler, Timestamp};
+
+fn open(
+    test_path: &std::path::Path,
+    payload_size: usize,
+    create_new: bool,
+    retention: Option<Retention>,
+) -> ByteSeries {
+    let builder = ByteSeries::builder()
+        .payload_size(payload_size)
+        .create_new(create_new)
+        .with_any_header()
+        .with_downsampled_cache(
+            FakeFloatResampler { payload_size },
+            vec![downsample::Config {
+                max_gap: None,
+                bucket_size: 10,
+            }],
+        );
+    let builder = match retention {
+        Some(retention) => builder.retention(retention),
+        None => builder,
+    };
+    builder.open(test_path).unwrap().0
+}
+
+fn timestamps() -> impl Iterator<Item = Timestamp> {
+    let mut ts = 0;
+    (0..2_000u64).map(move |i| {
+        // a new meta section every 100 lines
+        ts += if i % 100 == 0 { 100_000 } else { 7 };
+        ts
+    })
+}
+
+fn read_all(series: &mut ByteSeries) -> Vec<Timestamp> {
+    let mut read = Vec::new();
+    series
+        .read_all(.., &mut EmptyDecoder, &mut read, &mut Vec::new())
+        .unwrap();
+    read
+}
+
+#[apply(payload_sizes)]
+#[trace]
+fn drops_sections_before(#[case] payload_size: usize) {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("retention_drops_sections_before");
+    let mut series = open(&test_path, payload_size, true, None);
+    let mut reader = series.reader();
+    for ts in timestamps() {
+        series.push_line(ts, vec![0; payload_size]).unwrap();
+    }
+
+    let all: Vec<_> = timestamps().collect();
+    // the first line of a section then one in the middle of a section
+    for (cut, first_kept) in [(all[1000], all[1000]), (all[1250], all[1200])] {
+        series.drop_before(cut).unwrap();
+        let expected: Vec<_> =
+            all.iter().copied().filter(|ts| *ts >= first_kept).collect();
+        assert_eq!(read_all(&mut series), expected);
+        assert_eq!(series.len(), expected.len() as u64);
+        assert_eq!(series.range(), Some(first_kept..=all[1999]));
+
+        let mut read = Vec::new();
+        reader
+            .read_all(.., &mut EmptyDecoder, &mut read, &mut Vec::new())
+            .unwrap();
+        assert_eq!(read, expected);
+
+        let mut resampled = Vec::new();
+        series
+            .read_n(
+                10,
+                ..,
+                &mut FakeFloatResampler { payload_size },
+                &mut resampled,
+                &mut Vec::new(),
+                false,
+            )
+            .unwrap();
+        assert!(resampled.iter().all(|ts| *ts >= first_kept));
+    }
+
+    series
+        .push_line(all[1999] + 1, vec![0; payload_size])
+        .unwrap();
+    let expected = read_all(&mut series);
+    drop(series);
+
+    let mut series = open(&test_path, payload_size, false, None);
+    assert_eq!(read_all(&mut series), expected);
+}
+
+#[test]
+fn after_last_line_empties() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("retention_after_last_line_empties");
+    let mut series = open(&test_path, 4, true, None);
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+
+    series.drop_before(u64::MAX).unwrap();
+    assert!(series.is_empty());
+    assert_eq!(series.range(), None);
+
+    series.push_line(5, [0; 4]).unwrap();
+    assert_eq!(read_all(&mut series), vec![5]);
+}
+
+#[test]
+fn max_age_applied_on_open() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("retention_max_age_applied_on_open");
+    let mut series = open(&test_path, 4, true, None);
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+    drop(series);
+
+    let all: Vec<_> = timestamps().collect();
+    let retention = Retention {
+        max_age: Some(all[1999] - all[1500]),
+        max_bytes: None,
+    };
+    let mut series = open(&test_path, 4, false, Some(retention));
+    assert_eq!(read_all(&mut series), all[1500..]);
+}
+
+#[test]
+fn not_applied_when_header_does_not_match() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("retention_not_applied_when_header_does_not_match");
+    let (mut series, _) = ByteSeries::builder()
+        .payload_size(4)
+        .create_new(true)
+        .with_header(b"right".to_vec())
+        .open(&test_path)
+        .unwrap();
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+    drop(series);
+
+    let res = ByteSeries::builder()
+        .payload_size(4)
+        .with_header(b"wrong".to_vec())
+        .retention(Retention {
+            max_age: Some(0),
+            max_bytes: None,
+        })
+        .open(&test_path);
+    assert!(matches!(res, Err(series::Error::Header(_))));
+
+    let mut series = open(&test_path, 4, false, None);
+    assert_eq!(read_all(&mut series), timestamps().collect::<Vec<_>>());
+}
+
+#[test]
+fn max_bytes_applied_while_pushing() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("retention_max_bytes_applied_while_pushing");
+    let max_bytes = 3_000;
+    let retention = Retention {
+        max_age: None,
+        max_bytes: Some(max_bytes),
+    };
+    let mut series = open(&test_path, 4, true, Some(retention));
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+
+    let all: Vec<_> = timestamps().collect();
+    let read = read_all(&mut series);
+    assert_eq!(read.last(), all.last());
+    // each line is the payload plus a two byte timestamp
+    assert!(read.len() * (4 + 2) <= max_bytes as usize);
+    assert!(read.len() < all.len());
+}
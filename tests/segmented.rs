@@ -0,0 +1,325 @@
+use byteseries::{Durability, Rollover, SegmentedSeries};
+use pretty_assertions::assert_eq;
+use rstest::rstest;
+use rstest_reuse::apply;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{payload_sizes, setup_tracing, EmptyDecoder, FakeFloatResampler, Timestamp};
+
+fn timestamps() -> impl Iterator<Item = Timestamp> {
+    (0..5_000u64).map(|i| 1_000 + i * 13)
+}
+
+fn read(
+    series: &mut SegmentedSeries,
+    range: impl std::ops::RangeBounds<u64>,
+) -> Vec<Timestamp> {
+    let mut read = Vec::new();
+    series
+        .read_all(range, &mut EmptyDecoder, &mut read, &mut Vec::new())
+        .unwrap();
+    read
+}
+
+#[apply(payload_sizes)]
+#[trace]
+fn rolls_over_per_period(#[case] payload_size: usize) {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_rolls_over_per_period");
+    let rollover = Rollover::Period(10_000);
+    let mut series = SegmentedSeries::open(&dir, payload_size, rollover).unwrap();
+    for ts in timestamps() {
+        series.push_line(ts, vec![0; payload_size]).unwrap();
+    }
+
+    let all: Vec<_> = timestamps().collect();
+    let last = *all.last().unwrap();
+    assert_eq!(series.n_segments() as u64, last / 10_000 + 1);
+    assert_eq!(read(&mut series, ..), all);
+    assert_eq!(series.range(), Some(all[0]..=last));
+    assert_eq!(series.len().unwrap(), all.len() as u64);
+
+    // crosses many segment boundaries
+    let expected: Vec<_> = all
+        .iter()
+        .copied()
+        .filter(|ts| (15_000..42_000).contains(ts))
+        .collect();
+    assert_eq!(read(&mut series, 15_000..42_000), expected);
+    drop(series);
+
+    let mut series = SegmentedSeries::open(&dir, payload_size, rollover).unwrap();
+    assert_eq!(read(&mut series, ..), all);
+    assert!(series.push_line(last, vec![0; payload_size]).is_err());
+    series.push_line(last + 1, vec![0; payload_size]).unwrap();
+    assert_eq!(read(&mut series, last..), vec![last, last + 1]);
+}
+
+#[test]
+fn rolls_over_by_size() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_rolls_over_by_size");
+    let mut series = SegmentedSeries::open(&dir, 8, Rollover::Size(4_000)).unwrap();
+    for ts in timestamps() {
+        series.push_line(ts, ts.to_le_bytes()).unwrap();
+    }
+
+    // each line is the payload plus a two byte timestamp
+    let lines_per_segment = 4_000 / 10;
+    assert!(series.n_segments() >= 5_000 / (lines_per_segment + 1));
+    assert_eq!(read(&mut series, ..), timestamps().collect::<Vec<_>>());
+}
+
+#[test]
+fn drop_segments_before_removes_files() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_drop_segments_before_removes_files");
+    let rollover = Rollover::Period(10_000);
+    let mut series = SegmentedSeries::open(&dir, 4, rollover).unwrap();
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+
+    let files = || std::fs::read_dir(&dir).unwrap().count();
+    let files_before = files();
+    let segments_before = series.n_segments();
+    assert_eq!(series.drop_segments_before(35_000).unwrap(), 3);
+    assert_eq!(series.n_segments(), segments_before - 3);
+    assert_eq!(files(), files_before - 3 * 2);
+
+    let expected: Vec<_> = timestamps().filter(|ts| *ts >= 30_000).collect();
+    assert_eq!(read(&mut series, ..), expected);
+    drop(series);
+
+    let mut series = SegmentedSeries::open(&dir, 4, rollover).unwrap();
+    assert_eq!(read(&mut series, ..), expected);
+    let segments = series.n_segments();
+    assert_eq!(series.drop_segments_before(u64::MAX).unwrap(), segments - 1);
+    assert_eq!(series.n_segments(), 1);
+}
+
+#[test]
+fn drop_segments_before_removes_every_file() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_drop_segments_before_removes_every_file");
+    let mut series = SegmentedSeries::open(&dir, 4, Rollover::Period(10_000)).unwrap();
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+    // files a segment can have next to its data and index
+    for name in [
+        "segment_000000.quarantine",
+        "segment_000000.byteseries_checksums",
+        "segment_000000_None_10.byteseries",
+        "segment_000000_None_10.byteseries_index",
+        "segment_000000_None_10.quarantine",
+    ] {
+        std::fs::write(dir.join(name), [0; 4]).unwrap();
+    }
+
+    assert_eq!(series.drop_segments_before(15_000).unwrap(), 1);
+    let left: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("segment_000000"))
+        .collect();
+    assert_eq!(left, Vec::<String>::new());
+}
+
+#[test]
+fn open_does_not_write_manifest() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_open_does_not_write_manifest");
+    let mut series = SegmentedSeries::open(&dir, 4, Rollover::Period(10_000)).unwrap();
+    series.push_line(1_000, [0; 4]).unwrap();
+    drop(series);
+
+    let manifest = dir.join("manifest.ron");
+    let before = std::fs::read_to_string(&manifest).unwrap();
+    let series = SegmentedSeries::open(&dir, 4, Rollover::Size(4_000)).unwrap();
+    drop(series);
+    assert_eq!(std::fs::read_to_string(&manifest).unwrap(), before);
+}
+
+#[test]
+fn zero_period_is_refused() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_zero_period_is_refused");
+    let res = SegmentedSeries::open(&dir, 4, Rollover::Period(0));
+    assert!(matches!(res, Err(byteseries::segmented::Error::ZeroPeriod)));
+}
+
+#[test]
+fn read_n_lines_across_segments() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_read_n_lines_across_segments");
+    let mut series = SegmentedSeries::open(&dir, 4, Rollover::Period(10_000)).unwrap();
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+    let all: Vec<_> = timestamps().collect();
+
+    let mut first = Vec::new();
+    series
+        .read_first_n(
+            1_000,
+            &mut EmptyDecoder,
+            15_000..,
+            &mut first,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    let expected: Vec<_> = all
+        .iter()
+        .copied()
+        .filter(|ts| *ts >= 15_000)
+        .take(1_000)
+        .collect();
+    assert_eq!(first, expected);
+
+    let mut last = Vec::new();
+    series
+        .read_last_n(
+            1_000,
+            ..42_000,
+            &mut EmptyDecoder,
+            &mut last,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    let before: Vec<_> = all.iter().copied().filter(|ts| *ts < 42_000).collect();
+    assert_eq!(last, before[before.len() - 1_000..]);
+
+    let mut resampled = Vec::new();
+    series
+        .read_n(
+            100,
+            15_000..42_000,
+            &mut FakeFloatResampler { payload_size: 4 },
+            &mut resampled,
+            &mut Vec::new(),
+            false,
+        )
+        .unwrap();
+    assert!((50..=200).contains(&resampled.len()), "{}", resampled.len());
+    assert!(resampled.windows(2).all(|w| w[0] < w[1]));
+    assert!(resampled.iter().all(|ts| (15_000..42_000).contains(ts)));
+}
+
+fn segment_files(dir: &std::path::Path, extension: &str) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .count()
+}
+
+#[test]
+fn rolls_over_by_size_with_options() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_rolls_over_by_size_with_options");
+    let open = || {
+        SegmentedSeries::open_with(&dir, 8, Rollover::Size(4_000), |builder| {
+            builder.checksums(true).durability(Durability::EveryPush)
+        })
+        .unwrap()
+    };
+    let mut series = open();
+    for ts in timestamps() {
+        series.push_line(ts, ts.to_le_bytes()).unwrap();
+    }
+
+    assert!(series.n_segments() > 1);
+    assert_eq!(
+        segment_files(&dir, "byteseries_checksums"),
+        series.n_segments()
+    );
+    assert_eq!(read(&mut series, ..), timestamps().collect::<Vec<_>>());
+    drop(series);
+
+    let mut series = open();
+    assert_eq!(read(&mut series, ..), timestamps().collect::<Vec<_>>());
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn rolls_over_per_period_with_options() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_rolls_over_per_period_with_options");
+    let open = || {
+        SegmentedSeries::open_with(&dir, 4, Rollover::Period(10_000), |builder| {
+            builder.compression(byteseries::Compression::Lz4)
+        })
+        .unwrap()
+    };
+    let mut series = open();
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+
+    let all: Vec<_> = timestamps().collect();
+    assert_eq!(series.n_segments() as u64, all.last().unwrap() / 10_000 + 1);
+    // only compressed series have a tail file
+    assert_eq!(segment_files(&dir, "byteseries_tail"), series.n_segments());
+    let expected: Vec<_> = all
+        .iter()
+        .copied()
+        .filter(|ts| (15_000..42_000).contains(ts))
+        .collect();
+    assert_eq!(read(&mut series, 15_000..42_000), expected);
+    drop(series);
+
+    let mut series = open();
+    assert_eq!(read(&mut series, ..), all);
+}
+
+#[test]
+fn drop_segments_after_reading_them() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let dir = test_dir.child("segmented_drop_segments_after_reading_them");
+    let mut series =
+        SegmentedSeries::open_with(&dir, 4, Rollover::Period(10_000), |builder| {
+            builder.checksums(true)
+        })
+        .unwrap();
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+
+    // opens the older segments
+    let expected: Vec<_> = timestamps().filter(|ts| *ts < 35_000).collect();
+    assert_eq!(read(&mut series, ..35_000), expected);
+    let segments_before = series.n_segments();
+    assert_eq!(series.drop_segments_before(35_000).unwrap(), 3);
+    assert_eq!(
+        segment_files(&dir, "byteseries_checksums"),
+        segments_before - 3
+    );
+    assert_eq!(segment_files(&dir, "byteseries"), segments_before - 3);
+
+    let expected: Vec<_> = timestamps().filter(|ts| *ts >= 30_000).collect();
+    assert_eq!(read(&mut series, ..), expected);
+    series.push_line(100_000, [0; 4]).unwrap();
+    assert_eq!(read(&mut series, 99_000..), vec![100_000]);
+}
@@ -0,0 +1,127 @@
+use byteseries::{downsample, ByteSeries};
+use pretty_assertions::assert_eq;
+use rstest::rstest;
+use rstest_reuse::apply;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{payload_sizes, setup_tracing, EmptyDecoder, FakeFloatResampler, Timestamp};
+
+fn open(
+    test_path: &std::path::Path,
+    payload_size: usize,
+    create_new: bool,
+) -> ByteSeries {
+    ByteSeries::builder()
+        .payload_size(payload_size)
+        .create_new(create_new)
+        .with_any_header()
+        .with_downsampled_cache(
+            FakeFloatResampler { payload_size },
+            vec![downsample::Config {
+                max_gap: None,
+                bucket_size: 10,
+            }],
+        )
+        .open(test_path)
+        .unwrap()
+        .0
+}
+
+fn timestamps() -> impl Iterator<Item = Timestamp> {
+    let mut ts = 0;
+    (0..2_000u64).map(move |i| {
+        // every once in a while a jump to force a new meta section
+        ts += if i % 100 == 0 { 100_000 } else { 7 };
+        ts
+    })
+}
+
+fn read_all(series: &mut ByteSeries) -> Vec<Timestamp> {
+    let mut read = Vec::new();
+    series
+        .read_all(.., &mut EmptyDecoder, &mut read, &mut Vec::new())
+        .unwrap();
+    read
+}
+
+#[apply(payload_sizes)]
+#[trace]
+fn removes_lines_after(#[case] payload_size: usize) {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("truncate_removes_lines_after");
+    let mut series = open(&test_path, payload_size, true);
+    let mut reader = series.reader();
+    for ts in timestamps() {
+        series.push_line(ts, vec![0; payload_size]).unwrap();
+    }
+
+    let all: Vec<_> = timestamps().collect();
+    // a cut in the middle of a section and one just before a new section
+    for cut in [all[1500], all[1399], all[733] + 3] {
+        series.truncate_after(cut).unwrap();
+        let expected: Vec<_> = all.iter().copied().filter(|ts| *ts <= cut).collect();
+        assert_eq!(read_all(&mut series), expected);
+        assert_eq!(series.len(), expected.len() as u64);
+        assert_eq!(series.range(), Some(all[0]..=*expected.last().unwrap()));
+        assert_eq!(reader.len().unwrap(), expected.len() as u64);
+
+        let mut resampled = Vec::new();
+        series
+            .read_n(
+                10,
+                ..,
+                &mut FakeFloatResampler { payload_size },
+                &mut resampled,
+                &mut Vec::new(),
+                false,
+            )
+            .unwrap();
+        assert!(resampled.iter().all(|ts| *ts <= cut));
+    }
+
+    let last = series.range().unwrap().into_inner().1;
+    series.push_line(last + 1, vec![0; payload_size]).unwrap();
+    let expected = read_all(&mut series);
+    drop(series);
+
+    let mut series = open(&test_path, payload_size, false);
+    assert_eq!(read_all(&mut series), expected);
+}
+
+#[test]
+fn before_first_line_empties() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("truncate_before_first_line_empties");
+    let mut series = open(&test_path, 4, true);
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+
+    series.truncate_after(10).unwrap();
+    assert!(series.is_empty());
+    assert_eq!(series.range(), None);
+
+    series.push_line(5, [0; 4]).unwrap();
+    assert_eq!(read_all(&mut series), vec![5]);
+}
+
+#[test]
+fn after_last_line_does_nothing() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("truncate_after_last_line_does_nothing");
+    let mut series = open(&test_path, 4, true);
+    series.truncate_after(10).unwrap();
+    for ts in timestamps() {
+        series.push_line(ts, [0; 4]).unwrap();
+    }
+
+    series.truncate_after(u64::MAX).unwrap();
+    assert_eq!(read_all(&mut series), timestamps().collect::<Vec<_>>());
+}
@@ -0,0 +1,186 @@
+use std::io::Write;
+use std::path::Path;
+
+use byteseries::{Decoder, VariableSeries};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, Timestamp};
+
+#[derive(Debug)]
+struct BytesDecoder;
+
+impl Decoder for BytesDecoder {
+    type Item = Vec<u8>;
+
+    fn decode_payload(&mut self, payload: &[u8]) -> Self::Item {
+        payload.to_vec()
+    }
+}
+
+/// Between 3 and 300 bytes long, every byte is the length
+fn payload(ts: Timestamp) -> Vec<u8> {
+    let len = 3 + (ts * 37 % 298) as usize;
+    vec![len as u8; len]
+}
+
+fn read_all(series: &mut VariableSeries) -> (Vec<Timestamp>, Vec<Vec<u8>>) {
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_all(.., &mut BytesDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    (timestamps, data)
+}
+
+fn expected(
+    timestamps: impl Iterator<Item = Timestamp>,
+) -> (Vec<Timestamp>, Vec<Vec<u8>>) {
+    timestamps.map(|ts| (ts, payload(ts))).unzip()
+}
+
+fn fill(test_path: &Path, timestamps: impl Iterator<Item = Timestamp>) {
+    let mut series = VariableSeries::open(test_path).unwrap();
+    for ts in timestamps {
+        series.push_line(ts, payload(ts)).unwrap();
+    }
+}
+
+#[test]
+fn reads_back_after_reopen() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("variable_reads_back_after_reopen");
+    fill(&test_path, 0..1000);
+
+    let mut series = VariableSeries::open(&test_path).unwrap();
+    assert_eq!(series.len(), 1000);
+    assert_eq!(series.range(), Some(0..=999));
+    assert_eq!(read_all(&mut series), expected(0..1000));
+
+    series.push_line(1000, []).unwrap();
+    let (_, data) = read_all(&mut series);
+    assert_eq!(data.last().unwrap(), &Vec::<u8>::new());
+}
+
+#[test]
+fn reads_ranges() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("variable_reads_ranges");
+    fill(&test_path, (0..1000).map(|i| i * 100));
+    let mut series = VariableSeries::open(&test_path).unwrap();
+
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_all(
+            25_000..=30_000,
+            &mut BytesDecoder,
+            &mut timestamps,
+            &mut data,
+        )
+        .unwrap();
+    assert_eq!((timestamps, data), expected((250..=300).map(|i| i * 100)));
+
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_first_n(5, 50_000.., &mut BytesDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    assert_eq!((timestamps, data), expected((500..505).map(|i| i * 100)));
+
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_last_n(5, ..50_000, &mut BytesDecoder, &mut timestamps, &mut data)
+        .unwrap();
+    assert_eq!((timestamps, data), expected((495..500).map(|i| i * 100)));
+}
+
+#[test]
+fn truncate_removes_payloads() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("variable_truncate_removes_payloads");
+    fill(&test_path, 0..100);
+
+    let mut series = VariableSeries::open(&test_path).unwrap();
+    series.truncate_after(49).unwrap();
+    series.push_line(200, payload(200)).unwrap();
+    drop(series);
+
+    let payloads_len: usize = (0..50).chain([200]).map(|ts| payload(ts).len()).sum();
+    let on_disk = std::fs::metadata(test_path.with_extension("byteseries_payloads"))
+        .unwrap()
+        .len();
+    assert_eq!(on_disk, payloads_len as u64);
+
+    let mut series = VariableSeries::open(&test_path).unwrap();
+    assert_eq!(read_all(&mut series), expected((0..50).chain([200])));
+}
+
+#[test]
+fn removes_payload_without_line() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("variable_removes_payload_without_line");
+    fill(&test_path, 0..100);
+    // crashed after writing the payload but before the line
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(test_path.with_extension("byteseries_payloads"))
+        .unwrap()
+        .write_all(&[42; 100])
+        .unwrap();
+
+    let mut series = VariableSeries::open(&test_path).unwrap();
+    series.push_line(100, payload(100)).unwrap();
+    assert_eq!(read_all(&mut series), expected(0..101));
+}
+
+#[test]
+fn removes_line_without_payload() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("variable_removes_line_without_payload");
+    fill(&test_path, 0..100);
+    // the last two payloads never made it to disk
+    let payloads_len: usize = (0..98).map(|ts| payload(ts).len()).sum();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(test_path.with_extension("byteseries_payloads"))
+        .unwrap()
+        .set_len(payloads_len as u64 + 1)
+        .unwrap();
+
+    let mut series = VariableSeries::open(&test_path).unwrap();
+    assert_eq!(read_all(&mut series), expected(0..98));
+}
+
+#[test]
+fn removes_first_line_at_zero_without_payload() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("variable_removes_first_line_at_zero");
+    fill(&test_path, 0..1);
+    // the payload of the only line, at timestamp zero, got torn
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(test_path.with_extension("byteseries_payloads"))
+        .unwrap()
+        .set_len(1)
+        .unwrap();
+
+    let mut series = VariableSeries::open(&test_path).unwrap();
+    assert!(series.is_empty());
+    series.push_line(0, payload(0)).unwrap();
+    assert_eq!(read_all(&mut series), expected(0..1));
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use byteseries::series::verify::Issue;
+use byteseries::{downsample, ByteSeries};
+use pretty_assertions::assert_eq;
+use temp_dir::TempDir;
+
+mod shared;
+use shared::{setup_tracing, FloatResampler, Timestamp};
+
+const LINE_SIZE: usize = 2 + 4;
+/// with this many lines a new meta section starts after every 66 lines
+const STEP: Timestamp = 1000;
+const CONFIG: downsample::Config = downsample::Config {
+    max_gap: None,
+    bucket_size: 10,
+};
+
+fn open(test_path: &Path, create_new: bool, with_cache: bool) -> ByteSeries {
+    let configs = if with_cache { vec![CONFIG] } else { Vec::new() };
+    ByteSeries::builder()
+        .payload_size(4)
+        .create_new(create_new)
+        .with_any_header()
+        .with_downsampled_cache(FloatResampler, configs)
+        .checksums(true)
+        .open(test_path)
+        .unwrap()
+        .0
+}
+
+fn push(series: &mut ByteSeries, lines: std::ops::Range<u64>) {
+    for i in lines {
+        series
+            .push_line(i * STEP, (i as f32).to_le_bytes())
+            .unwrap();
+    }
+}
+
+fn fill(test_path: &Path, lines: std::ops::Range<u64>) {
+    let mut series = open(test_path, true, true);
+    push(&mut series, lines);
+}
+
+fn data_start(file: &[u8]) -> usize {
+    let header_len = u16::from_le_bytes([file[0], file[1]]) as usize;
+    4 + header_len
+}
+
+/// Offsets of the meta sections in the data file
+fn meta_starts(test_path: &Path) -> Vec<u64> {
+    let file = std::fs::read(test_path.with_extension("byteseries")).unwrap();
+    let mut starts = Vec::new();
+    let mut lines = file[data_start(&file)..]
+        .chunks_exact(LINE_SIZE)
+        .enumerate();
+    while let Some((i, line)) = lines.next() {
+        if line[..2] == [255, 255] {
+            starts.push((i * LINE_SIZE) as u64);
+            lines.next();
+        }
+    }
+    starts
+}
+
+fn all_files(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| (path.clone(), std::fs::read(path).unwrap()))
+        .collect()
+}
+
+#[test]
+fn clean_series_is_ok() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("verify_clean_series_is_ok");
+    fill(&test_path, 0..1000);
+
+    let report = byteseries::verify(&test_path).unwrap();
+    assert!(report.is_ok(), "{report:#?}");
+    assert_eq!(report.caches.len(), 1);
+    let report = byteseries::verify(test_path.with_extension("byteseries")).unwrap();
+    assert!(report.is_ok(), "{report:#?}");
+}
+
+#[test]
+fn torn_tail() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("verify_torn_tail");
+    fill(&test_path, 0..1000);
+
+    let path = test_path.with_extension("byteseries");
+    let mut file = std::fs::read(&path).unwrap();
+    let data_len = (file.len() - data_start(&file)) as u64;
+    file.extend_from_slice(&[1, 2, 3]);
+    std::fs::write(&path, file).unwrap();
+
+    let before = all_files(test_dir.path());
+    let report = byteseries::verify(&test_path).unwrap();
+    assert_eq!(
+        report.issues,
+        vec![Issue::TornTail {
+            start: data_len,
+            len: 3
+        }]
+    );
+    assert_eq!(before, all_files(test_dir.path()));
+}
+
+#[test]
+fn missing_second_preamble() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("verify_missing_second_preamble");
+    fill(&test_path, 0..1000);
+    let meta_start = meta_starts(&test_path)[3];
+
+    let path = test_path.with_extension("byteseries");
+    let mut file = std::fs::read(&path).unwrap();
+    let second_line = data_start(&file) + meta_start as usize + LINE_SIZE;
+    file[second_line..second_line + 2].copy_from_slice(&[0, 0]);
+    std::fs::write(&path, file).unwrap();
+
+    let report = byteseries::verify(&test_path).unwrap();
+    assert!(
+        report
+            .issues
+            .contains(&Issue::MissingSecondPreamble { offset: meta_start }),
+        "{report:#?}"
+    );
+}
+
+#[test]
+fn index_mismatch() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("verify_index_mismatch");
+    fill(&test_path, 0..1000);
+    let meta_start = meta_starts(&test_path)[1];
+
+    let path = test_path.with_extension("byteseries_index");
+    let mut file = std::fs::read(&path).unwrap();
+    let entry = data_start(&file) + 16;
+    let timestamp = u64::from_le_bytes(file[entry..entry + 8].try_into().unwrap());
+    file[entry..entry + 8].copy_from_slice(&(timestamp + 5).to_le_bytes());
+    std::fs::write(&path, file).unwrap();
+
+    let report = byteseries::verify(&test_path).unwrap();
+    assert_eq!(
+        report.issues,
+        vec![Issue::IndexMismatch {
+            meta_start,
+            in_index: Some(timestamp + 5),
+            in_data: Some(timestamp),
+        }]
+    );
+}
+
+#[test]
+fn lagging_cache() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("verify_lagging_cache");
+    fill(&test_path, 0..1000);
+    let mut series = open(&test_path, false, false);
+    push(&mut series, 1000..1100);
+    drop(series);
+
+    let report = byteseries::verify(&test_path).unwrap();
+    assert!(report.issues.is_empty(), "{report:#?}");
+    let [cache] = report.caches.as_slice() else {
+        panic!("there should be one cache, report: {report:#?}");
+    };
+    assert!(
+        matches!(
+            cache.issues.as_slice(),
+            [Issue::CacheLags {
+                last_in_cache: Some(_),
+                newer_source_lines: 100..
+            }]
+        ),
+        "{report:#?}"
+    );
+
+    // opening with the cache brings it up to date
+    drop(open(&test_path, false, true));
+    assert!(byteseries::verify(&test_path).unwrap().is_ok());
+}
+
+#[test]
+fn checksum_mismatch() {
+    setup_tracing();
+
+    let test_dir = TempDir::new().unwrap();
+    let test_path = test_dir.child("verify_checksum_mismatch");
+    fill(&test_path, 0..1000);
+    let section_start = meta_starts(&test_path)[2];
+
+    let path = test_path.with_extension("byteseries");
+    let mut file = std::fs::read(&path).unwrap();
+    let payload = data_start(&file) + section_start as usize + 3 * LINE_SIZE + 2;
+    file[payload] ^= 0b1000;
+    std::fs::write(&path, file).unwrap();
+
+    let report = byteseries::verify(&test_path).unwrap();
+    assert_eq!(
+        report.issues,
+        vec![Issue::ChecksumMismatch { section_start }]
+    );
+}